@@ -0,0 +1,210 @@
+use crate::session;
+use rusqlite::{params_from_iter, types::Value, Connection};
+use serde::{Deserialize, Serialize};
+
+// Tables whose every INSERT/UPDATE/DELETE is captured in audit_log
+const AUDITED_TABLES: &[&str] = &[
+    "products",
+    "sales",
+    "users",
+    "shifts",
+    "expenses",
+    "customers",
+    "purchase_orders",
+];
+
+// Columns never copied into the audit trail
+const REDACTED_COLUMNS: &[&str] = &["password_hash"];
+
+const MAX_PAGE_SIZE: u32 = 500;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    #[serde(default)]
+    pub table_name: Option<String>,
+    #[serde(default)]
+    pub operation: Option<String>,
+    #[serde(default)]
+    pub record_id: Option<String>,
+    #[serde(default)]
+    pub user_id: Option<String>,
+    #[serde(default)]
+    pub from: Option<String>,
+    #[serde(default)]
+    pub to: Option<String>,
+    #[serde(default)]
+    pub page: Option<u32>,
+    #[serde(default)]
+    pub page_size: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditEntry {
+    pub id: String,
+    pub table_name: String,
+    pub operation: String,
+    pub record_id: String,
+    pub user_id: Option<String>,
+    pub old_data: Option<serde_json::Value>,
+    pub new_data: Option<serde_json::Value>,
+    pub timestamp: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub total: i64,
+    pub page: u32,
+    pub page_size: u32,
+}
+
+pub fn table_columns(conn: &Connection, table: &str) -> Result<Vec<String>, String> {
+    let mut stmt = conn
+        .prepare(&format!("PRAGMA table_info({})", table))
+        .map_err(|e| e.to_string())?;
+    let columns = stmt
+        .query_map([], |row| row.get::<_, String>(1))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(columns)
+}
+
+fn json_image(columns: &[String], row: &str) -> String {
+    let pairs: Vec<String> = columns
+        .iter()
+        .map(|c| format!("'{}', {}.{}", c, row, c))
+        .collect();
+    format!("json_object({})", pairs.join(", "))
+}
+
+// (Re)create the audit triggers. Runs after every schema migration so the
+// JSON images always cover the current column set.
+pub fn install_triggers(conn: &Connection) -> Result<(), String> {
+    for table in AUDITED_TABLES {
+        let columns: Vec<String> = table_columns(conn, table)?
+            .into_iter()
+            .filter(|c| !REDACTED_COLUMNS.contains(&c.as_str()))
+            .collect();
+
+        let new_image = json_image(&columns, "NEW");
+        let old_image = json_image(&columns, "OLD");
+
+        // Sealing a row into the hash chain is bookkeeping, not an edit
+        let update_condition = if columns.iter().any(|c| c == "hash") {
+            "WHEN NOT (OLD.hash IS NULL AND NEW.hash IS NOT NULL)"
+        } else {
+            ""
+        };
+
+        let triggers = [
+            ("insert", "INSERT", "", "NEW.id", "NULL".to_string(), new_image.clone()),
+            ("update", "UPDATE", update_condition, "NEW.id", old_image.clone(), new_image),
+            ("delete", "DELETE", "", "OLD.id", old_image, "NULL".to_string()),
+        ];
+
+        for (suffix, operation, condition, record_id, old_data, new_data) in triggers {
+            let sql = format!(
+                r#"
+                DROP TRIGGER IF EXISTS audit_{table}_{suffix};
+                CREATE TRIGGER audit_{table}_{suffix}
+                AFTER {operation} ON {table}
+                {condition}
+                BEGIN
+                    INSERT INTO audit_log (id, table_name, operation, record_id, user_id, old_data, new_data, timestamp)
+                    VALUES (
+                        lower(hex(randomblob(16))),
+                        '{table}',
+                        '{operation}',
+                        {record_id},
+                        current_user_id(),
+                        {old_data},
+                        {new_data},
+                        strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+                    );
+                END;
+                "#,
+            );
+            conn.execute_batch(&sql)
+                .map_err(|e| format!("Failed to install audit trigger on {}: {}", table, e))?;
+        }
+    }
+
+    Ok(())
+}
+
+fn parse_json(value: Option<String>) -> Option<serde_json::Value> {
+    value.and_then(|v| serde_json::from_str(&v).ok())
+}
+
+pub fn get_audit_log(conn: &Connection, query: &AuditQuery) -> Result<AuditPage, String> {
+    session::require_role(&["admin", "manager"])?;
+    let mut conditions: Vec<&str> = Vec::new();
+    let mut values: Vec<Value> = Vec::new();
+
+    let filters = [
+        ("table_name = ?", &query.table_name),
+        ("operation = ?", &query.operation),
+        ("record_id = ?", &query.record_id),
+        ("user_id = ?", &query.user_id),
+        ("timestamp >= ?", &query.from),
+        ("timestamp <= ?", &query.to),
+    ];
+    for (condition, value) in filters {
+        if let Some(value) = value {
+            conditions.push(condition);
+            values.push(Value::Text(value.clone()));
+        }
+    }
+
+    let where_clause = if conditions.is_empty() {
+        String::new()
+    } else {
+        format!("WHERE {}", conditions.join(" AND "))
+    };
+
+    let total: i64 = conn
+        .query_row(
+            &format!("SELECT COUNT(*) FROM audit_log {}", where_clause),
+            params_from_iter(values.iter()),
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    let page = query.page.unwrap_or(1).max(1);
+    let page_size = query.page_size.unwrap_or(50).clamp(1, MAX_PAGE_SIZE);
+    values.push(Value::Integer(page_size as i64));
+    values.push(Value::Integer((page as i64 - 1) * page_size as i64));
+
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT id, table_name, operation, record_id, user_id, old_data, new_data, timestamp
+             FROM audit_log {} ORDER BY chain_seq DESC LIMIT ? OFFSET ?",
+            where_clause
+        ))
+        .map_err(|e| e.to_string())?;
+
+    let entries = stmt
+        .query_map(params_from_iter(values.iter()), |row| {
+            Ok(AuditEntry {
+                id: row.get(0)?,
+                table_name: row.get(1)?,
+                operation: row.get(2)?,
+                record_id: row.get(3)?,
+                user_id: row.get(4)?,
+                old_data: parse_json(row.get(5)?),
+                new_data: parse_json(row.get(6)?),
+                timestamp: row.get(7)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(AuditPage {
+        entries,
+        total,
+        page,
+        page_size,
+    })
+}
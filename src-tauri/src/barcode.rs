@@ -0,0 +1,543 @@
+use crate::database;
+use crate::units;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+// ASCII group separator, sent by scanners in place of FNC1
+const GS: char = '\u{1d}';
+
+// In-store EAN-13 codes (prefix 20–29) carry a 5-digit item code and a
+// 5-digit value: PP IIIII VVVVV C. Prefixes 20–24 embed the weight in grams,
+// 25–29 the price in minor units, which is how our label scales are set up.
+const EMBEDDED_WEIGHT_PREFIXES: std::ops::RangeInclusive<u32> = 20..=24;
+const EMBEDDED_PRICE_PREFIXES: std::ops::RangeInclusive<u32> = 25..=29;
+
+#[derive(Debug, Serialize)]
+pub struct ScannedProduct {
+    pub id: String,
+    pub name: String,
+    pub sku: String,
+    // Of the scanned unit: the pack when a pack barcode was scanned
+    pub price_minor: i64,
+    pub stock: i64,
+    pub unit_id: Option<String>,
+    pub unit: String,
+    pub quantity_decimals: i64,
+    // Base steps one scanned item stands for: the pack quantity, or one whole unit
+    pub base_quantity: i64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BarcodeLookup {
+    pub code: String,
+    pub symbology: String,
+    pub product: Option<ScannedProduct>,
+    pub quantity: f64,
+    pub weight_grams: Option<i64>,
+    pub embedded_price_minor: Option<i64>,
+    pub gtin: Option<String>,
+    pub batch: Option<String>,
+    pub serial: Option<String>,
+    pub expiry_date: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProductBarcode {
+    pub id: String,
+    pub product_id: String,
+    pub barcode: String,
+    pub created_at: String,
+}
+
+fn digits(code: &str) -> Option<Vec<u32>> {
+    code.chars().map(|c| c.to_digit(10)).collect()
+}
+
+// GS1 mod-10 check digit, valid for EAN-8, UPC-A, EAN-13 and GTIN-14
+pub fn has_valid_check_digit(code: &str) -> bool {
+    let digits = match digits(code) {
+        Some(d) if matches!(d.len(), 8 | 12 | 13 | 14) => d,
+        _ => return false,
+    };
+
+    let (body, check) = digits.split_at(digits.len() - 1);
+    let sum: u32 = body
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d * 3 } else { *d })
+        .sum();
+
+    (10 - sum % 10) % 10 == check[0]
+}
+
+fn symbology(code: &str) -> &'static str {
+    match code.len() {
+        8 => "ean8",
+        12 => "upca",
+        13 => "ean13",
+        14 => "gtin14",
+        _ => "unknown",
+    }
+}
+
+// GS1 expiry dates are YYMMDD; day 00 means the last day of the month
+fn gs1_date(value: &str) -> Option<String> {
+    if value.len() != 6 {
+        return None;
+    }
+    let year = 2000 + value.get(0..2)?.parse::<i32>().ok()?;
+    let month = value.get(2..4)?.parse::<u32>().ok()?;
+    let day = value.get(4..6)?.parse::<u32>().ok()?;
+
+    let date = if day == 0 {
+        let first_of_next = if month == 12 {
+            chrono::NaiveDate::from_ymd_opt(year + 1, 1, 1)?
+        } else {
+            chrono::NaiveDate::from_ymd_opt(year, month + 1, 1)?
+        };
+        first_of_next.pred_opt()?
+    } else {
+        chrono::NaiveDate::from_ymd_opt(year, month, day)?
+    };
+
+    Some(date.format("%Y-%m-%d").to_string())
+}
+
+// Length of the data following a fixed-length AI, None for variable-length
+fn fixed_ai_length(ai: &str) -> Option<usize> {
+    match ai {
+        "00" => Some(18),
+        "01" | "02" => Some(14),
+        "11" | "12" | "13" | "15" | "16" | "17" => Some(6),
+        "20" => Some(2),
+        // 31nn–36nn trade measures
+        _ if ai.len() == 4 && matches!(&ai[..2], "31" | "32" | "33" | "34" | "35" | "36") => Some(6),
+        _ => None,
+    }
+}
+
+// Number of digits in the AI at the start of `data`, from its first two
+fn ai_length(data: &str) -> usize {
+    let length = match &data[..2.min(data.len())] {
+        "23" | "24" | "25" | "40" | "41" | "42" | "71" => 3,
+        "31" | "32" | "33" | "34" | "35" | "36" | "39" | "43" | "70" | "72" | "80" | "81" | "82" => 4,
+        _ => 2,
+    };
+    length.min(data.len())
+}
+
+// Split a GS1-128 payload into (AI, value) pairs. Accepts both the raw form
+// with group separators and the human-readable "(01)...(17)..." form.
+// GS1 data is ASCII, so anything else is malformed rather than sliced.
+fn parse_gs1_elements(input: &str) -> Option<Vec<(String, String)>> {
+    if !input.is_ascii() {
+        return None;
+    }
+    let mut elements = Vec::new();
+
+    if input.starts_with('(') {
+        let mut rest = input;
+        while let Some(stripped) = rest.strip_prefix('(') {
+            let close = stripped.find(')')?;
+            let ai = stripped[..close].to_string();
+            let after = &stripped[close + 1..];
+            let end = after.find('(').unwrap_or(after.len());
+            elements.push((ai, after[..end].to_string()));
+            rest = &after[end..];
+        }
+        return if rest.is_empty() { Some(elements) } else { None };
+    }
+
+    let mut rest = input.trim_start_matches(GS);
+    while !rest.is_empty() {
+        let ai_len = ai_length(rest);
+        if rest.len() < ai_len {
+            return None;
+        }
+        let ai = rest[..ai_len].to_string();
+        let data = &rest[ai_len..];
+
+        let value_len = match fixed_ai_length(&ai) {
+            Some(len) if data.len() >= len => len,
+            Some(_) => return None,
+            None => data.find(GS).unwrap_or(data.len()),
+        };
+
+        elements.push((ai, data[..value_len].to_string()));
+        rest = data[value_len..].trim_start_matches(GS);
+    }
+
+    Some(elements)
+}
+
+fn find_product(conn: &Connection, code: &str) -> Result<Option<ScannedProduct>, String> {
+    let product = conn
+        .query_row(
+            "SELECT p.id, p.name, p.sku, p.price_minor, p.stock, p.unit, p.quantity_decimals
+             FROM products p
+             WHERE p.is_active = 1
+               AND (p.id IN (SELECT product_id FROM product_barcodes WHERE barcode = ?1)
+                    OR p.barcode = ?1
+                    OR p.sku = ?1)
+             LIMIT 1",
+            params![code],
+            |row| {
+                let quantity_decimals: i64 = row.get(6)?;
+                Ok(ScannedProduct {
+                    id: row.get(0)?,
+                    name: row.get(1)?,
+                    sku: row.get(2)?,
+                    price_minor: row.get(3)?,
+                    stock: row.get(4)?,
+                    unit_id: None,
+                    unit: row.get(5)?,
+                    quantity_decimals,
+                    base_quantity: units::scale(quantity_decimals),
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if product.is_some() {
+        return Ok(product);
+    }
+
+    let pack = match units::find_pack_by_barcode(conn, code)? {
+        Some(pack) => pack,
+        None => return Ok(None),
+    };
+    conn.query_row(
+        "SELECT id, name, sku, stock FROM products WHERE id = ?",
+        params![pack.product_id],
+        |row| {
+            Ok(ScannedProduct {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                sku: row.get(2)?,
+                price_minor: pack.price_minor,
+                stock: row.get(3)?,
+                unit_id: Some(pack.id.clone()),
+                unit: pack.name.clone(),
+                quantity_decimals: 0,
+                base_quantity: pack.quantity,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+// A GTIN-14 with leading zeros is the same item as its EAN-13/UPC-A form
+fn find_product_by_gtin(conn: &Connection, gtin: &str) -> Result<Option<ScannedProduct>, String> {
+    let mut candidate = gtin;
+    loop {
+        if let Some(product) = find_product(conn, candidate)? {
+            return Ok(Some(product));
+        }
+        match candidate.strip_prefix('0') {
+            Some(shorter) if shorter.len() >= 12 => candidate = shorter,
+            _ => return Ok(None),
+        }
+    }
+}
+
+fn lookup_embedded(conn: &Connection, code: &str, prefix: u32) -> Result<BarcodeLookup, String> {
+    let item_code = &code[..7];
+    let value: i64 = code[7..12].parse().map_err(|_| "Invalid embedded value".to_string())?;
+
+    // Items are registered either by their 7-digit prefix + item code or by
+    // the full label code with a zero value
+    let product = match find_product(conn, item_code)? {
+        Some(product) => Some(product),
+        None => conn
+            .query_row(
+                "SELECT barcode FROM product_barcodes WHERE barcode LIKE ?1 || '00000_' LIMIT 1",
+                params![item_code],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .map(|registered| find_product(conn, &registered))
+            .transpose()?
+            .flatten(),
+    };
+
+    let mut lookup = BarcodeLookup {
+        code: code.to_string(),
+        ..Default::default()
+    };
+
+    if EMBEDDED_WEIGHT_PREFIXES.contains(&prefix) {
+        lookup.symbology = "embedded-weight".to_string();
+        lookup.weight_grams = Some(value);
+        lookup.quantity = value as f64 / 1000.0;
+    } else {
+        lookup.symbology = "embedded-price".to_string();
+        lookup.embedded_price_minor = Some(value);
+        lookup.quantity = match &product {
+            Some(p) if p.price_minor > 0 => value as f64 / p.price_minor as f64,
+            _ => 1.0,
+        };
+    }
+
+    lookup.product = product;
+    Ok(lookup)
+}
+
+fn lookup_gs1(conn: &Connection, code: &str, payload: &str) -> Result<BarcodeLookup, String> {
+    let elements = parse_gs1_elements(payload)
+        .ok_or_else(|| format!("Malformed GS1-128 barcode: {}", code))?;
+
+    let mut lookup = BarcodeLookup {
+        code: code.to_string(),
+        symbology: "gs1-128".to_string(),
+        quantity: 1.0,
+        ..Default::default()
+    };
+
+    for (ai, value) in elements {
+        match ai.as_str() {
+            "01" | "02" => {
+                if !has_valid_check_digit(&value) {
+                    return Err(format!("Invalid GTIN check digit: {}", value));
+                }
+                lookup.gtin = Some(value);
+            }
+            "10" => lookup.batch = Some(value),
+            "21" => lookup.serial = Some(value),
+            "17" => lookup.expiry_date = gs1_date(&value),
+            "30" | "37" => {
+                if let Ok(count) = value.parse::<f64>() {
+                    lookup.quantity = count;
+                }
+            }
+            _ if ai.len() == 4 && ai.starts_with("310") => {
+                // Net weight in kg, last AI digit is the number of decimals
+                let decimals = ai[3..].parse::<i32>().unwrap_or(0);
+                let raw = value.parse::<f64>().unwrap_or(0.0);
+                let kilograms = raw / 10f64.powi(decimals);
+                lookup.weight_grams = Some((kilograms * 1000.0).round() as i64);
+                lookup.quantity = kilograms;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(gtin) = &lookup.gtin {
+        lookup.product = find_product_by_gtin(conn, gtin)?;
+    }
+
+    Ok(lookup)
+}
+
+pub fn lookup_barcode(conn: &Connection, code: &str) -> Result<BarcodeLookup, String> {
+    let code = code.trim();
+    if code.is_empty() {
+        return Err("Empty barcode".to_string());
+    }
+
+    // "]C1" is the AIM symbology identifier scanners prepend to GS1-128
+    if let Some(payload) = code.strip_prefix("]C1") {
+        return lookup_gs1(conn, code, payload);
+    }
+    if code.starts_with('(') || code.contains(GS) {
+        return lookup_gs1(conn, code, code);
+    }
+
+    if code.chars().all(|c| c.is_ascii_digit()) && matches!(code.len(), 8 | 12 | 13 | 14) {
+        if !has_valid_check_digit(code) {
+            return Err(format!("Invalid check digit: {}", code));
+        }
+
+        if code.len() == 13 {
+            let prefix: u32 = code[..2].parse().unwrap_or(0);
+            if EMBEDDED_WEIGHT_PREFIXES.contains(&prefix) || EMBEDDED_PRICE_PREFIXES.contains(&prefix) {
+                return lookup_embedded(conn, code, prefix);
+            }
+        }
+
+        return Ok(BarcodeLookup {
+            code: code.to_string(),
+            symbology: symbology(code).to_string(),
+            product: find_product_by_gtin(conn, code)?,
+            quantity: 1.0,
+            gtin: Some(format!("{:0>14}", code)),
+            ..Default::default()
+        });
+    }
+
+    // Anything else is an internal code (SKU or custom label)
+    Ok(BarcodeLookup {
+        code: code.to_string(),
+        symbology: "internal".to_string(),
+        product: find_product(conn, code)?,
+        quantity: 1.0,
+        ..Default::default()
+    })
+}
+
+pub fn add_product_barcode(conn: &Connection, product_id: &str, barcode: &str) -> Result<ProductBarcode, String> {
+    let barcode = barcode.trim();
+    if barcode.is_empty() {
+        return Err("Barcode is required".to_string());
+    }
+
+    let numeric = barcode.chars().all(|c| c.is_ascii_digit());
+    if numeric && matches!(barcode.len(), 8 | 12 | 13 | 14) && !has_valid_check_digit(barcode) {
+        return Err(format!("Invalid check digit: {}", barcode));
+    }
+
+    conn.query_row("SELECT id FROM products WHERE id = ?", params![product_id], |row| row.get::<_, String>(0))
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Product {} not found", product_id))?;
+
+    let taken_by: Option<String> = conn
+        .query_row(
+            "SELECT id FROM products WHERE barcode = ?1 AND id <> ?2
+             UNION ALL SELECT product_id FROM product_units WHERE barcode = ?1",
+            params![barcode, product_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(other) = taken_by {
+        return Err(format!("Barcode {} already belongs to product {}", barcode, other));
+    }
+
+    let entry = ProductBarcode {
+        id: database::generate_id("bc"),
+        product_id: product_id.to_string(),
+        barcode: barcode.to_string(),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+
+    conn.execute(
+        "INSERT INTO product_barcodes (id, product_id, barcode, created_at) VALUES (?, ?, ?, ?)",
+        params![entry.id, entry.product_id, entry.barcode, entry.created_at],
+    )
+    .map_err(|e| format!("Failed to add barcode: {}", e))?;
+
+    Ok(entry)
+}
+
+pub fn remove_product_barcode(conn: &Connection, barcode: &str) -> Result<(), String> {
+    let removed = conn
+        .execute("DELETE FROM product_barcodes WHERE barcode = ?", params![barcode])
+        .map_err(|e| format!("Failed to remove barcode: {}", e))?;
+    if removed == 0 {
+        return Err(format!("Barcode {} not found", barcode));
+    }
+    Ok(())
+}
+
+pub fn list_product_barcodes(conn: &Connection, product_id: &str) -> Result<Vec<ProductBarcode>, String> {
+    let mut stmt = conn
+        .prepare("SELECT id, product_id, barcode, created_at FROM product_barcodes WHERE product_id = ? ORDER BY created_at")
+        .map_err(|e| e.to_string())?;
+    let barcodes = stmt
+        .query_map(params![product_id], |row| {
+            Ok(ProductBarcode {
+                id: row.get(0)?,
+                product_id: row.get(1)?,
+                barcode: row.get(2)?,
+                created_at: row.get(3)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(barcodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pairs(elements: &[(&str, &str)]) -> Vec<(String, String)> {
+        elements.iter().map(|(ai, value)| (ai.to_string(), value.to_string())).collect()
+    }
+
+    fn store() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        database::register_functions(&conn).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE products (id TEXT PRIMARY KEY, name TEXT, sku TEXT, barcode TEXT, price_minor INTEGER,
+                                    stock INTEGER, unit TEXT, quantity_decimals INTEGER, is_active INTEGER);
+             CREATE TABLE product_barcodes (id TEXT PRIMARY KEY, product_id TEXT, barcode TEXT, created_at TEXT);
+             CREATE TABLE product_units (id TEXT PRIMARY KEY, product_id TEXT, name TEXT, quantity INTEGER,
+                                         barcode TEXT, price_minor INTEGER, created_at TEXT);
+             INSERT INTO products VALUES ('cheese', 'Cheese', 'CHEESE', '2212345', 2000, 0, 'kg', 3, 1);
+             INSERT INTO products VALUES ('ham', 'Ham', 'HAM', '2612345', 250, 0, 'each', 0, 1);",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn check_digits() {
+        assert!(has_valid_check_digit("4006381333931"));
+        assert!(!has_valid_check_digit("4006381333932"));
+        assert!(has_valid_check_digit("96385074"));
+        assert!(has_valid_check_digit("036000291452"));
+        assert!(has_valid_check_digit("04006381333931"));
+        assert!(!has_valid_check_digit("400638133393"));
+        assert!(!has_valid_check_digit("40063813339x1"));
+    }
+
+    #[test]
+    fn gs1_dates_with_day_zero_end_the_month() {
+        assert_eq!(gs1_date("260315").as_deref(), Some("2026-03-15"));
+        assert_eq!(gs1_date("240200").as_deref(), Some("2024-02-29"));
+        assert_eq!(gs1_date("261200").as_deref(), Some("2026-12-31"));
+        assert_eq!(gs1_date("261301"), None);
+        assert_eq!(gs1_date("2603"), None);
+        assert_eq!(gs1_date("1é031"), None);
+    }
+
+    #[test]
+    fn parses_both_gs1_forms() {
+        let expected = pairs(&[("01", "04006381333931"), ("17", "261231"), ("10", "LOT7"), ("3103", "001250")]);
+        assert_eq!(parse_gs1_elements("(01)04006381333931(17)261231(10)LOT7(3103)001250"), Some(expected.clone()));
+        assert_eq!(parse_gs1_elements("01040063813339311726123110LOT7\u{1d}3103001250"), Some(expected));
+    }
+
+    #[test]
+    fn rejects_malformed_gs1() {
+        assert_eq!(parse_gs1_elements("(01)0400638133393(17"), None);
+        assert_eq!(parse_gs1_elements("0104006381"), None);
+        assert_eq!(parse_gs1_elements("1é0"), None);
+        assert_eq!(parse_gs1_elements("(1é)0"), None);
+        assert!(lookup_barcode(&store(), "(01)04006381333932").is_err());
+        assert!(lookup_barcode(&store(), "]C11é0").is_err());
+    }
+
+    #[test]
+    fn gs1_weight_and_expiry() {
+        let lookup = lookup_barcode(&store(), "]C1010400638133393117260200\u{1d}3103001250").unwrap();
+        assert_eq!(lookup.gtin.as_deref(), Some("04006381333931"));
+        assert_eq!(lookup.expiry_date.as_deref(), Some("2026-02-28"));
+        assert_eq!(lookup.weight_grams, Some(1250));
+        assert_eq!(lookup.quantity, 1.25);
+    }
+
+    #[test]
+    fn embedded_weight_and_price() {
+        let conn = store();
+
+        let weighed = lookup_barcode(&conn, "2212345007509").unwrap();
+        assert_eq!(weighed.symbology, "embedded-weight");
+        assert_eq!(weighed.product.map(|p| p.id).as_deref(), Some("cheese"));
+        assert_eq!(weighed.weight_grams, Some(750));
+        assert_eq!(weighed.quantity, 0.75);
+
+        let priced = lookup_barcode(&conn, "2612345007507").unwrap();
+        assert_eq!(priced.symbology, "embedded-price");
+        assert_eq!(priced.product.map(|p| p.id).as_deref(), Some("ham"));
+        assert_eq!(priced.embedded_price_minor, Some(750));
+        assert_eq!(priced.quantity, 3.0);
+
+        assert!(lookup_barcode(&conn, "2212345007503").is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use crate::database;
+use crate::kits;
+use crate::session;
+use crate::units;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct ParkRequest {
+    #[serde(default)]
+    pub customer_id: Option<String>,
+    pub items: Vec<ParkedLine>,
+    #[serde(default)]
+    pub notes: Option<String>,
+    // Hold the units so they cannot be sold to someone else meanwhile
+    #[serde(default)]
+    pub reserve_stock: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ParkedLine {
+    pub product_id: String,
+    // In the selling unit, as on a sale line
+    pub quantity: i64,
+    #[serde(default)]
+    pub unit_id: Option<String>,
+    // Defaults to the current price of the selling unit
+    #[serde(default)]
+    pub price_minor: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParkedItem {
+    pub product_id: String,
+    pub product_name: String,
+    pub quantity: i64,
+    pub unit_id: Option<String>,
+    pub unit_name: String,
+    pub quantity_decimals: i64,
+    pub price_minor: i64,
+    pub total_minor: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ParkedCart {
+    pub id: String,
+    pub cart_number: String,
+    pub customer_id: Option<String>,
+    pub customer_name: Option<String>,
+    pub user_id: String,
+    pub username: String,
+    pub notes: Option<String>,
+    pub reserve_stock: bool,
+    pub status: String,
+    pub total_minor: i64,
+    pub items: Vec<ParkedItem>,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
+// Units held by parked carts that are still waiting to be recalled
+pub fn reserved_quantity(conn: &Connection, product_id: &str) -> Result<i64, String> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(i.quantity * COALESCE((SELECT quantity FROM product_units WHERE id = i.unit_id), 1)), 0)
+         FROM parked_cart_items i JOIN parked_carts c ON c.id = i.cart_id
+         WHERE i.product_id = ? AND c.status = 'parked' AND c.reserve_stock = 1 AND c.expires_at > ?",
+        params![product_id, chrono::Utc::now().to_rfc3339()],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+// Mark carts past their expiry so their reservations are released
+pub fn expire_carts(conn: &Connection) -> Result<usize, String> {
+    let expired = conn
+        .execute(
+            "UPDATE parked_carts SET status = 'expired' WHERE status = 'parked' AND expires_at <= ?",
+            params![chrono::Utc::now().to_rfc3339()],
+        )
+        .map_err(|e| format!("Failed to expire parked carts: {}", e))?;
+    if expired > 0 {
+        println!("⏰ {} parked cart(s) expired", expired);
+    }
+    Ok(expired)
+}
+
+pub fn park_cart(conn: &Connection, request: &ParkRequest, expiry: chrono::Duration) -> Result<ParkedCart, String> {
+    let user = session::require_user()?;
+
+    if request.items.is_empty() {
+        return Err("Cannot park an empty cart".to_string());
+    }
+
+    expire_carts(conn)?;
+
+    let now = chrono::Utc::now();
+    let created_at = now.to_rfc3339();
+    let expires_at = (now + expiry).to_rfc3339();
+    let cart_id = database::generate_id("cart");
+
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM parked_carts", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let cart_number = format!("P-{:04}", count + 1);
+
+    conn.execute(
+        "INSERT INTO parked_carts (id, cart_number, customer_id, user_id, notes, reserve_stock, status, expires_at, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, 'parked', ?, ?)",
+        params![cart_id, cart_number, request.customer_id, user.id, request.notes, request.reserve_stock, expires_at, created_at],
+    )
+    .map_err(|e| format!("Failed to park cart: {}", e))?;
+
+    for line in &request.items {
+        if line.quantity <= 0 {
+            return Err("Cart quantities must be positive".to_string());
+        }
+
+        let (name, stock, decimals): (String, i64, i64) = conn
+            .query_row(
+                "SELECT name, stock, quantity_decimals FROM products WHERE id = ?",
+                params![line.product_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Product {} not found", line.product_id))?;
+        let unit = units::selling_unit(conn, &line.product_id, line.unit_id.as_deref())?;
+
+        if request.reserve_stock {
+            // Lines already inserted for this cart count as reserved too
+            let available = kits::available_stock(conn, &line.product_id, stock)? - reserved_quantity(conn, &line.product_id)?;
+            if line.quantity * unit.base_quantity > available {
+                return Err(format!(
+                    "Only {} of {} available to reserve",
+                    units::format_quantity(available.max(0), decimals),
+                    name
+                ));
+            }
+        }
+
+        conn.execute(
+            "INSERT INTO parked_cart_items (id, cart_id, product_id, quantity, price_minor, unit_id) VALUES (?, ?, ?, ?, ?, ?)",
+            params![
+                database::generate_id("cartitem"),
+                cart_id,
+                line.product_id,
+                line.quantity,
+                line.price_minor.unwrap_or(unit.price_minor),
+                unit.unit_id
+            ],
+        )
+        .map_err(|e| format!("Failed to park {}: {}", name, e))?;
+    }
+
+    println!("🅿️ Cart {} parked by {}", cart_number, user.username);
+    load_cart(conn, &cart_id)
+}
+
+fn load_items(conn: &Connection, cart_id: &str) -> Result<Vec<ParkedItem>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT i.product_id, p.name, i.quantity, i.price_minor, i.unit_id, COALESCE(u.name, p.unit),
+                    CASE WHEN u.id IS NULL THEN p.quantity_decimals ELSE 0 END
+             FROM parked_cart_items i JOIN products p ON p.id = i.product_id
+             LEFT JOIN product_units u ON u.id = i.unit_id
+             WHERE i.cart_id = ? ORDER BY i.rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let items = stmt
+        .query_map(params![cart_id], |row| {
+            let quantity: i64 = row.get(2)?;
+            let price_minor: i64 = row.get(3)?;
+            let quantity_decimals: i64 = row.get(6)?;
+            Ok(ParkedItem {
+                product_id: row.get(0)?,
+                product_name: row.get(1)?,
+                quantity,
+                unit_id: row.get(4)?,
+                unit_name: row.get(5)?,
+                quantity_decimals,
+                price_minor,
+                total_minor: units::line_total(price_minor, quantity, quantity_decimals),
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(items)
+}
+
+fn load_cart(conn: &Connection, cart_id: &str) -> Result<ParkedCart, String> {
+    let mut cart = conn
+        .query_row(
+            "SELECT c.id, c.cart_number, c.customer_id, cu.name, c.user_id, u.username, c.notes,
+                    c.reserve_stock, c.status, c.expires_at, c.created_at
+             FROM parked_carts c
+             JOIN users u ON u.id = c.user_id
+             LEFT JOIN customers cu ON cu.id = c.customer_id
+             WHERE c.id = ?",
+            params![cart_id],
+            |row| {
+                Ok(ParkedCart {
+                    id: row.get(0)?,
+                    cart_number: row.get(1)?,
+                    customer_id: row.get(2)?,
+                    customer_name: row.get(3)?,
+                    user_id: row.get(4)?,
+                    username: row.get(5)?,
+                    notes: row.get(6)?,
+                    reserve_stock: row.get(7)?,
+                    status: row.get(8)?,
+                    total_minor: 0,
+                    items: Vec::new(),
+                    expires_at: row.get(9)?,
+                    created_at: row.get(10)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Parked cart {} not found", cart_id))?;
+
+    cart.items = load_items(conn, cart_id)?;
+    cart.total_minor = cart.items.iter().map(|i| i.total_minor).sum();
+    Ok(cart)
+}
+
+pub fn list_parked_carts(conn: &Connection) -> Result<Vec<ParkedCart>, String> {
+    expire_carts(conn)?;
+
+    let ids: Vec<String> = {
+        let mut stmt = conn
+            .prepare("SELECT id FROM parked_carts WHERE status = 'parked' ORDER BY created_at")
+            .map_err(|e| e.to_string())?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        ids
+    };
+
+    ids.iter().map(|id| load_cart(conn, id)).collect()
+}
+
+// Hand a parked cart back to the till. Its reservation ends here; the
+// cashier completes it through create_sale.
+pub fn recall_cart(conn: &Connection, cart_id: &str) -> Result<ParkedCart, String> {
+    let user = session::require_user()?;
+    expire_carts(conn)?;
+
+    let mut cart = load_cart(conn, cart_id)?;
+    if cart.status != "parked" {
+        return Err(format!("Cart {} is {} and cannot be recalled", cart.cart_number, cart.status));
+    }
+
+    let recalled_at = chrono::Utc::now().to_rfc3339();
+    conn.execute(
+        "UPDATE parked_carts SET status = 'recalled', recalled_by = ?, recalled_at = ? WHERE id = ?",
+        params![user.id, recalled_at, cart_id],
+    )
+    .map_err(|e| format!("Failed to recall cart: {}", e))?;
+
+    println!("🛒 Cart {} recalled by {}", cart.cart_number, user.username);
+    cart.status = "recalled".to_string();
+    Ok(cart)
+}
@@ -0,0 +1,417 @@
+use crate::session;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+// A product's low-stock threshold: its own once it has been set, otherwise
+// its category's default, otherwise the table default. Needs
+// `LEFT JOIN category_defaults d ON d.category_id = p.category_id`.
+pub const EFFECTIVE_LOW_STOCK_THRESHOLD: &str =
+    "CASE WHEN p.own_low_stock_threshold = 0 AND d.low_stock_threshold IS NOT NULL
+          THEN d.low_stock_threshold ELSE p.low_stock_threshold END";
+
+#[derive(Debug, Deserialize)]
+pub struct CategoryMove {
+    pub category_id: String,
+    // None makes it a top-level category
+    #[serde(default)]
+    pub parent_id: Option<String>,
+    #[serde(default)]
+    pub sort_order: Option<i64>,
+}
+
+// Defaults products inherit from the nearest category that sets them; None
+// inherits from the parent category
+#[derive(Debug, Deserialize)]
+pub struct CategoryDefaults {
+    pub category_id: String,
+    #[serde(default)]
+    pub tax_class_id: Option<String>,
+    #[serde(default)]
+    pub low_stock_threshold: Option<i64>,
+    #[serde(default)]
+    pub promotions_allowed: Option<bool>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategoryNode {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub parent_id: Option<String>,
+    pub sort_order: i64,
+    pub is_active: bool,
+    pub depth: i64,
+    // "Drinks / Soft drinks / Cola"
+    pub path: String,
+    pub product_count: i64,
+    pub tax_class_id: Option<String>,
+    pub low_stock_threshold: Option<i64>,
+    pub promotions_allowed: Option<bool>,
+    // After inheritance from ancestors
+    pub effective_tax_class_id: Option<String>,
+    pub effective_low_stock_threshold: Option<i64>,
+    pub effective_promotions_allowed: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MergedCategory {
+    pub source_id: String,
+    pub target_id: String,
+    pub products_moved: usize,
+    pub subcategories_moved: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CategorySales {
+    pub category_id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    pub depth: i64,
+    // Products filed directly under the category
+    pub own_net_minor: i64,
+    // The category and everything below it
+    pub net_minor: i64,
+    pub cost_minor: i64,
+    pub gross_profit_minor: i64,
+    pub lines_sold: i64,
+}
+
+// category_tree pairs every category with itself and each of its ancestors;
+// category_defaults resolves inherited defaults through it. A category can
+// never become its own ancestor, however parent_id is written.
+pub fn install(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        r#"
+        DROP VIEW IF EXISTS category_tree;
+        CREATE VIEW category_tree AS
+        WITH RECURSIVE tree (category_id, ancestor_id, depth) AS (
+            SELECT id, id, 0 FROM categories
+            UNION ALL
+            SELECT tree.category_id, c.parent_id, tree.depth + 1
+            FROM tree JOIN categories c ON c.id = tree.ancestor_id
+            WHERE c.parent_id IS NOT NULL AND tree.depth < 64
+        )
+        SELECT category_id, ancestor_id, depth FROM tree;
+
+        DROP VIEW IF EXISTS category_defaults;
+        CREATE VIEW category_defaults AS
+        SELECT c.id AS category_id,
+               (SELECT a.tax_class_id FROM category_tree t JOIN categories a ON a.id = t.ancestor_id
+                WHERE t.category_id = c.id AND a.tax_class_id IS NOT NULL ORDER BY t.depth LIMIT 1) AS tax_class_id,
+               (SELECT a.low_stock_threshold FROM category_tree t JOIN categories a ON a.id = t.ancestor_id
+                WHERE t.category_id = c.id AND a.low_stock_threshold IS NOT NULL ORDER BY t.depth LIMIT 1) AS low_stock_threshold,
+               (SELECT a.promotions_allowed FROM category_tree t JOIN categories a ON a.id = t.ancestor_id
+                WHERE t.category_id = c.id AND a.promotions_allowed IS NOT NULL ORDER BY t.depth LIMIT 1) AS promotions_allowed,
+               (SELECT a.loyalty_multiplier_bp FROM category_tree t JOIN categories a ON a.id = t.ancestor_id
+                WHERE t.category_id = c.id AND a.loyalty_multiplier_bp <> 10000 ORDER BY t.depth LIMIT 1) AS loyalty_multiplier_bp
+        FROM categories c;
+
+        DROP TRIGGER IF EXISTS categories_no_cycle_insert;
+        CREATE TRIGGER categories_no_cycle_insert
+        BEFORE INSERT ON categories
+        WHEN NEW.parent_id IS NOT NULL AND NEW.parent_id = NEW.id
+        BEGIN
+            SELECT RAISE(ABORT, 'A category cannot be its own parent');
+        END;
+
+        DROP TRIGGER IF EXISTS categories_no_cycle_update;
+        CREATE TRIGGER categories_no_cycle_update
+        BEFORE UPDATE OF parent_id ON categories
+        WHEN NEW.parent_id IS NOT NULL
+             AND EXISTS (SELECT 1 FROM category_tree WHERE category_id = NEW.parent_id AND ancestor_id = NEW.id)
+        BEGIN
+            SELECT RAISE(ABORT, 'A category cannot be moved under itself');
+        END;
+
+        DROP TRIGGER IF EXISTS products_own_low_stock_threshold;
+        DROP TRIGGER IF EXISTS products_own_low_stock_threshold_insert;
+        "#,
+    )
+    .map_err(|e| format!("Failed to install category views: {}", e))
+}
+
+fn category_name(conn: &Connection, category_id: &str) -> Result<String, String> {
+    conn.query_row("SELECT name FROM categories WHERE id = ?", params![category_id], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Category {} not found", category_id))
+}
+
+// Whether `category_id` is `ancestor_id` or sits somewhere below it
+fn is_within(conn: &Connection, category_id: &str, ancestor_id: &str) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM category_tree WHERE category_id = ? AND ancestor_id = ?)",
+        params![category_id, ancestor_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+// Every category in tree order: parents before children, siblings by
+// sort_order then name
+pub fn category_tree(conn: &Connection) -> Result<Vec<CategoryNode>, String> {
+    let mut stmt = conn
+        .prepare(
+            "WITH RECURSIVE ordered (id, depth, path, sort_key) AS (
+                 SELECT id, 0, name, printf('%010d', sort_order + 1000000000) || name || id
+                 FROM categories WHERE parent_id IS NULL OR parent_id NOT IN (SELECT id FROM categories)
+                 UNION ALL
+                 SELECT c.id, o.depth + 1, o.path || ' / ' || c.name,
+                        o.sort_key || '/' || printf('%010d', c.sort_order + 1000000000) || c.name || c.id
+                 FROM categories c JOIN ordered o ON c.parent_id = o.id
+                 WHERE o.depth < 64
+             )
+             SELECT c.id, c.name, c.description, c.parent_id, c.sort_order, c.is_active, o.depth, o.path,
+                    (SELECT COUNT(*) FROM products p WHERE p.category_id = c.id),
+                    c.tax_class_id, c.low_stock_threshold, c.promotions_allowed,
+                    d.tax_class_id, d.low_stock_threshold, COALESCE(d.promotions_allowed, 1)
+             FROM ordered o
+             JOIN categories c ON c.id = o.id
+             JOIN category_defaults d ON d.category_id = c.id
+             ORDER BY o.sort_key",
+        )
+        .map_err(|e| e.to_string())?;
+    let nodes = stmt
+        .query_map([], |row| {
+            Ok(CategoryNode {
+                id: row.get(0)?,
+                name: row.get(1)?,
+                description: row.get(2)?,
+                parent_id: row.get(3)?,
+                sort_order: row.get(4)?,
+                is_active: row.get(5)?,
+                depth: row.get(6)?,
+                path: row.get(7)?,
+                product_count: row.get(8)?,
+                tax_class_id: row.get(9)?,
+                low_stock_threshold: row.get(10)?,
+                promotions_allowed: row.get(11)?,
+                effective_tax_class_id: row.get(12)?,
+                effective_low_stock_threshold: row.get(13)?,
+                effective_promotions_allowed: row.get(14)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(nodes)
+}
+
+pub fn move_category(conn: &Connection, request: &CategoryMove) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    let name = category_name(conn, &request.category_id)?;
+    if let Some(parent_id) = &request.parent_id {
+        let parent_name = category_name(conn, parent_id)?;
+        if is_within(conn, parent_id, &request.category_id)? {
+            return Err(format!("{} cannot be moved under {}, which is inside it", name, parent_name));
+        }
+    }
+
+    conn.execute(
+        "UPDATE categories SET parent_id = ?, sort_order = COALESCE(?, sort_order), updated_at = ? WHERE id = ?",
+        params![request.parent_id, request.sort_order, chrono::Utc::now().to_rfc3339(), request.category_id],
+    )
+    .map_err(|e| format!("Failed to move category: {}", e))?;
+    println!("🗂️ Category {} moved", name);
+    Ok(())
+}
+
+// Fold one category into another: its products, subcategories and
+// category promotions move to the target and the source is removed
+pub fn merge_category(conn: &Connection, source_id: &str, target_id: &str) -> Result<MergedCategory, String> {
+    session::require_role(&["admin", "manager"])?;
+    if source_id == target_id {
+        return Err("Choose two different categories to merge".to_string());
+    }
+    let source_name = category_name(conn, source_id)?;
+    let target_name = category_name(conn, target_id)?;
+    if is_within(conn, target_id, source_id)? {
+        return Err(format!("{} is inside {} and cannot absorb it", target_name, source_name));
+    }
+
+    let now = chrono::Utc::now().to_rfc3339();
+    let products_moved = conn
+        .execute(
+            "UPDATE products SET category_id = ?, updated_at = ? WHERE category_id = ?",
+            params![target_id, now, source_id],
+        )
+        .map_err(|e| format!("Failed to move products: {}", e))?;
+    let subcategories_moved = conn
+        .execute(
+            "UPDATE categories SET parent_id = ?, updated_at = ? WHERE parent_id = ?",
+            params![target_id, now, source_id],
+        )
+        .map_err(|e| format!("Failed to move subcategories: {}", e))?;
+    conn.execute(
+        "UPDATE promotions SET target_id = ? WHERE scope = 'category' AND target_id = ?",
+        params![target_id, source_id],
+    )
+    .map_err(|e| format!("Failed to move category promotions: {}", e))?;
+    conn.execute(
+        "UPDATE stocktakes SET category_id = ? WHERE category_id = ?",
+        params![target_id, source_id],
+    )
+    .map_err(|e| format!("Failed to move stocktakes: {}", e))?;
+    conn.execute("DELETE FROM categories WHERE id = ?", params![source_id])
+        .map_err(|e| format!("Failed to remove category {}: {}", source_name, e))?;
+
+    println!("🗂️ Category {} merged into {}", source_name, target_name);
+    Ok(MergedCategory {
+        source_id: source_id.to_string(),
+        target_id: target_id.to_string(),
+        products_moved,
+        subcategories_moved,
+    })
+}
+
+pub fn set_category_defaults(conn: &Connection, defaults: &CategoryDefaults) -> Result<CategoryNode, String> {
+    session::require_role(&["admin", "manager"])?;
+    category_name(conn, &defaults.category_id)?;
+    if let Some(tax_class_id) = &defaults.tax_class_id {
+        let exists: bool = conn
+            .query_row("SELECT COUNT(*) > 0 FROM tax_classes WHERE id = ?", params![tax_class_id], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        if !exists {
+            return Err(format!("Tax class {} not found", tax_class_id));
+        }
+    }
+    if defaults.low_stock_threshold.is_some_and(|t| t < 0) {
+        return Err("Low-stock threshold cannot be negative".to_string());
+    }
+
+    conn.execute(
+        "UPDATE categories SET tax_class_id = ?, low_stock_threshold = ?, promotions_allowed = ?, updated_at = ?
+         WHERE id = ?",
+        params![
+            defaults.tax_class_id,
+            defaults.low_stock_threshold,
+            defaults.promotions_allowed,
+            chrono::Utc::now().to_rfc3339(),
+            defaults.category_id
+        ],
+    )
+    .map_err(|e| format!("Failed to save category defaults: {}", e))?;
+
+    category_tree(conn)?
+        .into_iter()
+        .find(|c| c.id == defaults.category_id)
+        .ok_or_else(|| format!("Category {} not found", defaults.category_id))
+}
+
+// A product's own promotion eligibility; None follows its category
+pub fn set_product_promotions(conn: &Connection, product_id: &str, promotions_allowed: Option<bool>) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    let updated = conn
+        .execute(
+            "UPDATE products SET promotions_allowed = ?, updated_at = ? WHERE id = ?",
+            params![promotions_allowed, chrono::Utc::now().to_rfc3339(), product_id],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err(format!("Product {} not found", product_id));
+    }
+    Ok(())
+}
+
+// Give a product its own low-stock threshold, overriding its category's
+pub fn set_low_stock_threshold(conn: &Connection, product_id: &str, threshold: i64) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    if threshold < 0 {
+        return Err("Low-stock threshold cannot be negative".to_string());
+    }
+    let updated = conn
+        .execute(
+            "UPDATE products SET low_stock_threshold = ?, own_low_stock_threshold = 1, updated_at = ? WHERE id = ?",
+            params![threshold, chrono::Utc::now().to_rfc3339(), product_id],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err(format!("Product {} not found", product_id));
+    }
+    Ok(())
+}
+
+// Hand a product's low-stock threshold back to its category
+pub fn inherit_low_stock_threshold(conn: &Connection, product_id: &str) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    let updated = conn
+        .execute(
+            "UPDATE products SET own_low_stock_threshold = 0, updated_at = ? WHERE id = ?",
+            params![chrono::Utc::now().to_rfc3339(), product_id],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err(format!("Product {} not found", product_id));
+    }
+    Ok(())
+}
+
+// The category and all of its ancestors, nearest first
+pub fn ancestors(conn: &Connection, category_id: &str) -> Result<Vec<String>, String> {
+    let mut stmt = conn
+        .prepare("SELECT ancestor_id FROM category_tree WHERE category_id = ? ORDER BY depth")
+        .map_err(|e| e.to_string())?;
+    let ids = stmt
+        .query_map(params![category_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(ids)
+}
+
+// Sales net of returns for every category, rolled up through its subtree
+pub fn category_sales(conn: &Connection, from: Option<&str>, to: Option<&str>) -> Result<Vec<CategorySales>, String> {
+    let nodes = category_tree(conn)?;
+    let mut stmt = conn
+        .prepare(
+            "WITH sold AS (
+                 SELECT si.id, p.category_id,
+                        COALESCE(si.paid_minor, si.total_minor - si.discount_minor)
+                          - COALESCE((SELECT SUM(ri.amount_minor) FROM return_items ri WHERE ri.sale_item_id = si.id), 0)
+                          AS net_minor,
+                        COALESCE(si.cost_minor, 0)
+                          - COALESCE(si.cost_minor * (SELECT SUM(ri.quantity) FROM return_items ri WHERE ri.sale_item_id = si.id)
+                                     / si.quantity, 0)
+                          AS cost_minor
+                 FROM sale_items si
+                 JOIN reportable_sales s ON s.id = si.sale_id
+                 JOIN products p ON p.id = si.product_id
+                 WHERE (?1 IS NULL OR s.created_at >= ?1) AND (?2 IS NULL OR s.created_at < ?2)
+             )
+             SELECT t.ancestor_id,
+                    COALESCE(SUM(CASE WHEN t.depth = 0 THEN sold.net_minor END), 0),
+                    COALESCE(SUM(sold.net_minor), 0),
+                    COALESCE(SUM(sold.cost_minor), 0),
+                    COUNT(sold.id)
+             FROM sold JOIN category_tree t ON t.category_id = sold.category_id
+             GROUP BY t.ancestor_id",
+        )
+        .map_err(|e| e.to_string())?;
+    let totals: Vec<(String, i64, i64, i64, i64)> = stmt
+        .query_map(params![from, to], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(nodes
+        .into_iter()
+        .map(|node| {
+            let (own_net_minor, net_minor, cost_minor, lines_sold) = totals
+                .iter()
+                .find(|t| t.0 == node.id)
+                .map(|t| (t.1, t.2, t.3, t.4))
+                .unwrap_or((0, 0, 0, 0));
+            CategorySales {
+                category_id: node.id,
+                name: node.name,
+                parent_id: node.parent_id,
+                depth: node.depth,
+                own_net_minor,
+                net_minor,
+                cost_minor,
+                gross_profit_minor: net_minor - cost_minor,
+                lines_sold,
+            }
+        })
+        .collect())
+}
@@ -1,5 +1,32 @@
-use crate::printer::{self, Receipt};
+use crate::carts::{self, ParkRequest};
+use crate::categories::{self, CategoryDefaults, CategoryMove};
+use crate::payments;
+use crate::printer::{self, Receipt, ReceiptHeader};
+use crate::returns::{self, ReturnRequest};
+use crate::promotions::{self, Promotion};
+use crate::purchasing::{self, PurchaseOrderRequest, ReceiveRequest, SupplierInvoiceRequest};
+use crate::receivables::{self, AccountPayment};
+use crate::replenishment::{self, ReorderQuery, SupplierProductRequest};
+use crate::sales::{self, QuoteRequest, SaleRequest, VoidRequest};
+use crate::search;
+use crate::audit::{self, AuditQuery};
+use crate::barcode;
+use crate::costing;
 use crate::database;
+use crate::inventory::{self, StockAdjustment};
+use crate::kits::{self, KitRecipe};
+use crate::ledger;
+use crate::lots;
+use crate::loyalty::{self, LoyaltyProgram};
+use crate::session::{self, SessionUser};
+use crate::shifts;
+use crate::stocktake::{self, CountEntry, OpenStocktake, PostStocktake};
+use crate::stored_value::{self, IssueRequest};
+use crate::tax::{self, TaxClass};
+use crate::training;
+use crate::units::{self, PackRequest};
+use crate::variants::{self, VariantMatrix, VariantUpdate};
+use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
 
 #[tauri::command]
@@ -30,7 +57,8 @@ pub async fn db_run(sql: String, params: Vec<String>) -> Result<String, String>
 #[tauri::command]
 pub async fn print_receipt(receipt: Receipt) -> Result<String, String> {
     println!("[Tauri] print_receipt START");
-    let result = printer::print_receipt(receipt);
+    let training = database::get_db()?.is_training();
+    let result = printer::print_receipt(receipt, training);
     println!("[Tauri] print_receipt END");
     result
 }
@@ -62,51 +90,70 @@ pub async fn set_default_printer(printer_name: String) -> Result<String, String>
 pub struct UserLogin {
     username: String,
     password: String,
+    #[serde(default)]
+    profile: Option<String>,
+}
+
+// The session user and their full name if the credentials match an active account
+fn authenticate(conn: &Connection, login_data: &UserLogin) -> Result<(SessionUser, String), String> {
+    let user = session::authenticate(conn, &login_data.username, &login_data.password)?;
+    let full_name = conn
+        .query_row("SELECT full_name FROM users WHERE id = ?", [&user.id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    Ok((user, full_name))
 }
 
 #[tauri::command]
 pub async fn login_user(login_data: UserLogin) -> Result<String, String> {
-    let conn = database::get_db().get_connection();
-    
-    // Find user by username
-    let user_result = conn.query_row(
-        "SELECT id, password_hash, full_name, role, is_active FROM users WHERE username = ?",
-        &[&login_data.username],
-        |row| {
-            Ok((
-                row.get::<_, String>(0)?, // id
-                row.get::<_, String>(1)?, // password_hash
-                row.get::<_, String>(2)?, // full_name
-                row.get::<_, String>(3)?, // role
-                row.get::<_, i32>(4)?,    // is_active
-            ))
-        }
-    );
-    
-    match user_result {
-        Ok((user_id, password_hash, full_name, role, is_active)) => {
-            if is_active == 0 {
-                return Err("Account is disabled".to_string());
-            }
-            
-            // Verify password
-            if bcrypt::verify(&login_data.password, &password_hash).map_err(|e| e.to_string())? {
-                let result = serde_json::json!({
-                    "success": true,
-                    "user": {
-                        "id": user_id,
-                        "username": login_data.username,
-                        "full_name": full_name,
-                        "role": role
-                    }
-                });
-                Ok(serde_json::to_string(&result).unwrap())
-            } else {
-                Err("Invalid username or password".to_string())
-            }
+    let db = database::get_db()?;
+
+    // Logging in to another store profile checks the credentials against
+    // that store's database and only swaps it in once they match
+    let active = db.active_profile();
+    let target = match &login_data.profile {
+        Some(name) if *name != active.name => db.open_profile(name).map(Some),
+        _ => Ok(None),
+    };
+    let authenticated = target.and_then(|target| {
+        let user = match &target {
+            Some((_, conn)) => authenticate(conn, &login_data)?,
+            None => authenticate(&db.get_connection(), &login_data)?,
+        };
+        Ok((target, user))
+    });
+
+    // A failed login leaves nobody signed in
+    let (target, (user, full_name)) = match authenticated {
+        Ok(authenticated) => authenticated,
+        Err(e) => {
+            session::clear_current_user();
+            return Err(e);
         }
-        Err(_) => Err("User not found".to_string()),
-    }
+    };
+    let profile = match target {
+        Some((profile, conn)) => db.switch_to_open(profile, conn),
+        None => active,
+    };
+
+    session::set_current_user(user.clone());
+
+    let result = serde_json::json!({
+        "success": true,
+        "user": {
+            "id": user.id,
+            "username": user.username,
+            "full_name": full_name,
+            "role": user.role
+        },
+        "profile": profile.name
+    });
+    Ok(serde_json::to_string(&result).unwrap())
+}
+
+#[tauri::command]
+pub async fn logout_user() -> Result<String, String> {
+    session::clear_current_user();
+    Ok("Logged out".to_string())
 }
 
 #[tauri::command]
@@ -152,4 +199,808 @@ pub async fn create_user(user_data: serde_json::Value) -> Result<String, String>
     )?;
     
     Ok("User created successfully".to_string())
-}
\ No newline at end of file
+}
+#[tauri::command]
+pub async fn list_store_profiles() -> Result<String, String> {
+    let db = database::get_db()?;
+    let profiles = db.config().list_profiles()?;
+    let active = db.active_profile();
+
+    let result = serde_json::json!({
+        "active": active.name,
+        "profiles": profiles
+    });
+    Ok(serde_json::to_string(&result).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_active_profile() -> Result<String, String> {
+    let profile = database::get_db()?.active_profile();
+    Ok(serde_json::to_string(&profile).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_training_status() -> Result<String, String> {
+    let db = database::get_db()?;
+    let result = serde_json::json!({
+        "training": db.is_training(),
+        "fiscal_enabled": training::fiscal_submission_allowed(db),
+        "profile": db.active_profile(),
+        "live_profile": db.live_profile()
+    });
+    Ok(serde_json::to_string(&result).unwrap())
+}
+
+#[tauri::command]
+pub async fn enter_training_mode() -> Result<String, String> {
+    println!("[Tauri] enter_training_mode START");
+    let profile = training::enter_training(database::get_db()?)?;
+    println!("[Tauri] enter_training_mode END");
+    Ok(serde_json::to_string(&profile).unwrap())
+}
+
+#[tauri::command]
+pub async fn exit_training_mode() -> Result<String, String> {
+    println!("[Tauri] exit_training_mode START");
+    let profile = training::exit_training(database::get_db()?)?;
+    println!("[Tauri] exit_training_mode END");
+    Ok(serde_json::to_string(&profile).unwrap())
+}
+
+#[tauri::command]
+pub async fn reset_training_database() -> Result<String, String> {
+    println!("[Tauri] reset_training_database START");
+    let profile = training::reset_training(database::get_db()?)?;
+    println!("[Tauri] reset_training_database END");
+    Ok(serde_json::to_string(&profile).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_audit_log(query: Option<AuditQuery>) -> Result<String, String> {
+    let query = query.unwrap_or_default();
+    let conn = database::get_db()?.get_connection();
+    let page = audit::get_audit_log(&conn, &query)?;
+    Ok(serde_json::to_string(&page).unwrap())
+}
+
+#[tauri::command]
+pub async fn verify_ledger() -> Result<String, String> {
+    println!("[Tauri] verify_ledger START");
+    let conn = database::get_db()?.get_connection();
+    let report = ledger::verify_ledger(&conn)?;
+    println!("[Tauri] verify_ledger END");
+    Ok(serde_json::to_string(&report).unwrap())
+}
+
+#[tauri::command]
+pub async fn search_products(query: String, limit: Option<u32>) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let matches = search::search_products(&conn, &query, limit)?;
+    Ok(serde_json::to_string(&matches).unwrap())
+}
+
+#[tauri::command]
+pub async fn lookup_barcode(code: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let lookup = barcode::lookup_barcode(&conn, &code)?;
+    Ok(serde_json::to_string(&lookup).unwrap())
+}
+
+#[tauri::command]
+pub async fn add_product_barcode(product_id: String, barcode: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let entry = barcode::add_product_barcode(&conn, &product_id, &barcode)?;
+    Ok(serde_json::to_string(&entry).unwrap())
+}
+
+#[tauri::command]
+pub async fn remove_product_barcode(barcode: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    barcode::remove_product_barcode(&conn, &barcode)?;
+    Ok("Barcode removed".to_string())
+}
+
+#[tauri::command]
+pub async fn list_product_barcodes(product_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let barcodes = barcode::list_product_barcodes(&conn, &product_id)?;
+    Ok(serde_json::to_string(&barcodes).unwrap())
+}
+
+#[tauri::command]
+pub async fn create_return(request: ReturnRequest) -> Result<String, String> {
+    println!("[Tauri] create_return START");
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let record = returns::create_return(&tx, &request)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    println!("[Tauri] create_return END");
+    Ok(serde_json::to_string(&record).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_return(return_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let record = returns::get_return(&conn, &return_id)?;
+    Ok(serde_json::to_string(&record).unwrap())
+}
+
+#[tauri::command]
+pub async fn print_credit_receipt(return_id: String, header: ReceiptHeader) -> Result<String, String> {
+    println!("[Tauri] print_credit_receipt START");
+    let db = database::get_db()?;
+    let record = returns::get_return(&db.get_connection(), &return_id)?;
+    let receipt = returns::credit_receipt(&record, header);
+    let result = printer::print_receipt(receipt, db.is_training());
+    println!("[Tauri] print_credit_receipt END");
+    result
+}
+
+#[tauri::command]
+pub async fn price_cart(request: QuoteRequest) -> Result<String, String> {
+    let db = database::get_db()?;
+    let rounding = db.config().tax_rounding()?;
+    let conn = db.get_connection();
+    let quote = sales::quote_cart(
+        &conn,
+        request.customer_id.as_deref(),
+        &request.items,
+        request.discount_minor,
+        rounding,
+    )?;
+    Ok(serde_json::to_string(&quote).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_promotions() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let promotions = promotions::list_promotions(&conn)?;
+    Ok(serde_json::to_string(&promotions).unwrap())
+}
+
+#[tauri::command]
+pub async fn save_promotion(promotion: Promotion) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let saved = promotions::save_promotion(&conn, &promotion)?;
+    Ok(serde_json::to_string(&saved).unwrap())
+}
+
+#[tauri::command]
+pub async fn create_sale(request: SaleRequest) -> Result<String, String> {
+    println!("[Tauri] create_sale START");
+    let db = database::get_db()?;
+    let rounding = db.config().tax_rounding()?;
+    let minor_units_per_unit = db.config().minor_units_per_unit();
+    let mut conn = db.get_connection();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let sale = sales::create_sale(&tx, &request, rounding, minor_units_per_unit)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    println!("[Tauri] create_sale END");
+    Ok(serde_json::to_string(&sale).unwrap())
+}
+
+#[tauri::command]
+pub async fn print_sale_receipt(sale_id: String, header: ReceiptHeader) -> Result<String, String> {
+    println!("[Tauri] print_sale_receipt START");
+    let db = database::get_db()?;
+    let receipt = sales::sale_receipt(&db.get_connection(), &sale_id, header)?;
+    let result = printer::print_receipt(receipt, db.is_training());
+    println!("[Tauri] print_sale_receipt END");
+    result
+}
+
+#[tauri::command]
+pub async fn list_tax_classes() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let classes = tax::list_tax_classes(&conn)?;
+    Ok(serde_json::to_string(&classes).unwrap())
+}
+
+#[tauri::command]
+pub async fn save_tax_class(tax_class: TaxClass) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let saved = tax::save_tax_class(&conn, &tax_class)?;
+    Ok(serde_json::to_string(&saved).unwrap())
+}
+
+#[tauri::command]
+pub async fn set_product_tax(
+    product_id: String,
+    tax_class_id: Option<String>,
+    price_includes_tax: bool,
+) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    tax::assign_product_tax(&conn, &product_id, tax_class_id.as_deref(), price_includes_tax)?;
+    Ok("Product tax updated".to_string())
+}
+
+#[tauri::command]
+pub async fn get_loyalty_program() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let program = loyalty::get_program(&conn)?;
+    Ok(serde_json::to_string(&program).unwrap())
+}
+
+#[tauri::command]
+pub async fn save_loyalty_program(program: LoyaltyProgram) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let saved = loyalty::save_program(&conn, &program)?;
+    Ok(serde_json::to_string(&saved).unwrap())
+}
+
+#[tauri::command]
+pub async fn set_category_loyalty_multiplier(category_id: String, multiplier_bp: i64) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    loyalty::set_category_multiplier(&conn, &category_id, multiplier_bp)?;
+    Ok("Loyalty multiplier updated".to_string())
+}
+
+#[tauri::command]
+pub async fn get_loyalty_account(customer_id: String) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    // Reading an account lapses any expired points first
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let account = loyalty::account(&tx, &customer_id)?;
+    tx.commit().map_err(|e| format!("Failed to commit expired points: {}", e))?;
+    Ok(serde_json::to_string(&account).unwrap())
+}
+
+#[tauri::command]
+pub async fn issue_gift_card(request: IssueRequest) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let card = stored_value::issue_gift_card(&tx, &request)?;
+    tx.commit().map_err(|e| format!("Failed to commit gift card: {}", e))?;
+    Ok(serde_json::to_string(&card).unwrap())
+}
+
+#[tauri::command]
+pub async fn top_up_gift_card(code: String, amount_minor: i64, payment_method: String) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let card = stored_value::top_up_gift_card(&tx, &code, amount_minor, &payment_method)?;
+    tx.commit().map_err(|e| format!("Failed to commit top-up: {}", e))?;
+    Ok(serde_json::to_string(&card).unwrap())
+}
+
+#[tauri::command]
+pub async fn check_gift_card_balance(code: String) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    // Looking up a card expires any that are past their date first
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let card = stored_value::check_balance(&tx, &code)?;
+    tx.commit().map_err(|e| format!("Failed to commit expired gift cards: {}", e))?;
+    Ok(serde_json::to_string(&card).unwrap())
+}
+
+#[tauri::command]
+pub async fn expire_gift_cards() -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let expired = stored_value::expire_gift_cards(&tx)?;
+    tx.commit().map_err(|e| format!("Failed to commit expired gift cards: {}", e))?;
+    Ok(serde_json::to_string(&expired).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_stored_value_history(code: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let history = stored_value::history(&conn, &code)?;
+    Ok(serde_json::to_string(&history).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_customer_store_credit(customer_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let account = stored_value::customer_store_credit(&conn, &customer_id)?;
+    Ok(serde_json::to_string(&account).unwrap())
+}
+
+#[tauri::command]
+pub async fn open_stocktake(request: OpenStocktake) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let stocktake = stocktake::open_stocktake(&tx, &request)?;
+    tx.commit().map_err(|e| format!("Failed to commit stocktake: {}", e))?;
+    Ok(serde_json::to_string(&stocktake).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_stocktakes() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let stocktakes = stocktake::list_stocktakes(&conn)?;
+    Ok(serde_json::to_string(&stocktakes).unwrap())
+}
+
+#[tauri::command]
+pub async fn record_stock_count(entry: CountEntry) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let line = stocktake::record_count(&conn, &entry)?;
+    Ok(serde_json::to_string(&line).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_stocktake_report(stocktake_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let report = stocktake::stocktake_report(&conn, &stocktake_id)?;
+    Ok(serde_json::to_string(&report).unwrap())
+}
+
+#[tauri::command]
+pub async fn post_stocktake(request: PostStocktake) -> Result<String, String> {
+    println!("[Tauri] post_stocktake START");
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let report = stocktake::post_stocktake(&tx, &request)?;
+    tx.commit().map_err(|e| format!("Failed to commit stocktake: {}", e))?;
+    println!("[Tauri] post_stocktake END");
+    Ok(serde_json::to_string(&report).unwrap())
+}
+
+#[tauri::command]
+pub async fn cancel_stocktake(stocktake_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let stocktake = stocktake::cancel_stocktake(&conn, &stocktake_id)?;
+    Ok(serde_json::to_string(&stocktake).unwrap())
+}
+
+#[tauri::command]
+pub async fn set_lot_tracking(product_id: String, track_lots: bool, expiry_warning_days: i64) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    lots::set_lot_tracking(&tx, &product_id, track_lots, expiry_warning_days)?;
+    let product_lots = lots::list_lots(&tx, &product_id)?;
+    tx.commit().map_err(|e| format!("Failed to commit lot tracking: {}", e))?;
+    Ok(serde_json::to_string(&product_lots).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_product_lots(product_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let product_lots = lots::list_lots(&conn, &product_id)?;
+    Ok(serde_json::to_string(&product_lots).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_expiry_alerts() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let alerts = lots::expiry_alerts(&conn)?;
+    Ok(serde_json::to_string(&alerts).unwrap())
+}
+
+#[tauri::command]
+pub async fn write_off_expired_lots() -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let movements = lots::write_off_expired(&tx)?;
+    tx.commit().map_err(|e| format!("Failed to commit expired write-off: {}", e))?;
+    Ok(serde_json::to_string(&movements).unwrap())
+}
+
+#[tauri::command]
+pub async fn set_product_unit(product_id: String, unit: String, quantity_decimals: i64) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let product_unit = units::set_product_unit(&conn, &product_id, &unit, quantity_decimals)?;
+    Ok(serde_json::to_string(&product_unit).unwrap())
+}
+
+#[tauri::command]
+pub async fn add_product_pack(request: PackRequest) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let pack = units::add_pack(&conn, &request)?;
+    Ok(serde_json::to_string(&pack).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_product_packs(product_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let packs = units::list_packs(&conn, &product_id)?;
+    Ok(serde_json::to_string(&packs).unwrap())
+}
+
+#[tauri::command]
+pub async fn remove_product_pack(pack_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    units::remove_pack(&conn, &pack_id)?;
+    Ok("Pack removed".to_string())
+}
+
+#[tauri::command]
+pub async fn get_costing_settings() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let settings = costing::get_settings(&conn)?;
+    Ok(serde_json::to_string(&settings).unwrap())
+}
+
+#[tauri::command]
+pub async fn set_costing_method(method: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let settings = costing::set_method(&conn, &method)?;
+    Ok(serde_json::to_string(&settings).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_inventory_valuation(as_of: Option<String>) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    // Valuing first costs any movements not yet costed
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let valuation = costing::valuation(&tx, as_of.as_deref())?;
+    tx.commit().map_err(|e| format!("Failed to commit movement costs: {}", e))?;
+    Ok(serde_json::to_string(&valuation).unwrap())
+}
+
+#[tauri::command]
+pub async fn set_kit_components(recipe: KitRecipe) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let kit = kits::set_kit_components(&tx, &recipe)?;
+    tx.commit().map_err(|e| format!("Failed to commit kit: {}", e))?;
+    Ok(serde_json::to_string(&kit).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_kit(kit_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let kit = kits::get_kit(&conn, &kit_id)?;
+    Ok(serde_json::to_string(&kit).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_kits() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let kits = kits::list_kits(&conn)?;
+    Ok(serde_json::to_string(&kits).unwrap())
+}
+
+#[tauri::command]
+pub async fn assemble_kits(kit_id: String, quantity: i64) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let assembly = kits::assemble(&tx, &kit_id, quantity)?;
+    tx.commit().map_err(|e| format!("Failed to commit assembly: {}", e))?;
+    Ok(serde_json::to_string(&assembly).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_category_tree() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let tree = categories::category_tree(&conn)?;
+    Ok(serde_json::to_string(&tree).unwrap())
+}
+
+#[tauri::command]
+pub async fn move_category(request: CategoryMove) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    categories::move_category(&conn, &request)?;
+    let tree = categories::category_tree(&conn)?;
+    Ok(serde_json::to_string(&tree).unwrap())
+}
+
+#[tauri::command]
+pub async fn merge_category(source_id: String, target_id: String) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let merged = categories::merge_category(&tx, &source_id, &target_id)?;
+    tx.commit().map_err(|e| format!("Failed to commit category merge: {}", e))?;
+    Ok(serde_json::to_string(&merged).unwrap())
+}
+
+#[tauri::command]
+pub async fn set_category_defaults(defaults: CategoryDefaults) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let category = categories::set_category_defaults(&conn, &defaults)?;
+    Ok(serde_json::to_string(&category).unwrap())
+}
+
+#[tauri::command]
+pub async fn set_product_promotions(product_id: String, promotions_allowed: Option<bool>) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    categories::set_product_promotions(&conn, &product_id, promotions_allowed)?;
+    Ok("Product promotions updated".to_string())
+}
+
+#[tauri::command]
+pub async fn set_low_stock_threshold(product_id: String, threshold: i64) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    categories::set_low_stock_threshold(&conn, &product_id, threshold)?;
+    Ok("Low-stock threshold updated".to_string())
+}
+
+#[tauri::command]
+pub async fn inherit_low_stock_threshold(product_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    categories::inherit_low_stock_threshold(&conn, &product_id)?;
+    Ok("Low-stock threshold follows the category".to_string())
+}
+
+#[tauri::command]
+pub async fn get_category_sales(from: Option<String>, to: Option<String>) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let report = categories::category_sales(&conn, from.as_deref(), to.as_deref())?;
+    Ok(serde_json::to_string(&report).unwrap())
+}
+
+#[tauri::command]
+pub async fn create_variant_matrix(matrix: VariantMatrix) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let product_variants = variants::create_variant_matrix(&tx, &matrix)?;
+    tx.commit().map_err(|e| format!("Failed to commit variants: {}", e))?;
+    Ok(serde_json::to_string(&product_variants).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_product_variants(parent_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let product_variants = variants::list_variants(&conn, &parent_id)?;
+    Ok(serde_json::to_string(&product_variants).unwrap())
+}
+
+#[tauri::command]
+pub async fn update_product_variant(update: VariantUpdate) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let variant = variants::update_variant(&tx, &update)?;
+    tx.commit().map_err(|e| format!("Failed to commit variant: {}", e))?;
+    Ok(serde_json::to_string(&variant).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_variant_sales(parent_id: String, from: Option<String>, to: Option<String>) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let sales = variants::variant_sales(&conn, &parent_id, from.as_deref(), to.as_deref())?;
+    Ok(serde_json::to_string(&sales).unwrap())
+}
+
+#[tauri::command]
+pub async fn create_purchase_order(request: PurchaseOrderRequest) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let order = purchasing::create_purchase_order(&tx, &request)?;
+    tx.commit().map_err(|e| format!("Failed to commit purchase order: {}", e))?;
+    Ok(serde_json::to_string(&order).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_purchase_orders(status: Option<String>) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let orders = purchasing::list_purchase_orders(&conn, status.as_deref())?;
+    Ok(serde_json::to_string(&orders).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_purchase_order(order_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let order = purchasing::get_purchase_order(&conn, &order_id)?;
+    Ok(serde_json::to_string(&order).unwrap())
+}
+
+#[tauri::command]
+pub async fn send_purchase_order(order_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let order = purchasing::send_purchase_order(&conn, &order_id)?;
+    Ok(serde_json::to_string(&order).unwrap())
+}
+
+#[tauri::command]
+pub async fn close_purchase_order(order_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let order = purchasing::close_purchase_order(&conn, &order_id)?;
+    Ok(serde_json::to_string(&order).unwrap())
+}
+
+#[tauri::command]
+pub async fn receive_goods(request: ReceiveRequest) -> Result<String, String> {
+    println!("[Tauri] receive_goods START");
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let receipt = purchasing::receive_goods(&tx, &request)?;
+    tx.commit().map_err(|e| format!("Failed to commit goods receipt: {}", e))?;
+    println!("[Tauri] receive_goods END");
+    Ok(serde_json::to_string(&receipt).unwrap())
+}
+
+#[tauri::command]
+pub async fn record_supplier_invoice(request: SupplierInvoiceRequest) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let payable = purchasing::record_supplier_invoice(&conn, &request)?;
+    Ok(serde_json::to_string(&payable).unwrap())
+}
+
+#[tauri::command]
+pub async fn pay_supplier_invoice(expense_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let payable = purchasing::pay_supplier_invoice(&conn, &expense_id)?;
+    Ok(serde_json::to_string(&payable).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_payables() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let payables = purchasing::list_payables(&conn)?;
+    Ok(serde_json::to_string(&payables).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_low_stock_alerts() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let alerts = replenishment::low_stock_alerts(&conn)?;
+    Ok(serde_json::to_string(&alerts).unwrap())
+}
+
+#[tauri::command]
+pub async fn set_supplier_lead_time(supplier_id: String, lead_time_days: i64) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    replenishment::set_supplier_lead_time(&conn, &supplier_id, lead_time_days)?;
+    Ok("Lead time updated".to_string())
+}
+
+#[tauri::command]
+pub async fn set_supplier_product(request: SupplierProductRequest) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let product = replenishment::set_supplier_product(&conn, &request)?;
+    Ok(serde_json::to_string(&product).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_supplier_products(supplier_id: Option<String>, product_id: Option<String>) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let products = replenishment::list_supplier_products(&conn, supplier_id.as_deref(), product_id.as_deref())?;
+    Ok(serde_json::to_string(&products).unwrap())
+}
+
+#[tauri::command]
+pub async fn remove_supplier_product(supplier_id: String, product_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    replenishment::remove_supplier_product(&conn, &supplier_id, &product_id)?;
+    Ok("Supplier product removed".to_string())
+}
+
+#[tauri::command]
+pub async fn get_reorder_suggestions(query: ReorderQuery) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let suggestions = replenishment::reorder_suggestions(&conn, &query)?;
+    Ok(serde_json::to_string(&suggestions).unwrap())
+}
+
+#[tauri::command]
+pub async fn create_reorder_purchase_orders(query: ReorderQuery) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let orders = replenishment::draft_purchase_orders(&tx, &query)?;
+    tx.commit().map_err(|e| format!("Failed to commit purchase orders: {}", e))?;
+    Ok(serde_json::to_string(&orders).unwrap())
+}
+
+#[tauri::command]
+pub async fn set_customer_credit_limit(customer_id: String, limit_minor: i64) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    receivables::set_credit_limit(&conn, &customer_id, limit_minor)?;
+    Ok("Credit limit updated".to_string())
+}
+
+#[tauri::command]
+pub async fn receive_account_payment(payment: AccountPayment) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let entry = receivables::receive_payment(&tx, &payment)?;
+    tx.commit().map_err(|e| format!("Failed to commit account payment: {}", e))?;
+    Ok(serde_json::to_string(&entry).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_customer_account(customer_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let entries = receivables::entries(&conn, &customer_id)?;
+    Ok(serde_json::to_string(&entries).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_receivables_aging() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let report = receivables::aging_report(&conn)?;
+    Ok(serde_json::to_string(&report).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_customer_statement(customer_id: String, from: Option<String>, to: Option<String>) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let statement = receivables::statement(&conn, &customer_id, from.as_deref(), to.as_deref())?;
+    Ok(serde_json::to_string(&statement).unwrap())
+}
+
+#[tauri::command]
+pub async fn print_customer_statement(
+    customer_id: String,
+    from: Option<String>,
+    to: Option<String>,
+    header: ReceiptHeader,
+) -> Result<String, String> {
+    println!("[Tauri] print_customer_statement START");
+    let db = database::get_db()?;
+    let statement = receivables::statement(&db.get_connection(), &customer_id, from.as_deref(), to.as_deref())?;
+    let document = receivables::statement_document(&statement, header);
+    let result = printer::print_statement(&document, db.is_training());
+    println!("[Tauri] print_customer_statement END");
+    result
+}
+
+#[tauri::command]
+pub async fn stock_history(product_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let movements = inventory::stock_history(&conn, &product_id)?;
+    Ok(serde_json::to_string(&movements).unwrap())
+}
+
+#[tauri::command]
+pub async fn adjust_stock(adjustment: StockAdjustment) -> Result<String, String> {
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| format!("Failed to start transaction: {}", e))?;
+    let movement = inventory::adjust_stock(&tx, &adjustment)?;
+    tx.commit().map_err(|e| format!("Failed to commit stock movement: {}", e))?;
+    Ok(serde_json::to_string(&movement).unwrap())
+}
+
+#[tauri::command]
+pub async fn verify_stock() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let discrepancies = inventory::verify_stock(&conn)?;
+    Ok(serde_json::to_string(&discrepancies).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_sale_payments(sale_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let payments = payments::payments_for_sale(&conn, &sale_id)?;
+    Ok(serde_json::to_string(&payments).unwrap())
+}
+
+#[tauri::command]
+pub async fn get_shift_reconciliation(shift_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let reconciliation = shifts::reconcile(&conn, &shift_id)?;
+    Ok(serde_json::to_string(&reconciliation).unwrap())
+}
+
+#[tauri::command]
+pub async fn park_cart(request: ParkRequest) -> Result<String, String> {
+    let db = database::get_db()?;
+    let expiry = db.config().parked_cart_expiry();
+    let mut conn = db.get_connection();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let cart = carts::park_cart(&tx, &request, expiry)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    Ok(serde_json::to_string(&cart).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_parked_carts() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let carts = carts::list_parked_carts(&conn)?;
+    Ok(serde_json::to_string(&carts).unwrap())
+}
+
+#[tauri::command]
+pub async fn recall_cart(cart_id: String) -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let cart = carts::recall_cart(&conn, &cart_id)?;
+    Ok(serde_json::to_string(&cart).unwrap())
+}
+
+#[tauri::command]
+pub async fn void_sale(request: VoidRequest) -> Result<String, String> {
+    println!("[Tauri] void_sale START");
+    let mut conn = database::get_db()?.get_connection();
+    let tx = conn.transaction().map_err(|e| e.to_string())?;
+    let voided = sales::void_sale(&tx, &request)?;
+    tx.commit().map_err(|e| e.to_string())?;
+    println!("[Tauri] void_sale END");
+    Ok(serde_json::to_string(&voided).unwrap())
+}
+
+#[tauri::command]
+pub async fn list_voided_sales() -> Result<String, String> {
+    let conn = database::get_db()?.get_connection();
+    let voided = sales::list_voided_sales(&conn)?;
+    Ok(serde_json::to_string(&voided).unwrap())
+}
@@ -0,0 +1,232 @@
+use crate::tax::Rounding;
+use directories::ProjectDirs;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+pub const DEFAULT_PROFILE: &str = "default";
+pub const DB_PATH_ENV: &str = "GLASSPOS_DB_PATH";
+pub const PROFILE_ENV: &str = "GLASSPOS_PROFILE";
+pub const DEFAULT_PARKED_CART_EXPIRY_MINUTES: i64 = 480;
+pub const DEFAULT_MINOR_UNITS_PER_UNIT: i64 = 100;
+
+// Contents of config.json in the app config directory. Every field is optional
+// so an empty or missing file falls back to the built-in defaults.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct AppConfig {
+    #[serde(default)]
+    pub database_path: Option<PathBuf>,
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileConfig>,
+    // How long a parked cart is held before it expires
+    #[serde(default)]
+    pub parked_cart_expiry_minutes: Option<i64>,
+    // half_up (default), half_even, down or up
+    #[serde(default)]
+    pub tax_rounding: Option<String>,
+    // Minor units in one unit of the currency: 100 for cents, 1000 for
+    // three-decimal currencies
+    #[serde(default)]
+    pub minor_units_per_unit: Option<i64>,
+    // false stops the low-stock watcher notifying the UI
+    #[serde(default)]
+    pub low_stock_alerts: Option<bool>,
+}
+
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProfileConfig {
+    #[serde(default)]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub database_path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoreProfile {
+    pub name: String,
+    pub label: String,
+    pub database_path: PathBuf,
+    #[serde(default)]
+    pub training: bool,
+}
+
+// Overrides given on the command line, e.g. `--db-path ./pos.db --profile training`
+#[derive(Debug, Default, Clone)]
+pub struct CliOverrides {
+    pub database_path: Option<PathBuf>,
+    pub profile: Option<String>,
+}
+
+impl CliOverrides {
+    pub fn from_args<I: IntoIterator<Item = String>>(args: I) -> Self {
+        let mut overrides = CliOverrides::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+
+            match flag.as_str() {
+                "--db-path" => {
+                    overrides.database_path = inline_value.or_else(|| args.next()).map(PathBuf::from);
+                }
+                "--profile" => {
+                    overrides.profile = inline_value.or_else(|| args.next());
+                }
+                _ => {}
+            }
+        }
+
+        overrides
+    }
+}
+
+fn project_dirs() -> Result<ProjectDirs, String> {
+    ProjectDirs::from("com", "glasspos", "pos")
+        .ok_or_else(|| "Could not determine the application data directory".to_string())
+}
+
+pub fn data_dir() -> Result<PathBuf, String> {
+    Ok(project_dirs()?.data_dir().to_path_buf())
+}
+
+pub fn config_file_path() -> Result<PathBuf, String> {
+    Ok(project_dirs()?.config_dir().join("config.json"))
+}
+
+pub fn load_config() -> Result<AppConfig, String> {
+    let path = match config_file_path() {
+        Ok(path) => path,
+        // No config directory means there is nothing to read, not a failure
+        Err(_) => return Ok(AppConfig::default()),
+    };
+
+    if !path.exists() {
+        return Ok(AppConfig::default());
+    }
+
+    let contents = std::fs::read_to_string(&path)
+        .map_err(|e| format!("Failed to read config file {:?}: {}", path, e))?;
+
+    serde_json::from_str(&contents)
+        .map_err(|e| format!("Invalid config file {:?}: {}", path, e))
+}
+
+fn is_valid_profile_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+impl AppConfig {
+    // Resolve a profile name to its database file. The default profile keeps
+    // the historical location so existing installs find their data.
+    pub fn resolve_profile(&self, name: &str) -> Result<StoreProfile, String> {
+        if !is_valid_profile_name(name) {
+            return Err(format!("Invalid profile name: {}", name));
+        }
+
+        let profile_config = self.profiles.get(name);
+        let label = profile_config
+            .and_then(|p| p.label.clone())
+            .unwrap_or_else(|| name.to_string());
+
+        let database_path = match profile_config.and_then(|p| p.database_path.clone()) {
+            Some(path) => path,
+            None if name == DEFAULT_PROFILE => match &self.database_path {
+                Some(path) => path.clone(),
+                None => data_dir()?.join("glasspos.db"),
+            },
+            None => data_dir()?.join("profiles").join(name).join("glasspos.db"),
+        };
+
+        Ok(StoreProfile {
+            name: name.to_string(),
+            label,
+            database_path,
+            training: false,
+        })
+    }
+
+    pub fn parked_cart_expiry(&self) -> chrono::Duration {
+        let minutes = self
+            .parked_cart_expiry_minutes
+            .filter(|m| *m > 0)
+            .unwrap_or(DEFAULT_PARKED_CART_EXPIRY_MINUTES);
+        chrono::Duration::minutes(minutes)
+    }
+
+    pub fn tax_rounding(&self) -> Result<Rounding, String> {
+        Rounding::parse(self.tax_rounding.as_deref().unwrap_or("half_up"))
+    }
+
+    pub fn low_stock_alerts(&self) -> bool {
+        self.low_stock_alerts.unwrap_or(true)
+    }
+
+    pub fn minor_units_per_unit(&self) -> i64 {
+        self.minor_units_per_unit
+            .filter(|m| *m > 0)
+            .unwrap_or(DEFAULT_MINOR_UNITS_PER_UNIT)
+    }
+
+    // The profiles list_profiles offers: the default and any configured one
+    pub fn has_profile(&self, name: &str) -> bool {
+        name == DEFAULT_PROFILE || self.profiles.contains_key(name)
+    }
+
+    pub fn list_profiles(&self) -> Result<Vec<StoreProfile>, String> {
+        let mut names: Vec<String> = self.profiles.keys().cloned().collect();
+        if !self.profiles.contains_key(DEFAULT_PROFILE) {
+            names.insert(0, DEFAULT_PROFILE.to_string());
+        }
+
+        names.iter().map(|name| self.resolve_profile(name)).collect()
+    }
+}
+
+// The sandbox copy of a live profile lives next to it, e.g. glasspos.db
+// trains on glasspos-training.db.
+pub fn training_profile(live: &StoreProfile) -> StoreProfile {
+    let stem = live
+        .database_path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_else(|| "glasspos".to_string());
+
+    StoreProfile {
+        name: format!("{}-training", live.name),
+        label: format!("{} (Training)", live.label),
+        database_path: live.database_path.with_file_name(format!("{}-training.db", stem)),
+        training: true,
+    }
+}
+
+// Work out which profile to open at startup.
+// Precedence: CLI argument, then environment variable, then config file.
+pub fn startup_profile(config: &AppConfig, cli: &CliOverrides) -> Result<StoreProfile, String> {
+    let name = cli
+        .profile
+        .clone()
+        .or_else(|| std::env::var(PROFILE_ENV).ok().filter(|v| !v.is_empty()))
+        .or_else(|| config.default_profile.clone())
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+
+    let mut profile = config.resolve_profile(&name)?;
+
+    let path_override = cli
+        .database_path
+        .clone()
+        .or_else(|| std::env::var(DB_PATH_ENV).ok().filter(|v| !v.is_empty()).map(PathBuf::from));
+
+    if let Some(path) = path_override {
+        profile.database_path = path;
+    }
+
+    Ok(profile)
+}
@@ -0,0 +1,395 @@
+use crate::inventory::{self, StockMovement};
+use crate::session;
+use crate::units;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+pub const FIFO: &str = "fifo";
+pub const AVERAGE: &str = "average";
+
+// Every stock movement is costed once, in ledger order: units coming in open
+// a cost layer, units going out consume layers oldest first (FIFO) or at the
+// running average (all open layers folded into one). movement_costs keeps the
+// signed value of each movement, so the stock value on any date is a sum.
+#[derive(Debug, Serialize)]
+pub struct CostingSettings {
+    pub method: String,
+    pub updated_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ValuationLine {
+    pub product_id: String,
+    pub name: String,
+    pub sku: String,
+    pub quantity: i64,
+    pub quantity_decimals: i64,
+    pub value_minor: i64,
+    // Per whole unit
+    pub unit_cost_minor: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InventoryValuation {
+    pub as_of: Option<String>,
+    pub method: String,
+    pub total_value_minor: i64,
+    pub lines: Vec<ValuationLine>,
+}
+
+struct Uncosted {
+    id: String,
+    movement_type: String,
+    quantity: i64,
+    stock_after: i64,
+    reference_id: Option<String>,
+    created_at: String,
+}
+
+pub fn install_defaults(conn: &Connection) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR IGNORE INTO costing_settings (id, method, updated_at) VALUES (1, ?, NULL)",
+        params![FIFO],
+    )
+    .map_err(|e| format!("Failed to create costing settings: {}", e))?;
+
+    let costed = cost_pending(conn)?;
+    if costed > 0 {
+        println!("💲 Stock movements costed for {} product(s)", costed);
+    }
+    Ok(())
+}
+
+// Stock from before costing, and movements written by triggers (opening
+// stock, direct updates), are costed at the products' current cost
+fn cost_pending(conn: &Connection) -> Result<usize, String> {
+    let product_ids: Vec<String> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT DISTINCT m.product_id FROM inventory_movements m
+                 WHERE NOT EXISTS (SELECT 1 FROM movement_costs c WHERE c.movement_id = m.id)",
+            )
+            .map_err(|e| e.to_string())?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        ids
+    };
+    for product_id in &product_ids {
+        cost_movements(conn, product_id)?;
+    }
+    Ok(product_ids.len())
+}
+
+pub fn get_settings(conn: &Connection) -> Result<CostingSettings, String> {
+    conn.query_row("SELECT method, updated_at FROM costing_settings WHERE id = 1", [], |row| {
+        Ok(CostingSettings {
+            method: row.get(0)?,
+            updated_at: row.get(1)?,
+        })
+    })
+    .map_err(|e| format!("Failed to load costing settings: {}", e))
+}
+
+// Takes effect from the next movement; what is already costed stays as it was
+pub fn set_method(conn: &Connection, method: &str) -> Result<CostingSettings, String> {
+    session::require_role(&["admin", "manager"])?;
+    if method != FIFO && method != AVERAGE {
+        return Err(format!("Unknown costing method: {}", method));
+    }
+    conn.execute(
+        "UPDATE costing_settings SET method = ?, updated_at = ? WHERE id = 1",
+        params![method, chrono::Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| format!("Failed to save costing method: {}", e))?;
+    get_settings(conn)
+}
+
+// part/whole of a value, rounded half away from zero like line totals
+fn share(value: i64, part: i64, whole: i64) -> i64 {
+    if whole == 0 {
+        return 0;
+    }
+    let (value, whole) = if whole < 0 { (-(value as i128), -(whole as i128)) } else { (value as i128, whole as i128) };
+    let scaled = value * part as i128;
+    let rounded = if scaled >= 0 { (scaled + whole / 2) / whole } else { (scaled - whole / 2) / whole };
+    rounded as i64
+}
+
+// Cost every movement of a product that has not been costed yet, in order
+pub fn cost_movements(conn: &Connection, product_id: &str) -> Result<(), String> {
+    let uncosted: Vec<Uncosted> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT m.id, m.movement_type, m.quantity, m.stock_after, m.reference_id, m.created_at
+                 FROM inventory_movements m
+                 WHERE m.product_id = ? AND NOT EXISTS (SELECT 1 FROM movement_costs c WHERE c.movement_id = m.id)
+                 ORDER BY m.rowid",
+            )
+            .map_err(|e| e.to_string())?;
+        let uncosted = stmt
+            .query_map(params![product_id], |row| {
+                Ok(Uncosted {
+                    id: row.get(0)?,
+                    movement_type: row.get(1)?,
+                    quantity: row.get(2)?,
+                    stock_after: row.get(3)?,
+                    reference_id: row.get(4)?,
+                    created_at: row.get(5)?,
+                })
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        uncosted
+    };
+    if uncosted.is_empty() {
+        return Ok(());
+    }
+
+    let method = get_settings(conn)?.method;
+    let (cost_minor, decimals): (i64, i64) = conn
+        .query_row("SELECT cost_minor, quantity_decimals FROM products WHERE id = ?", params![product_id], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .map_err(|e| e.to_string())?;
+
+    for movement in &uncosted {
+        let value = if movement.quantity > 0 {
+            let value = inbound_value(conn, product_id, movement, cost_minor, decimals)?;
+            // Units that only make up for stock that ran negative open no layer
+            let layered = movement.quantity.min(movement.stock_after.max(0));
+            if layered > 0 {
+                conn.execute(
+                    "INSERT INTO cost_layers (id, product_id, movement_id, quantity, remaining, remaining_cost_minor, created_at)
+                     VALUES (lower(hex(randomblob(16))), ?, ?, ?, ?, ?, ?)",
+                    params![
+                        product_id,
+                        movement.id,
+                        movement.quantity,
+                        layered,
+                        share(value, layered, movement.quantity),
+                        movement.created_at
+                    ],
+                )
+                .map_err(|e| format!("Failed to open cost layer: {}", e))?;
+            }
+            if method == AVERAGE {
+                fold_layers(conn, product_id)?;
+            }
+            value
+        } else if movement.quantity < 0 {
+            if method == AVERAGE {
+                fold_layers(conn, product_id)?;
+            }
+            -consume_layers(conn, product_id, -movement.quantity, cost_minor, decimals)?
+        } else {
+            0
+        };
+
+        conn.execute(
+            "INSERT INTO movement_costs (movement_id, product_id, cost_minor, created_at) VALUES (?, ?, ?, ?)",
+            params![movement.id, product_id, value, movement.created_at],
+        )
+        .map_err(|e| format!("Failed to record movement cost: {}", e))?;
+    }
+    Ok(())
+}
+
+// What units coming in are worth: returns and voids at what they left at,
+// assembled kits at what went into them, anything else at the product's cost
+fn inbound_value(conn: &Connection, product_id: &str, movement: &Uncosted, cost_minor: i64, decimals: i64) -> Result<i64, String> {
+    let sale_id: Option<String> = match movement.movement_type.as_str() {
+        inventory::VOID => movement.reference_id.clone(),
+        inventory::RETURN => conn
+            .query_row("SELECT sale_id FROM returns WHERE id = ?", params![movement.reference_id], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?,
+        _ => None,
+    };
+    if let Some(sale_id) = sale_id {
+        let (sold_cost, sold): (i64, i64) = conn
+            .query_row(
+                "SELECT COALESCE(SUM(c.cost_minor), 0), COALESCE(SUM(m.quantity), 0)
+                 FROM inventory_movements m JOIN movement_costs c ON c.movement_id = m.id
+                 WHERE m.product_id = ? AND m.reference_id = ? AND m.movement_type = ?",
+                params![product_id, sale_id, inventory::SALE],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|e| e.to_string())?;
+        if sold != 0 {
+            return Ok(share(sold_cost, movement.quantity, sold));
+        }
+        // A kit sold straight from its components comes back at their cost
+        let (line_cost, line_quantity): (i64, i64) = conn
+            .query_row(
+                "SELECT COALESCE(SUM(cost_minor), 0), COALESCE(SUM(quantity), 0) FROM sale_items
+                 WHERE sale_id = ? AND product_id = ? AND cost_minor IS NOT NULL",
+                params![sale_id, product_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|e| e.to_string())?;
+        if line_quantity != 0 {
+            return Ok(share(line_cost, movement.quantity, line_quantity));
+        }
+    }
+
+    if movement.movement_type == inventory::ASSEMBLY {
+        let used: i64 = conn
+            .query_row(
+                "SELECT COALESCE(SUM(c.cost_minor), 0)
+                 FROM inventory_movements m JOIN movement_costs c ON c.movement_id = m.id
+                 WHERE m.reference_id = ? AND m.movement_type = ? AND m.product_id <> ?",
+                params![movement.reference_id, inventory::ASSEMBLY, product_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if used < 0 {
+            return Ok(-used);
+        }
+    }
+
+    Ok(units::line_total(cost_minor, movement.quantity, decimals))
+}
+
+// Average costing keeps a single open layer holding all units on hand
+fn fold_layers(conn: &Connection, product_id: &str) -> Result<(), String> {
+    let open: Vec<(String, i64, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, remaining, remaining_cost_minor FROM cost_layers
+                 WHERE product_id = ? AND remaining > 0 ORDER BY rowid DESC",
+            )
+            .map_err(|e| e.to_string())?;
+        let open = stmt
+            .query_map(params![product_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        open
+    };
+    if open.len() < 2 {
+        return Ok(());
+    }
+
+    let remaining: i64 = open.iter().map(|l| l.1).sum();
+    let remaining_cost: i64 = open.iter().map(|l| l.2).sum();
+    conn.execute(
+        "UPDATE cost_layers SET remaining = 0, remaining_cost_minor = 0 WHERE product_id = ? AND remaining > 0 AND id <> ?",
+        params![product_id, open[0].0],
+    )
+    .map_err(|e| format!("Failed to fold cost layers: {}", e))?;
+    conn.execute(
+        "UPDATE cost_layers SET remaining = ?, remaining_cost_minor = ? WHERE id = ?",
+        params![remaining, remaining_cost, open[0].0],
+    )
+    .map_err(|e| format!("Failed to fold cost layers: {}", e))?;
+    Ok(())
+}
+
+// Take units out of the oldest layers; units beyond them are costed at the
+// product's cost
+fn consume_layers(conn: &Connection, product_id: &str, quantity: i64, cost_minor: i64, decimals: i64) -> Result<i64, String> {
+    let open: Vec<(String, i64, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, remaining, remaining_cost_minor FROM cost_layers
+                 WHERE product_id = ? AND remaining > 0 ORDER BY rowid",
+            )
+            .map_err(|e| e.to_string())?;
+        let open = stmt
+            .query_map(params![product_id], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        open
+    };
+
+    let mut left = quantity;
+    let mut value = 0;
+    for (layer_id, remaining, remaining_cost) in open {
+        if left == 0 {
+            break;
+        }
+        let taken = left.min(remaining);
+        let taken_cost = if taken == remaining { remaining_cost } else { share(remaining_cost, taken, remaining) };
+        conn.execute(
+            "UPDATE cost_layers SET remaining = remaining - ?, remaining_cost_minor = remaining_cost_minor - ? WHERE id = ?",
+            params![taken, taken_cost, layer_id],
+        )
+        .map_err(|e| format!("Failed to consume cost layer: {}", e))?;
+        value += taken_cost;
+        left -= taken;
+    }
+    Ok(value + units::line_total(cost_minor, left, decimals))
+}
+
+// Signed value of a set of movements, e.g. what a sale line took out of stock
+pub fn cost_of(conn: &Connection, movements: &[StockMovement]) -> Result<i64, String> {
+    let mut total = 0;
+    for movement in movements {
+        let cost: i64 = conn
+            .query_row(
+                "SELECT cost_minor FROM movement_costs WHERE movement_id = ?",
+                params![movement.id],
+                |row| row.get(0),
+            )
+            .map_err(|e| format!("Movement {} has no cost: {}", movement.id, e))?;
+        total += cost;
+    }
+    Ok(total)
+}
+
+// Stock on hand and its value just before as_of (RFC 3339), or now
+pub fn valuation(conn: &Connection, as_of: Option<&str>) -> Result<InventoryValuation, String> {
+    // Movements are stamped in UTC, so as_of is brought to UTC in the same
+    // form before the text comparison
+    let as_of = as_of
+        .map(|value| {
+            chrono::DateTime::parse_from_rfc3339(value)
+                .map(|t| t.with_timezone(&chrono::Utc).to_rfc3339())
+                .map_err(|_| format!("Invalid valuation date: {}", value))
+        })
+        .transpose()?;
+
+    cost_pending(conn)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT p.id, p.name, p.sku, p.quantity_decimals, SUM(m.quantity), COALESCE(SUM(c.cost_minor), 0)
+             FROM inventory_movements m
+             JOIN products p ON p.id = m.product_id
+             LEFT JOIN movement_costs c ON c.movement_id = m.id
+             WHERE ?1 IS NULL OR m.created_at < ?1
+             GROUP BY p.id
+             HAVING SUM(m.quantity) <> 0 OR COALESCE(SUM(c.cost_minor), 0) <> 0
+             ORDER BY p.name",
+        )
+        .map_err(|e| e.to_string())?;
+    let lines = stmt
+        .query_map(params![as_of], |row| {
+            let quantity_decimals: i64 = row.get(3)?;
+            let quantity: i64 = row.get(4)?;
+            let value_minor: i64 = row.get(5)?;
+            Ok(ValuationLine {
+                product_id: row.get(0)?,
+                name: row.get(1)?,
+                sku: row.get(2)?,
+                quantity,
+                quantity_decimals,
+                value_minor,
+                unit_cost_minor: share(value_minor, units::scale(quantity_decimals), quantity),
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(InventoryValuation {
+        as_of,
+        method: get_settings(conn)?.method,
+        total_value_minor: lines.iter().map(|l| l.value_minor).sum(),
+        lines,
+    })
+}
@@ -1,33 +1,81 @@
-use rusqlite::{Connection, Result, ToSql, params};
+use rusqlite::{functions::FunctionFlags, Connection, Result, ToSql, params};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Mutex;
-use once_cell::sync::Lazy;
-use directories::ProjectDirs;
+use once_cell::sync::OnceCell;
 use bcrypt;
+use crate::config::{self, AppConfig, CliOverrides, StoreProfile};
+use crate::{audit, categories, costing, inventory, ledger, loyalty, returns, search, session, stored_value, tax, units, variants};
 
 pub struct Database {
     conn: Mutex<Connection>,
+    profile: Mutex<StoreProfile>,
+    live_profile: Mutex<Option<StoreProfile>>,
+    // As opened at launch, including any --db-path / GLASSPOS_DB_PATH override
+    startup_profile: StoreProfile,
+    config: AppConfig,
 }
 
-static DB: Lazy<Database> = Lazy::new(|| {
-    Database::new().expect("Failed to initialize database")
-});
+static DB: OnceCell<Database> = OnceCell::new();
+static ID_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+// Ids for rows the backend creates itself, e.g. "ret-18c2f4a9b3e10000-0001"
+pub fn generate_id(prefix: &str) -> String {
+    let nanos = chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default();
+    let counter = ID_COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff;
+    format!("{}-{:x}-{:04x}", prefix, nanos, counter)
+}
+
+// SQL functions the schema depends on (triggers call these), so every
+// connection to a store database must register them.
+pub fn register_functions(conn: &Connection) -> Result<(), String> {
+    conn.create_scalar_function("current_user_id", 0, FunctionFlags::SQLITE_UTF8, |_| {
+        Ok(session::current_user_id())
+    })
+    .map_err(|e| format!("Failed to register current_user_id(): {}", e))?;
+    ledger::register_functions(conn)?;
+    search::register_functions(conn)?;
+    units::register_functions(conn)?;
+    Ok(())
+}
+
+// Add a column to an existing table if an older database lacks it
+pub fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<(), String> {
+    let columns = audit::table_columns(conn, table)?;
+    if columns.iter().any(|c| c == column) {
+        return Ok(());
+    }
+
+    conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])
+        .map_err(|e| format!("Failed to add {}.{}: {}", table, column, e))?;
+    Ok(())
+}
+
+fn open_connection(path: &Path) -> Result<Connection, String> {
+    // Create directory if it doesn't exist
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create database directory {:?}: {}", parent, e))?;
+    }
+
+    println!("📁 Database path: {:?}", path);
+
+    let conn = Connection::open(path)
+        .map_err(|e| format!("Failed to open database {:?}: {}", path, e))?;
+    register_functions(&conn)?;
+    create_schema(&conn)?;
+    Ok(conn)
+}
 
 impl Database {
-    pub fn new() -> Result<Self, rusqlite::Error> {
-        // Get app data directory
-        let proj_dirs = ProjectDirs::from("com", "glasspos", "pos")
-            .expect("Failed to get project directories");
-        let app_data_dir = proj_dirs.data_dir();
-        
-        // Create directory if it doesn't exist
-        std::fs::create_dir_all(&app_data_dir).ok();
-        
-        let db_path = app_data_dir.join("glasspos.db");
-        println!("📁 Database path: {:?}", db_path);
-        
-        let conn = Connection::open(db_path)?;
+    pub fn new(config: AppConfig, profile: StoreProfile) -> Result<Self, String> {
+        let conn = open_connection(&profile.database_path)?;
         Ok(Database {
             conn: Mutex::new(conn),
+            profile: Mutex::new(profile.clone()),
+            live_profile: Mutex::new(None),
+            startup_profile: profile,
+            config,
         })
     }
 
@@ -60,10 +108,90 @@ impl Database {
     pub fn get_connection(&self) -> std::sync::MutexGuard<'_, Connection> {
         self.conn.lock().unwrap()
     }
+
+    pub fn active_profile(&self) -> StoreProfile {
+        self.profile.lock().unwrap().clone()
+    }
+
+    pub fn config(&self) -> &AppConfig {
+        &self.config
+    }
+
+    pub fn is_training(&self) -> bool {
+        self.profile.lock().unwrap().training
+    }
+
+    // The profile training mode was entered from, if training is active
+    pub fn live_profile(&self) -> Option<StoreProfile> {
+        self.live_profile.lock().unwrap().clone()
+    }
+
+    // Open another profile's database without making it active, so it can be
+    // checked (e.g. the user's credentials) before switch_to_open swaps it in.
+    // Only the startup profile and configured ones can be opened, so a typo
+    // never creates an empty store.
+    pub fn open_profile(&self, name: &str) -> Result<(StoreProfile, Connection), String> {
+        let profile = if name == self.startup_profile.name {
+            self.startup_profile.clone()
+        } else if self.config.has_profile(name) {
+            self.config.resolve_profile(name)?
+        } else {
+            return Err(format!("Unknown store profile: {}", name));
+        };
+        let conn = open_connection(&profile.database_path)?;
+        Ok((profile, conn))
+    }
+
+    // Close the current store database and open another profile in its place.
+    // The new file is fully initialised before the swap so a failure leaves
+    // the current profile untouched.
+    pub fn switch_to(&self, profile: StoreProfile) -> Result<StoreProfile, String> {
+        if self.active_profile().name == profile.name {
+            return Ok(profile);
+        }
+
+        let new_conn = open_connection(&profile.database_path)?;
+        Ok(self.switch_to_open(profile, new_conn))
+    }
+
+    pub fn switch_to_open(&self, profile: StoreProfile, new_conn: Connection) -> StoreProfile {
+        let mut conn = self.conn.lock().unwrap();
+        let mut active = self.profile.lock().unwrap();
+        let mut live = self.live_profile.lock().unwrap();
+
+        if profile.training {
+            if !active.training {
+                *live = Some(active.clone());
+            }
+        } else {
+            *live = None;
+        }
+
+        *conn = new_conn;
+        *active = profile.clone();
+
+        println!("🔁 Switched to store profile '{}'", profile.name);
+        profile
+    }
 }
 
 pub fn init_database() -> Result<(), String> {
     println!("🗄️ Initializing database...");
+
+    let config = config::load_config()?;
+    let cli = CliOverrides::from_args(std::env::args().skip(1));
+    let profile = config::startup_profile(&config, &cli)?;
+    println!("🏪 Store profile: {}", profile.name);
+
+    let database = Database::new(config, profile)?;
+    DB.set(database)
+        .map_err(|_| "Database already initialized".to_string())?;
+
+    println!("🎉 Database initialization completed!");
+    Ok(())
+}
+
+fn create_schema(conn: &Connection) -> Result<(), String> {
     
     // Create all required tables
     let tables = vec![
@@ -210,33 +338,695 @@ pub fn init_database() -> Result<(), String> {
             FOREIGN KEY (user_id) REFERENCES users(id)
         )
         "#,
+
+        // Payments table (one row per tender on a sale)
+        r#"
+        CREATE TABLE IF NOT EXISTS payments (
+            id TEXT PRIMARY KEY,
+            sale_id TEXT NOT NULL,
+            method TEXT NOT NULL,
+            amount_minor INTEGER NOT NULL,
+            tendered_minor INTEGER NOT NULL,
+            change_minor INTEGER NOT NULL DEFAULT 0,
+            reference TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (sale_id) REFERENCES sales(id)
+        )
+        "#,
+
+        // Parked carts (sales put on hold)
+        r#"
+        CREATE TABLE IF NOT EXISTS parked_carts (
+            id TEXT PRIMARY KEY,
+            cart_number TEXT UNIQUE NOT NULL,
+            customer_id TEXT,
+            user_id TEXT NOT NULL,
+            notes TEXT,
+            reserve_stock INTEGER NOT NULL DEFAULT 0,
+            status TEXT NOT NULL DEFAULT 'parked',
+            expires_at TEXT NOT NULL,
+            recalled_by TEXT,
+            recalled_at TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (customer_id) REFERENCES customers(id),
+            FOREIGN KEY (user_id) REFERENCES users(id),
+            FOREIGN KEY (recalled_by) REFERENCES users(id)
+        )
+        "#,
+
+        r#"
+        CREATE TABLE IF NOT EXISTS parked_cart_items (
+            id TEXT PRIMARY KEY,
+            cart_id TEXT NOT NULL,
+            product_id TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            price_minor INTEGER NOT NULL,
+            FOREIGN KEY (cart_id) REFERENCES parked_carts(id) ON DELETE CASCADE,
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        // Promotions (evaluated in promotions.rs when a cart is priced)
+        r#"
+        CREATE TABLE IF NOT EXISTS promotions (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            scope TEXT NOT NULL,
+            target_id TEXT,
+            percent_bp INTEGER NOT NULL DEFAULT 0,
+            amount_minor INTEGER NOT NULL DEFAULT 0,
+            buy_quantity INTEGER NOT NULL DEFAULT 0,
+            get_quantity INTEGER NOT NULL DEFAULT 0,
+            bundle_quantity INTEGER NOT NULL DEFAULT 0,
+            min_basket_minor INTEGER NOT NULL DEFAULT 0,
+            customer_group TEXT,
+            starts_at TEXT,
+            ends_at TEXT,
+            days_of_week TEXT,
+            start_time TEXT,
+            end_time TEXT,
+            priority INTEGER NOT NULL DEFAULT 0,
+            stackable INTEGER NOT NULL DEFAULT 0,
+            is_active INTEGER NOT NULL DEFAULT 1,
+            created_at TEXT NOT NULL,
+            updated_at TEXT
+        )
+        "#,
+
+        // Promotions applied to each sold line
+        r#"
+        CREATE TABLE IF NOT EXISTS sale_item_promotions (
+            id TEXT PRIMARY KEY,
+            sale_item_id TEXT NOT NULL,
+            promotion_id TEXT NOT NULL,
+            promotion_name TEXT NOT NULL,
+            discount_minor INTEGER NOT NULL,
+            FOREIGN KEY (sale_item_id) REFERENCES sale_items(id),
+            FOREIGN KEY (promotion_id) REFERENCES promotions(id)
+        )
+        "#,
+
+        // Tax classes (standard, zero-rated, exempt)
+        r#"
+        CREATE TABLE IF NOT EXISTS tax_classes (
+            id TEXT PRIMARY KEY,
+            code TEXT UNIQUE NOT NULL,
+            name TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            rate_bp INTEGER NOT NULL DEFAULT 0,
+            is_default INTEGER NOT NULL DEFAULT 0,
+            created_at TEXT NOT NULL,
+            updated_at TEXT
+        )
+        "#,
+
+        // Tax per rate for each sale, as printed on the receipt
+        r#"
+        CREATE TABLE IF NOT EXISTS sale_tax_lines (
+            id TEXT PRIMARY KEY,
+            sale_id TEXT NOT NULL,
+            tax_class_id TEXT NOT NULL,
+            code TEXT NOT NULL,
+            name TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            rate_bp INTEGER NOT NULL,
+            taxable_minor INTEGER NOT NULL,
+            tax_minor INTEGER NOT NULL,
+            FOREIGN KEY (sale_id) REFERENCES sales(id)
+        )
+        "#,
+
+        // Loyalty program settings (a single row)
+        r#"
+        CREATE TABLE IF NOT EXISTS loyalty_program (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            enabled INTEGER NOT NULL DEFAULT 0,
+            points_per_unit INTEGER NOT NULL DEFAULT 1,
+            point_value_minor INTEGER NOT NULL DEFAULT 1,
+            expiry_days INTEGER NOT NULL DEFAULT 365,
+            updated_at TEXT
+        )
+        "#,
+
+        // Loyalty points ledger; a customer's balance is the sum of points.
+        // Sale and return ids are references only so training copies can
+        // drop their sales and keep real balances.
+        r#"
+        CREATE TABLE IF NOT EXISTS loyalty_ledger (
+            id TEXT PRIMARY KEY,
+            customer_id TEXT NOT NULL,
+            entry_type TEXT NOT NULL,
+            points INTEGER NOT NULL,
+            remaining INTEGER NOT NULL DEFAULT 0,
+            sale_id TEXT,
+            return_id TEXT,
+            expires_at TEXT,
+            note TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (customer_id) REFERENCES customers(id)
+        )
+        "#,
+
+        // Gift cards and customer store credit
+        r#"
+        CREATE TABLE IF NOT EXISTS stored_value_accounts (
+            id TEXT PRIMARY KEY,
+            code TEXT UNIQUE NOT NULL,
+            kind TEXT NOT NULL,
+            customer_id TEXT,
+            status TEXT NOT NULL DEFAULT 'active',
+            expires_at TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (customer_id) REFERENCES customers(id)
+        )
+        "#,
+
+        // Append-only movements of stored value; an account's balance is the
+        // sum of amounts. Sale, return and shift ids are references only, as
+        // in the loyalty ledger.
+        r#"
+        CREATE TABLE IF NOT EXISTS stored_value_ledger (
+            id TEXT PRIMARY KEY,
+            account_id TEXT NOT NULL,
+            entry_type TEXT NOT NULL,
+            amount_minor INTEGER NOT NULL,
+            balance_after_minor INTEGER NOT NULL,
+            sale_id TEXT,
+            return_id TEXT,
+            shift_id TEXT,
+            user_id TEXT,
+            payment_method TEXT,
+            note TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (account_id) REFERENCES stored_value_accounts(id)
+        )
+        "#,
+
+        // Customer accounts receivable; the balance owed is the sum of amounts.
+        // Sale and return ids are references only, as in the loyalty ledger.
+        r#"
+        CREATE TABLE IF NOT EXISTS customer_account_entries (
+            id TEXT PRIMARY KEY,
+            customer_id TEXT NOT NULL,
+            entry_type TEXT NOT NULL,
+            amount_minor INTEGER NOT NULL,
+            balance_after_minor INTEGER NOT NULL,
+            sale_id TEXT,
+            return_id TEXT,
+            shift_id TEXT,
+            user_id TEXT,
+            payment_method TEXT,
+            reference TEXT,
+            note TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (customer_id) REFERENCES customers(id)
+        )
+        "#,
+
+        // Every change to sellable stock; products.stock follows the latest
+        // stock_after. Reference ids point at the sale, return or other
+        // document behind the movement.
+        r#"
+        CREATE TABLE IF NOT EXISTS inventory_movements (
+            id TEXT PRIMARY KEY,
+            product_id TEXT NOT NULL,
+            movement_type TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            stock_after INTEGER NOT NULL,
+            reference_id TEXT,
+            user_id TEXT,
+            reason TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        // Purchase orders to suppliers and their lines
+        r#"
+        CREATE TABLE IF NOT EXISTS purchase_orders (
+            id TEXT PRIMARY KEY,
+            po_number TEXT UNIQUE NOT NULL,
+            supplier_id TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'draft',
+            expected_at TEXT,
+            notes TEXT,
+            created_by TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            updated_at TEXT,
+            FOREIGN KEY (supplier_id) REFERENCES suppliers(id),
+            FOREIGN KEY (created_by) REFERENCES users(id)
+        )
+        "#,
+
+        r#"
+        CREATE TABLE IF NOT EXISTS purchase_order_items (
+            id TEXT PRIMARY KEY,
+            order_id TEXT NOT NULL,
+            product_id TEXT NOT NULL,
+            quantity_ordered INTEGER NOT NULL,
+            quantity_received INTEGER NOT NULL DEFAULT 0,
+            unit_cost_minor INTEGER NOT NULL,
+            FOREIGN KEY (order_id) REFERENCES purchase_orders(id),
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        // Deliveries booked against a purchase order
+        r#"
+        CREATE TABLE IF NOT EXISTS goods_receipts (
+            id TEXT PRIMARY KEY,
+            receipt_number TEXT UNIQUE NOT NULL,
+            order_id TEXT NOT NULL,
+            received_by TEXT NOT NULL,
+            notes TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (order_id) REFERENCES purchase_orders(id),
+            FOREIGN KEY (received_by) REFERENCES users(id)
+        )
+        "#,
+
+        r#"
+        CREATE TABLE IF NOT EXISTS goods_receipt_items (
+            id TEXT PRIMARY KEY,
+            receipt_id TEXT NOT NULL,
+            order_item_id TEXT NOT NULL,
+            product_id TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            unit_cost_minor INTEGER NOT NULL,
+            over_quantity INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY (receipt_id) REFERENCES goods_receipts(id),
+            FOREIGN KEY (order_item_id) REFERENCES purchase_order_items(id),
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        // Stock count sessions. Lines freeze the expected quantity and cost
+        // when the session opens; counts are added up per product.
+        r#"
+        CREATE TABLE IF NOT EXISTS stocktakes (
+            id TEXT PRIMARY KEY,
+            stocktake_number TEXT UNIQUE NOT NULL,
+            category_id TEXT,
+            status TEXT NOT NULL DEFAULT 'open',
+            notes TEXT,
+            opened_by TEXT NOT NULL,
+            opened_at TEXT NOT NULL,
+            posted_by TEXT,
+            posted_at TEXT,
+            FOREIGN KEY (category_id) REFERENCES categories(id),
+            FOREIGN KEY (opened_by) REFERENCES users(id)
+        )
+        "#,
+
+        r#"
+        CREATE TABLE IF NOT EXISTS stocktake_lines (
+            id TEXT PRIMARY KEY,
+            stocktake_id TEXT NOT NULL,
+            product_id TEXT NOT NULL,
+            expected_quantity INTEGER NOT NULL,
+            unit_cost_minor INTEGER NOT NULL,
+            approved INTEGER NOT NULL DEFAULT 0,
+            UNIQUE (stocktake_id, product_id),
+            FOREIGN KEY (stocktake_id) REFERENCES stocktakes(id),
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        r#"
+        CREATE TABLE IF NOT EXISTS stocktake_counts (
+            id TEXT PRIMARY KEY,
+            stocktake_id TEXT NOT NULL,
+            product_id TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            source TEXT NOT NULL,
+            barcode TEXT,
+            counted_by TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (stocktake_id) REFERENCES stocktakes(id),
+            FOREIGN KEY (product_id) REFERENCES products(id),
+            FOREIGN KEY (counted_by) REFERENCES users(id)
+        )
+        "#,
+
+        // Lots of lot-tracked products, sold earliest expiry first
+        r#"
+        CREATE TABLE IF NOT EXISTS product_lots (
+            id TEXT PRIMARY KEY,
+            product_id TEXT NOT NULL,
+            lot_number TEXT NOT NULL,
+            expiry_date TEXT,
+            quantity_received INTEGER NOT NULL,
+            quantity_remaining INTEGER NOT NULL CHECK (quantity_remaining >= 0),
+            created_at TEXT NOT NULL,
+            UNIQUE (product_id, lot_number),
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        r#"
+        CREATE TABLE IF NOT EXISTS lot_movements (
+            id TEXT PRIMARY KEY,
+            lot_id TEXT NOT NULL,
+            movement_id TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            FOREIGN KEY (lot_id) REFERENCES product_lots(id),
+            FOREIGN KEY (movement_id) REFERENCES inventory_movements(id)
+        )
+        "#,
+
+        // Attribute values of product variants (e.g. Size = M), one row each
+        r#"
+        CREATE TABLE IF NOT EXISTS product_variant_attributes (
+            id TEXT PRIMARY KEY,
+            product_id TEXT NOT NULL,
+            attribute TEXT NOT NULL,
+            value TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            UNIQUE (product_id, attribute),
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        // Packs a product is also sold in (1 case = 12 pcs), each with its own barcode and price
+        r#"
+        CREATE TABLE IF NOT EXISTS product_units (
+            id TEXT PRIMARY KEY,
+            product_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            quantity INTEGER NOT NULL CHECK (quantity > 0),
+            barcode TEXT UNIQUE,
+            price_minor INTEGER,
+            created_at TEXT NOT NULL,
+            UNIQUE (product_id, name),
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        // Recipes of kit products: what one kit takes out of component stock
+        r#"
+        CREATE TABLE IF NOT EXISTS kit_components (
+            id TEXT PRIMARY KEY,
+            kit_id TEXT NOT NULL,
+            component_id TEXT NOT NULL,
+            quantity INTEGER NOT NULL CHECK (quantity > 0),
+            UNIQUE (kit_id, component_id),
+            FOREIGN KEY (kit_id) REFERENCES products(id),
+            FOREIGN KEY (component_id) REFERENCES products(id)
+        )
+        "#,
+
+        // What each supplier sells us and on what terms; min and max stock
+        // are fixed-point like stock
+        r#"
+        CREATE TABLE IF NOT EXISTS supplier_products (
+            id TEXT PRIMARY KEY,
+            supplier_id TEXT NOT NULL,
+            product_id TEXT NOT NULL,
+            supplier_sku TEXT,
+            lead_time_days INTEGER,
+            min_stock INTEGER,
+            max_stock INTEGER,
+            order_multiple INTEGER NOT NULL DEFAULT 1 CHECK (order_multiple > 0),
+            unit_cost_minor INTEGER,
+            created_at TEXT NOT NULL,
+            UNIQUE (supplier_id, product_id),
+            FOREIGN KEY (supplier_id) REFERENCES suppliers(id),
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        // Costing method for stock valuation (a single row)
+        r#"
+        CREATE TABLE IF NOT EXISTS costing_settings (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            method TEXT NOT NULL DEFAULT 'fifo',
+            updated_at TEXT
+        )
+        "#,
+
+        // Units received at one cost and not yet sold or written off
+        r#"
+        CREATE TABLE IF NOT EXISTS cost_layers (
+            id TEXT PRIMARY KEY,
+            product_id TEXT NOT NULL,
+            movement_id TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            remaining INTEGER NOT NULL CHECK (remaining >= 0),
+            remaining_cost_minor INTEGER NOT NULL,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (product_id) REFERENCES products(id),
+            FOREIGN KEY (movement_id) REFERENCES inventory_movements(id)
+        )
+        "#,
+
+        // Signed value of each stock movement, fixed when it is recorded
+        r#"
+        CREATE TABLE IF NOT EXISTS movement_costs (
+            movement_id TEXT PRIMARY KEY,
+            product_id TEXT NOT NULL,
+            cost_minor INTEGER NOT NULL,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (movement_id) REFERENCES inventory_movements(id),
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        // Product barcodes table (any number of codes per product)
+        r#"
+        CREATE TABLE IF NOT EXISTS product_barcodes (
+            id TEXT PRIMARY KEY,
+            product_id TEXT NOT NULL,
+            barcode TEXT UNIQUE NOT NULL,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (product_id) REFERENCES products(id) ON DELETE CASCADE
+        )
+        "#,
+        "CREATE INDEX IF NOT EXISTS idx_product_barcodes_product ON product_barcodes(product_id)",
+
+        // Returns table (refunds against an original sale)
+        r#"
+        CREATE TABLE IF NOT EXISTS returns (
+            id TEXT PRIMARY KEY,
+            return_number TEXT UNIQUE NOT NULL,
+            sale_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            shift_id TEXT,
+            refund_method TEXT NOT NULL,
+            refund_minor INTEGER NOT NULL DEFAULT 0,
+            reason TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (sale_id) REFERENCES sales(id),
+            FOREIGN KEY (user_id) REFERENCES users(id),
+            FOREIGN KEY (shift_id) REFERENCES shifts(id)
+        )
+        "#,
+
+        // Return items table
+        r#"
+        CREATE TABLE IF NOT EXISTS return_items (
+            id TEXT PRIMARY KEY,
+            return_id TEXT NOT NULL,
+            sale_item_id TEXT NOT NULL,
+            product_id TEXT NOT NULL,
+            quantity INTEGER NOT NULL,
+            amount_minor INTEGER NOT NULL,
+            damaged INTEGER NOT NULL DEFAULT 0,
+            FOREIGN KEY (return_id) REFERENCES returns(id),
+            FOREIGN KEY (sale_item_id) REFERENCES sale_items(id),
+            FOREIGN KEY (product_id) REFERENCES products(id)
+        )
+        "#,
+
+        // Return refunds table (the money a return gave back, per tender)
+        r#"
+        CREATE TABLE IF NOT EXISTS return_refunds (
+            id TEXT PRIMARY KEY,
+            return_id TEXT NOT NULL,
+            payment_id TEXT,
+            method TEXT NOT NULL,
+            amount_minor INTEGER NOT NULL,
+            reference TEXT,
+            created_at TEXT NOT NULL,
+            FOREIGN KEY (return_id) REFERENCES returns(id),
+            FOREIGN KEY (payment_id) REFERENCES payments(id)
+        )
+        "#,
+        "CREATE INDEX IF NOT EXISTS idx_returns_sale ON returns(sale_id)",
+        "CREATE INDEX IF NOT EXISTS idx_return_refunds_return ON return_refunds(return_id)",
+        "CREATE INDEX IF NOT EXISTS idx_return_refunds_payment ON return_refunds(payment_id)",
+        "CREATE INDEX IF NOT EXISTS idx_return_items_sale_item ON return_items(sale_item_id)",
+
+        // Audit log table (written by triggers, see audit.rs)
+        r#"
+        CREATE TABLE IF NOT EXISTS audit_log (
+            id TEXT PRIMARY KEY,
+            table_name TEXT NOT NULL,
+            operation TEXT NOT NULL CHECK (operation IN ('INSERT', 'UPDATE', 'DELETE')),
+            record_id TEXT NOT NULL,
+            user_id TEXT,
+            old_data TEXT,
+            new_data TEXT,
+            timestamp TEXT NOT NULL
+        )
+        "#,
+        "CREATE INDEX IF NOT EXISTS idx_audit_log_table ON audit_log(table_name, record_id)",
+        "CREATE INDEX IF NOT EXISTS idx_audit_log_timestamp ON audit_log(timestamp)",
+        "CREATE INDEX IF NOT EXISTS idx_audit_log_user ON audit_log(user_id)",
     ];
 
     for (i, table_sql) in tables.iter().enumerate() {
-        DB.execute(table_sql, &[])
+        conn.execute(table_sql, [])
             .map_err(|e| format!("Failed to create table {}: {}", i + 1, e))?;
     }
     
+    // Columns added after the first release
+    let columns = [
+        ("sales", "chain_seq", "INTEGER"),
+        ("sales", "prev_hash", "TEXT"),
+        ("sales", "hash", "TEXT"),
+        ("sale_items", "hash", "TEXT"),
+        ("audit_log", "chain_seq", "INTEGER"),
+        ("audit_log", "prev_hash", "TEXT"),
+        ("audit_log", "hash", "TEXT"),
+        ("products", "barcode", "TEXT"),
+        ("products", "damaged_stock", "INTEGER NOT NULL DEFAULT 0"),
+        ("customers", "store_credit_minor", "INTEGER NOT NULL DEFAULT 0"),
+        ("shifts", "total_refunds_minor", "INTEGER NOT NULL DEFAULT 0"),
+        ("sales", "shift_id", "TEXT REFERENCES shifts(id)"),
+        ("sales", "void_reason", "TEXT"),
+        ("sales", "void_note", "TEXT"),
+        ("sales", "voided_by", "TEXT REFERENCES users(id)"),
+        ("sales", "voided_at", "TEXT"),
+        ("sale_items", "discount_minor", "INTEGER NOT NULL DEFAULT 0"),
+        ("customers", "customer_group", "TEXT"),
+        ("products", "tax_class_id", "TEXT REFERENCES tax_classes(id)"),
+        ("products", "price_includes_tax", "INTEGER NOT NULL DEFAULT 1"),
+        ("sale_items", "tax_class_id", "TEXT"),
+        ("sale_items", "tax_rate_bp", "INTEGER NOT NULL DEFAULT 0"),
+        ("sale_items", "tax_minor", "INTEGER NOT NULL DEFAULT 0"),
+        ("sale_items", "paid_minor", "INTEGER"),
+        ("categories", "loyalty_multiplier_bp", "INTEGER NOT NULL DEFAULT 10000"),
+        ("returns", "loyalty_refund_minor", "INTEGER NOT NULL DEFAULT 0"),
+        ("returns", "points_reversed", "INTEGER NOT NULL DEFAULT 0"),
+        ("returns", "points_restored", "INTEGER NOT NULL DEFAULT 0"),
+        ("customers", "credit_limit_minor", "INTEGER NOT NULL DEFAULT 0"),
+        ("customers", "account_balance_minor", "INTEGER NOT NULL DEFAULT 0"),
+        ("expenses", "purchase_order_id", "TEXT REFERENCES purchase_orders(id)"),
+        ("expenses", "due_date", "TEXT"),
+        ("expenses", "payment_status", "TEXT NOT NULL DEFAULT 'paid'"),
+        ("expenses", "paid_at", "TEXT"),
+        ("products", "track_lots", "INTEGER NOT NULL DEFAULT 0"),
+        ("products", "expiry_warning_days", "INTEGER NOT NULL DEFAULT 7"),
+        ("products", "parent_id", "TEXT REFERENCES products(id)"),
+        ("products", "variant_label", "TEXT"),
+        ("products", "price_override_minor", "INTEGER"),
+        ("products", "unit", "TEXT NOT NULL DEFAULT 'pcs'"),
+        ("products", "quantity_decimals", "INTEGER NOT NULL DEFAULT 0"),
+        ("sale_items", "unit_id", "TEXT"),
+        ("sale_items", "unit_name", "TEXT"),
+        ("sale_items", "unit_quantity", "INTEGER"),
+        ("sale_items", "quantity_decimals", "INTEGER NOT NULL DEFAULT 0"),
+        ("parked_cart_items", "unit_id", "TEXT"),
+        ("sale_items", "cost_minor", "INTEGER"),
+        ("suppliers", "lead_time_days", "INTEGER NOT NULL DEFAULT 7"),
+        ("categories", "parent_id", "TEXT"),
+        ("categories", "sort_order", "INTEGER NOT NULL DEFAULT 0"),
+        ("categories", "is_active", "INTEGER NOT NULL DEFAULT 1"),
+        ("categories", "tax_class_id", "TEXT"),
+        ("categories", "low_stock_threshold", "INTEGER"),
+        ("categories", "promotions_allowed", "INTEGER"),
+        ("products", "promotions_allowed", "INTEGER"),
+        ("products", "own_low_stock_threshold", "INTEGER NOT NULL DEFAULT 0"),
+    ];
+    let thresholds_inherited = audit::table_columns(conn, "products")?
+        .iter()
+        .any(|c| c == "own_low_stock_threshold");
+    for (table, column, definition) in columns {
+        ensure_column(conn, table, column, definition)?;
+    }
+    // Products from before categories had thresholds keep their own
+    if !thresholds_inherited {
+        conn.execute("UPDATE products SET own_low_stock_threshold = 1", [])
+            .map_err(|e| format!("Failed to keep product low-stock thresholds: {}", e))?;
+    }
+
+    let indexes = [
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_sales_chain_seq ON sales(chain_seq)",
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_audit_log_chain_seq ON audit_log(chain_seq)",
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_products_barcode ON products(barcode) WHERE barcode IS NOT NULL",
+        "CREATE INDEX IF NOT EXISTS idx_loyalty_ledger_customer ON loyalty_ledger(customer_id)",
+        "CREATE INDEX IF NOT EXISTS idx_stored_value_accounts_customer ON stored_value_accounts(customer_id)",
+        "CREATE INDEX IF NOT EXISTS idx_stored_value_ledger_account ON stored_value_ledger(account_id)",
+        "CREATE INDEX IF NOT EXISTS idx_stored_value_ledger_sale ON stored_value_ledger(sale_id)",
+        "CREATE INDEX IF NOT EXISTS idx_customer_account_entries_customer ON customer_account_entries(customer_id)",
+        "CREATE INDEX IF NOT EXISTS idx_customer_account_entries_sale ON customer_account_entries(sale_id)",
+        "CREATE INDEX IF NOT EXISTS idx_inventory_movements_product ON inventory_movements(product_id)",
+        "CREATE INDEX IF NOT EXISTS idx_inventory_movements_type ON inventory_movements(movement_type)",
+        "CREATE INDEX IF NOT EXISTS idx_inventory_movements_date ON inventory_movements(created_at)",
+        "CREATE INDEX IF NOT EXISTS idx_purchase_orders_supplier ON purchase_orders(supplier_id)",
+        "CREATE INDEX IF NOT EXISTS idx_purchase_order_items_order ON purchase_order_items(order_id)",
+        "CREATE INDEX IF NOT EXISTS idx_goods_receipts_order ON goods_receipts(order_id)",
+        "CREATE INDEX IF NOT EXISTS idx_expenses_purchase_order ON expenses(purchase_order_id)",
+        "CREATE INDEX IF NOT EXISTS idx_stocktake_counts_line ON stocktake_counts(stocktake_id, product_id)",
+        "CREATE INDEX IF NOT EXISTS idx_product_lots_expiry ON product_lots(product_id, expiry_date)",
+        "CREATE INDEX IF NOT EXISTS idx_lot_movements_lot ON lot_movements(lot_id)",
+        "CREATE INDEX IF NOT EXISTS idx_lot_movements_movement ON lot_movements(movement_id)",
+        "CREATE INDEX IF NOT EXISTS idx_products_parent ON products(parent_id)",
+        "CREATE INDEX IF NOT EXISTS idx_product_units_product ON product_units(product_id)",
+        "CREATE INDEX IF NOT EXISTS idx_kit_components_component ON kit_components(component_id)",
+        "CREATE INDEX IF NOT EXISTS idx_inventory_movements_reference ON inventory_movements(reference_id)",
+        "CREATE INDEX IF NOT EXISTS idx_product_variant_attributes_value ON product_variant_attributes(attribute, value)",
+        "CREATE INDEX IF NOT EXISTS idx_cost_layers_open ON cost_layers(product_id, remaining)",
+        "CREATE INDEX IF NOT EXISTS idx_supplier_products_product ON supplier_products(product_id)",
+        "CREATE INDEX IF NOT EXISTS idx_categories_parent ON categories(parent_id)",
+    ];
+    for index_sql in indexes {
+        conn.execute(index_sql, [])
+            .map_err(|e| format!("Failed to create index: {}", e))?;
+    }
+
+    // Reports read reportable_sales so voided sales never count; the audit
+    // view keeps them visible
+    let views = [
+        "CREATE VIEW IF NOT EXISTS reportable_sales AS SELECT * FROM sales WHERE status <> 'voided'",
+        "CREATE VIEW IF NOT EXISTS voided_sales AS SELECT * FROM sales WHERE status = 'voided'",
+    ];
+    for view_sql in views {
+        conn.execute(view_sql, [])
+            .map_err(|e| format!("Failed to create view: {}", e))?;
+    }
+
     println!("✅ Database tables created successfully");
+
+    audit::install_triggers(conn)?;
+    ledger::install_triggers(conn)?;
+    ledger::seal_existing(conn)?;
+    search::install(conn)?;
+    categories::install(conn)?;
+    tax::install_defaults(conn)?;
+    loyalty::install_defaults(conn)?;
+    stored_value::install(conn)?;
+    returns::install(conn)?;
+    inventory::install(conn)?;
+    variants::install(conn)?;
+    costing::install_defaults(conn)?;
     
     // Initialize default data
-    match insert_default_admin() {
+    match insert_default_admin(conn) {
         Ok(_) => println!("👤 Default admin user initialization successful"),
         Err(e) => eprintln!("⚠️ Failed to create default admin: {}", e),
     }
     
-    match insert_default_category() {
+    match insert_default_category(conn) {
         Ok(_) => println!("📂 Default category initialization successful"),
         Err(e) => eprintln!("⚠️ Failed to create default category: {}", e),
     }
     
-    println!("🎉 Database initialization completed!");
     Ok(())
 }
 
-fn insert_default_admin() -> Result<(), String> {
-    let conn = DB.get_connection();
-    
+fn insert_default_admin(conn: &Connection) -> Result<(), String> {
     // Check if admin user already exists
     match conn.query_row(
         "SELECT id FROM users WHERE username = ?",
@@ -284,9 +1074,8 @@ fn insert_default_admin() -> Result<(), String> {
     Ok(())
 }
 
-fn insert_default_category() -> Result<(), String> {
+fn insert_default_category(conn: &Connection) -> Result<(), String> {
     // Check if default category already exists
-    let conn = DB.get_connection();
     let existing_category: Result<String, _> = conn.query_row(
         "SELECT id FROM categories WHERE name = 'General'",
         params![],
@@ -304,17 +1093,12 @@ fn insert_default_category() -> Result<(), String> {
     let description = "Default category for products";
     let created_at = chrono::Utc::now().to_rfc3339();
     
-    DB.execute(
+    conn.execute(
         r#"
         INSERT INTO categories (id, name, description, created_at)
         VALUES (?, ?, ?, ?)
         "#,
-        &[
-            &category_id as &dyn ToSql,
-            &name as &dyn ToSql,
-            &description as &dyn ToSql,
-            &created_at as &dyn ToSql,
-        ]
+        params![category_id, name, description, created_at]
     ).map_err(|e| e.to_string())?;
     
     println!("📁 Default category created");
@@ -323,18 +1107,18 @@ fn insert_default_category() -> Result<(), String> {
 
 pub fn execute_query(sql: &str, params: Vec<String>) -> Result<String, String> {
     let params: Vec<&dyn ToSql> = params.iter().map(|s| s as &dyn ToSql).collect();
-    DB.execute(sql, &params)
+    get_db()?.execute(sql, &params)
         .map(|_| "Query executed successfully".to_string())
         .map_err(|e| e.to_string())
 }
 
 pub fn run_query(sql: &str, params: Vec<String>) -> Result<Vec<Vec<String>>, String> {
     let params: Vec<&dyn ToSql> = params.iter().map(|s| s as &dyn ToSql).collect();
-    DB.query(sql, &params)
+    get_db()?.query(sql, &params)
         .map_err(|e| e.to_string())
 }
 
 // Helper function to get database connection
-pub fn get_db() -> &'static Database {
-    &DB
-}
\ No newline at end of file
+pub fn get_db() -> Result<&'static Database, String> {
+    DB.get().ok_or_else(|| "Database is not initialized".to_string())
+}
@@ -0,0 +1,317 @@
+use crate::costing;
+use crate::database;
+use crate::lots::{self, LotInput};
+use crate::session;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+pub const IN: &str = "in";
+pub const OUT: &str = "out";
+pub const ADJUSTMENT: &str = "adjustment";
+pub const SALE: &str = "sale";
+pub const RETURN: &str = "return";
+pub const EXPIRED: &str = "expired";
+pub const DAMAGED: &str = "damaged";
+pub const VOID: &str = "void";
+// Components built into kits, or kits broken back into components
+pub const ASSEMBLY: &str = "assembly";
+// Stock a product already had when the ledger started tracking it
+pub const OPENING: &str = "opening";
+
+// Movements staff may record by hand; the rest come from sales and returns
+const MANUAL_TYPES: &[&str] = &[IN, OUT, ADJUSTMENT, EXPIRED, DAMAGED];
+
+#[derive(Debug, Serialize)]
+pub struct StockMovement {
+    pub id: String,
+    pub product_id: String,
+    pub movement_type: String,
+    // Signed change to sellable stock
+    pub quantity: i64,
+    pub stock_after: i64,
+    pub reference_id: Option<String>,
+    pub user_id: Option<String>,
+    pub reason: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct StockAdjustment {
+    pub product_id: String,
+    pub movement_type: String,
+    // Units in or out; for an adjustment, the signed correction
+    pub quantity: i64,
+    #[serde(default)]
+    pub reason: Option<String>,
+    // Lot the units go into or come out of, for lot-tracked products
+    #[serde(default)]
+    pub lot: Option<LotInput>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StockDiscrepancy {
+    pub product_id: String,
+    pub product_name: String,
+    pub stock: i64,
+    pub ledger_stock: i64,
+}
+
+// products.stock follows the ledger: each movement sets it to its
+// stock_after. Writes that bypass the ledger (the frontend's own SQL, new
+// products) are recorded as movements so the two never drift apart.
+pub fn install(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        r#"
+        DROP TRIGGER IF EXISTS inventory_movements_no_update;
+        CREATE TRIGGER inventory_movements_no_update
+        BEFORE UPDATE ON inventory_movements
+        WHEN NOT ledger_override()
+        BEGIN
+            SELECT RAISE(ABORT, 'Inventory movements are append-only');
+        END;
+
+        DROP TRIGGER IF EXISTS inventory_movements_no_delete;
+        CREATE TRIGGER inventory_movements_no_delete
+        BEFORE DELETE ON inventory_movements
+        WHEN NOT ledger_override()
+        BEGIN
+            SELECT RAISE(ABORT, 'Inventory movements are append-only');
+        END;
+
+        DROP TRIGGER IF EXISTS inventory_movements_apply;
+        CREATE TRIGGER inventory_movements_apply
+        AFTER INSERT ON inventory_movements
+        BEGIN
+            UPDATE products SET stock = NEW.stock_after, updated_at = NEW.created_at
+            WHERE id = NEW.product_id AND stock <> NEW.stock_after;
+        END;
+
+        DROP TRIGGER IF EXISTS products_stock_opening;
+        CREATE TRIGGER products_stock_opening
+        AFTER INSERT ON products
+        WHEN NEW.stock <> 0
+        BEGIN
+            INSERT INTO inventory_movements (id, product_id, movement_type, quantity, stock_after, user_id, reason, created_at)
+            VALUES (lower(hex(randomblob(16))), NEW.id, 'opening', NEW.stock, NEW.stock, current_user_id(),
+                    'Stock on creation', strftime('%Y-%m-%dT%H:%M:%fZ', 'now'));
+        END;
+
+        DROP TRIGGER IF EXISTS products_stock_direct_update;
+        CREATE TRIGGER products_stock_direct_update
+        AFTER UPDATE OF stock ON products
+        WHEN NEW.stock IS NOT (SELECT stock_after FROM inventory_movements
+                               WHERE product_id = NEW.id ORDER BY rowid DESC LIMIT 1)
+        BEGIN
+            INSERT INTO inventory_movements (id, product_id, movement_type, quantity, stock_after, user_id, reason, created_at)
+            VALUES (lower(hex(randomblob(16))), NEW.id, 'adjustment', NEW.stock - OLD.stock, NEW.stock, current_user_id(),
+                    'Direct stock update', strftime('%Y-%m-%dT%H:%M:%fZ', 'now'));
+        END;
+        "#,
+    )
+    .map_err(|e| format!("Failed to install inventory triggers: {}", e))?;
+
+    // Products that had stock before the ledger existed start from an opening balance
+    let opened = record_opening_stock(conn, "Stock before movement tracking")?;
+    if opened > 0 {
+        println!("📦 Opening stock recorded for {} product(s)", opened);
+    }
+    Ok(())
+}
+
+// An opening movement for every stocked product that has no movements yet
+pub fn record_opening_stock(conn: &Connection, reason: &str) -> Result<usize, String> {
+    conn.execute(
+        "INSERT INTO inventory_movements (id, product_id, movement_type, quantity, stock_after, reason, created_at)
+         SELECT lower(hex(randomblob(16))), id, ?1, stock, stock, ?2, ?3
+         FROM products
+         WHERE stock <> 0 AND id NOT IN (SELECT product_id FROM inventory_movements)",
+        params![OPENING, reason, chrono::Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| format!("Failed to record opening stock: {}", e))
+}
+
+// Record a change to a product's sellable stock and apply it
+pub fn record(
+    conn: &Connection,
+    product_id: &str,
+    movement_type: &str,
+    quantity: i64,
+    reference_id: Option<&str>,
+    reason: Option<&str>,
+) -> Result<StockMovement, String> {
+    apply(conn, product_id, movement_type, quantity, reference_id, reason, None)
+}
+
+// As record, for units going into or out of a named lot of a lot-tracked product
+pub fn record_into_lot(
+    conn: &Connection,
+    product_id: &str,
+    movement_type: &str,
+    quantity: i64,
+    reference_id: Option<&str>,
+    reason: Option<&str>,
+    lot: &LotInput,
+) -> Result<StockMovement, String> {
+    apply(conn, product_id, movement_type, quantity, reference_id, reason, Some(lot))
+}
+
+fn apply(
+    conn: &Connection,
+    product_id: &str,
+    movement_type: &str,
+    quantity: i64,
+    reference_id: Option<&str>,
+    reason: Option<&str>,
+    lot: Option<&LotInput>,
+) -> Result<StockMovement, String> {
+    let stock: i64 = conn
+        .query_row("SELECT stock FROM products WHERE id = ?", params![product_id], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Product {} not found", product_id))?;
+
+    let movement = StockMovement {
+        id: database::generate_id("mov"),
+        product_id: product_id.to_string(),
+        movement_type: movement_type.to_string(),
+        quantity,
+        stock_after: stock + quantity,
+        reference_id: reference_id.map(str::to_string),
+        user_id: session::current_user_id(),
+        reason: reason.map(str::to_string),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+    conn.execute(
+        "INSERT INTO inventory_movements (id, product_id, movement_type, quantity, stock_after, reference_id, user_id,
+                                          reason, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            movement.id,
+            movement.product_id,
+            movement.movement_type,
+            movement.quantity,
+            movement.stock_after,
+            movement.reference_id,
+            movement.user_id,
+            movement.reason,
+            movement.created_at
+        ],
+    )
+    .map_err(|e| format!("Failed to record stock movement: {}", e))?;
+    lots::follow_movement(conn, &movement, lot)?;
+    costing::cost_movements(conn, product_id)?;
+    Ok(movement)
+}
+
+// Move units out of sellable stock into products.damaged_stock
+pub fn write_off_damaged(
+    conn: &Connection,
+    product_id: &str,
+    quantity: i64,
+    reference_id: Option<&str>,
+) -> Result<StockMovement, String> {
+    let movement = record(conn, product_id, DAMAGED, -quantity, reference_id, Some("Damaged"))?;
+    conn.execute(
+        "UPDATE products SET damaged_stock = damaged_stock + ?, updated_at = ? WHERE id = ?",
+        params![quantity, chrono::Utc::now().to_rfc3339(), product_id],
+    )
+    .map_err(|e| format!("Failed to record damaged stock: {}", e))?;
+    Ok(movement)
+}
+
+pub fn adjust_stock(conn: &Connection, adjustment: &StockAdjustment) -> Result<StockMovement, String> {
+    session::require_role(&["admin", "manager"])?;
+    if !MANUAL_TYPES.contains(&adjustment.movement_type.as_str()) {
+        return Err(format!("Unknown stock movement: {}", adjustment.movement_type));
+    }
+    if adjustment.quantity == 0 {
+        return Err("A stock movement needs a quantity".to_string());
+    }
+    if adjustment.movement_type != ADJUSTMENT && adjustment.quantity < 0 {
+        return Err("Quantities are given as positive units".to_string());
+    }
+
+    let quantity = match adjustment.movement_type.as_str() {
+        IN | ADJUSTMENT => adjustment.quantity,
+        _ => -adjustment.quantity,
+    };
+    let movement = match (adjustment.movement_type.as_str(), &adjustment.lot) {
+        (DAMAGED, None) => write_off_damaged(conn, &adjustment.product_id, adjustment.quantity, None)?,
+        (DAMAGED, Some(_)) => return Err("Damaged stock is written off from the earliest expiring lots".to_string()),
+        (_, Some(lot)) => record_into_lot(
+            conn,
+            &adjustment.product_id,
+            &adjustment.movement_type,
+            quantity,
+            None,
+            adjustment.reason.as_deref(),
+            lot,
+        )?,
+        (_, None) => record(
+            conn,
+            &adjustment.product_id,
+            &adjustment.movement_type,
+            quantity,
+            None,
+            adjustment.reason.as_deref(),
+        )?,
+    };
+
+    println!(
+        "📦 {} of {} x{}, stock now {}",
+        movement.movement_type, movement.product_id, movement.quantity, movement.stock_after
+    );
+    Ok(movement)
+}
+
+pub fn stock_history(conn: &Connection, product_id: &str) -> Result<Vec<StockMovement>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, product_id, movement_type, quantity, stock_after, reference_id, user_id, reason, created_at
+             FROM inventory_movements WHERE product_id = ? ORDER BY rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let movements = stmt
+        .query_map(params![product_id], |row| {
+            Ok(StockMovement {
+                id: row.get(0)?,
+                product_id: row.get(1)?,
+                movement_type: row.get(2)?,
+                quantity: row.get(3)?,
+                stock_after: row.get(4)?,
+                reference_id: row.get(5)?,
+                user_id: row.get(6)?,
+                reason: row.get(7)?,
+                created_at: row.get(8)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(movements)
+}
+
+// Products whose stock does not add up to the sum of their movements
+pub fn verify_stock(conn: &Connection) -> Result<Vec<StockDiscrepancy>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT p.id, p.name, p.stock, COALESCE(SUM(m.quantity), 0) AS ledger_stock
+             FROM products p LEFT JOIN inventory_movements m ON m.product_id = p.id
+             GROUP BY p.id HAVING p.stock <> ledger_stock
+             ORDER BY p.name",
+        )
+        .map_err(|e| e.to_string())?;
+    let discrepancies = stmt
+        .query_map([], |row| {
+            Ok(StockDiscrepancy {
+                product_id: row.get(0)?,
+                product_name: row.get(1)?,
+                stock: row.get(2)?,
+                ledger_stock: row.get(3)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(discrepancies)
+}
@@ -0,0 +1,306 @@
+use crate::database;
+use crate::inventory;
+use crate::session;
+use crate::units;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct KitComponentInput {
+    pub product_id: String,
+    // Per kit, fixed-point in the component's base unit
+    pub quantity: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct KitRecipe {
+    pub kit_id: String,
+    // Empty turns the product back into an ordinary one
+    pub components: Vec<KitComponentInput>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct KitComponent {
+    pub product_id: String,
+    pub name: String,
+    pub sku: String,
+    pub quantity: i64,
+    pub unit: String,
+    pub quantity_decimals: i64,
+    pub stock: i64,
+    // Whole kits this component's stock is enough for
+    pub kits_possible: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Kit {
+    pub kit_id: String,
+    pub name: String,
+    pub sku: String,
+    // Kits already assembled
+    pub stock: i64,
+    // Kits the component stock could still make
+    pub buildable: i64,
+    pub available: i64,
+    pub components: Vec<KitComponent>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Assembly {
+    pub id: String,
+    pub kit_id: String,
+    // Negative when kits were broken back down
+    pub quantity: i64,
+    pub kit_stock: i64,
+    pub movements: Vec<inventory::StockMovement>,
+}
+
+pub fn is_kit(conn: &Connection, product_id: &str) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM kit_components WHERE kit_id = ?)",
+        params![product_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+pub fn set_kit_components(conn: &Connection, recipe: &KitRecipe) -> Result<Kit, String> {
+    session::require_role(&["admin", "manager"])?;
+    let (name, decimals): (String, i64) = conn
+        .query_row(
+            "SELECT name, quantity_decimals FROM products WHERE id = ?",
+            params![recipe.kit_id],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Product {} not found", recipe.kit_id))?;
+    if decimals != 0 {
+        return Err(format!("{} is sold by weight or measure and cannot be a kit", name));
+    }
+    let is_parent: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM products WHERE parent_id = ?)",
+            params![recipe.kit_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if is_parent {
+        return Err(format!("{} has variants; make each variant a kit instead", name));
+    }
+
+    for (i, component) in recipe.components.iter().enumerate() {
+        if component.quantity <= 0 {
+            return Err("Component quantities must be positive".to_string());
+        }
+        if component.product_id == recipe.kit_id {
+            return Err(format!("{} cannot be a component of itself", name));
+        }
+        if recipe.components[..i].iter().any(|c| c.product_id == component.product_id) {
+            return Err(format!("Component {} is listed twice", component.product_id));
+        }
+        units::product_unit(conn, &component.product_id)?;
+        if is_kit(conn, &component.product_id)? {
+            return Err(format!("Component {} is itself a kit", component.product_id));
+        }
+    }
+    let used_in_kit: bool = conn
+        .query_row(
+            "SELECT EXISTS(SELECT 1 FROM kit_components WHERE component_id = ?)",
+            params![recipe.kit_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if used_in_kit && !recipe.components.is_empty() {
+        return Err(format!("{} is a component of another kit", name));
+    }
+
+    conn.execute("DELETE FROM kit_components WHERE kit_id = ?", params![recipe.kit_id])
+        .map_err(|e| format!("Failed to clear kit components: {}", e))?;
+    for component in &recipe.components {
+        conn.execute(
+            "INSERT INTO kit_components (id, kit_id, component_id, quantity) VALUES (?, ?, ?, ?)",
+            params![database::generate_id("kitc"), recipe.kit_id, component.product_id, component.quantity],
+        )
+        .map_err(|e| format!("Failed to add kit component: {}", e))?;
+    }
+
+    println!("🎁 {} now has {} component(s)", name, recipe.components.len());
+    get_kit(conn, &recipe.kit_id)
+}
+
+fn components(conn: &Connection, kit_id: &str) -> Result<Vec<KitComponent>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT p.id, p.name, p.sku, k.quantity, p.unit, p.quantity_decimals, p.stock
+             FROM kit_components k JOIN products p ON p.id = k.component_id
+             WHERE k.kit_id = ? ORDER BY k.rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let components = stmt
+        .query_map(params![kit_id], |row| {
+            let quantity: i64 = row.get(3)?;
+            let stock: i64 = row.get(6)?;
+            Ok(KitComponent {
+                product_id: row.get(0)?,
+                name: row.get(1)?,
+                sku: row.get(2)?,
+                quantity,
+                unit: row.get(4)?,
+                quantity_decimals: row.get(5)?,
+                stock,
+                kits_possible: stock.max(0) / quantity,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(components)
+}
+
+pub fn get_kit(conn: &Connection, kit_id: &str) -> Result<Kit, String> {
+    let (name, sku, stock): (String, String, i64) = conn
+        .query_row("SELECT name, sku, stock FROM products WHERE id = ?", params![kit_id], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Product {} not found", kit_id))?;
+    let components = components(conn, kit_id)?;
+    let buildable = components.iter().map(|c| c.kits_possible).min().unwrap_or(0);
+
+    Ok(Kit {
+        kit_id: kit_id.to_string(),
+        name,
+        sku,
+        stock,
+        buildable,
+        available: stock.max(0) + buildable,
+        components,
+    })
+}
+
+// Stock a product can be sold from: for a kit, what is assembled plus what
+// its components can still make
+pub fn available_stock(conn: &Connection, product_id: &str, stock: i64) -> Result<i64, String> {
+    let components = components(conn, product_id)?;
+    match components.iter().map(|c| c.kits_possible).min() {
+        Some(buildable) => Ok(stock.max(0) + buildable),
+        None => Ok(stock),
+    }
+}
+
+pub fn list_kits(conn: &Connection) -> Result<Vec<Kit>, String> {
+    let kit_ids: Vec<String> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT p.id FROM products p
+                 WHERE p.id IN (SELECT kit_id FROM kit_components) ORDER BY p.name",
+            )
+            .map_err(|e| e.to_string())?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        ids
+    };
+    kit_ids.iter().map(|id| get_kit(conn, id)).collect()
+}
+
+// Take a sold line out of stock. Kits come from assembled stock first and
+// the rest straight from their components.
+pub fn take_for_sale(
+    conn: &Connection,
+    product_id: &str,
+    quantity: i64,
+    sale_id: &str,
+) -> Result<Vec<inventory::StockMovement>, String> {
+    let components = components(conn, product_id)?;
+    if components.is_empty() {
+        return Ok(vec![inventory::record(conn, product_id, inventory::SALE, -quantity, Some(sale_id), None)?]);
+    }
+
+    let (name, stock): (String, i64) = conn
+        .query_row("SELECT name, stock FROM products WHERE id = ?", params![product_id], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .map_err(|e| e.to_string())?;
+    let mut movements = Vec::new();
+    let assembled = stock.max(0).min(quantity);
+    if assembled > 0 {
+        movements.push(inventory::record(conn, product_id, inventory::SALE, -assembled, Some(sale_id), None)?);
+    }
+
+    let unbuilt = quantity - assembled;
+    if unbuilt > 0 {
+        let reason = format!("Kit {}", name);
+        for component in &components {
+            movements.push(inventory::record(
+                conn,
+                &component.product_id,
+                inventory::SALE,
+                -(unbuilt * component.quantity),
+                Some(sale_id),
+                Some(&reason),
+            )?);
+        }
+    }
+    Ok(movements)
+}
+
+// Build kits ahead of time from component stock, or with a negative
+// quantity break assembled kits back into their components
+pub fn assemble(conn: &Connection, kit_id: &str, quantity: i64) -> Result<Assembly, String> {
+    session::require_role(&["admin", "manager"])?;
+    if quantity == 0 {
+        return Err("Give the number of kits to assemble".to_string());
+    }
+    let kit = get_kit(conn, kit_id)?;
+    if kit.components.is_empty() {
+        return Err(format!("{} is not a kit", kit.name));
+    }
+
+    if quantity > 0 {
+        for component in &kit.components {
+            let needed = quantity * component.quantity;
+            if component.stock < needed {
+                return Err(format!(
+                    "Not enough {} to assemble {} kit(s): {} in stock, {} needed",
+                    component.name,
+                    quantity,
+                    units::format_quantity(component.stock, component.quantity_decimals),
+                    units::format_quantity(needed, component.quantity_decimals)
+                ));
+            }
+        }
+    } else if kit.stock < -quantity {
+        return Err(format!("Only {} assembled {} to break down", kit.stock.max(0), kit.name));
+    }
+
+    let assembly_id = database::generate_id("asm");
+    let reason = if quantity > 0 { "Assembled into kits" } else { "Broken down from kits" };
+    let mut movements = Vec::new();
+    for component in &kit.components {
+        movements.push(inventory::record(
+            conn,
+            &component.product_id,
+            inventory::ASSEMBLY,
+            -(quantity * component.quantity),
+            Some(&assembly_id),
+            Some(reason),
+        )?);
+    }
+    let kit_movement = inventory::record(conn, kit_id, inventory::ASSEMBLY, quantity, Some(&assembly_id), Some(reason))?;
+    let kit_stock = kit_movement.stock_after;
+    movements.push(kit_movement);
+
+    println!("🎁 {} x{}: {}, {} in stock", kit.name, quantity.abs(), reason.to_lowercase(), kit_stock);
+    Ok(Assembly {
+        id: assembly_id,
+        kit_id: kit_id.to_string(),
+        quantity,
+        kit_stock,
+        movements,
+    })
+}
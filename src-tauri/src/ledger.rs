@@ -0,0 +1,446 @@
+use rusqlite::functions::{Context, FunctionFlags};
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Immutable sale columns covered by the chain. Status and notes are left out
+// because a void or refund legitimately changes them.
+const SALE_HASH_COLUMNS: &[&str] = &[
+    "id",
+    "sale_number",
+    "customer_id",
+    "user_id",
+    "total_minor",
+    "tax_minor",
+    "discount_minor",
+    "payment_method",
+    "created_at",
+];
+
+const SALE_ITEM_HASH_COLUMNS: &[&str] = &[
+    "id",
+    "sale_id",
+    "product_id",
+    "quantity",
+    "price_minor",
+    "total_minor",
+];
+
+const AUDIT_HASH_COLUMNS: &[&str] = &[
+    "id",
+    "table_name",
+    "operation",
+    "record_id",
+    "user_id",
+    "old_data",
+    "new_data",
+    "timestamp",
+];
+
+#[derive(Debug, Serialize)]
+pub struct BrokenLink {
+    pub chain: String,
+    pub chain_seq: Option<i64>,
+    pub record_id: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LedgerReport {
+    pub ok: bool,
+    pub sales_checked: i64,
+    pub sale_items_checked: i64,
+    pub audit_entries_checked: i64,
+    pub first_broken_link: Option<BrokenLink>,
+}
+
+struct SanctionGuard(Arc<AtomicBool>);
+
+impl Drop for SanctionGuard {
+    fn drop(&mut self) {
+        self.0.store(false, Ordering::SeqCst);
+    }
+}
+
+// ledger_override() answers from a flag captured per connection, so lifting
+// the guard on one connection leaves every other connection to the same file
+// guarded.
+fn register_override(conn: &Connection, lifted: Arc<AtomicBool>) -> Result<(), String> {
+    conn.create_scalar_function("ledger_override", 0, FunctionFlags::SQLITE_UTF8, move |_| {
+        Ok(lifted.load(Ordering::SeqCst))
+    })
+    .map_err(|e| format!("Failed to register ledger_override(): {}", e))
+}
+
+// Run `f` with the completed-sale guard lifted on `conn` only, for a formal
+// void/refund or the training reset
+pub fn sanctioned<T>(conn: &Connection, f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    let lifted = Arc::new(AtomicBool::new(true));
+    register_override(conn, lifted.clone())?;
+    let _guard = SanctionGuard(lifted);
+    f()
+}
+
+fn push_field(buffer: &mut Vec<u8>, value: ValueRef<'_>) {
+    buffer.push(0x1f);
+    match value {
+        ValueRef::Null => buffer.push(0x00),
+        ValueRef::Integer(i) => buffer.extend_from_slice(i.to_string().as_bytes()),
+        ValueRef::Real(f) => buffer.extend_from_slice(f.to_string().as_bytes()),
+        ValueRef::Text(t) | ValueRef::Blob(t) => buffer.extend_from_slice(t),
+    }
+}
+
+// SHA-256 over the previous link's hash followed by each field, separated by
+// the ASCII unit separator. NULL is encoded distinctly from the empty string.
+pub fn chain_hash(prev_hash: &str, fields: &[ValueRef<'_>]) -> String {
+    let mut buffer = prev_hash.as_bytes().to_vec();
+    for field in fields {
+        push_field(&mut buffer, *field);
+    }
+
+    Sha256::digest(&buffer)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn chain_hash_sql(ctx: &Context<'_>) -> rusqlite::Result<String> {
+    let prev_hash = match ctx.get_raw(0) {
+        ValueRef::Text(t) => String::from_utf8_lossy(t).to_string(),
+        _ => String::new(),
+    };
+    let fields: Vec<ValueRef<'_>> = (1..ctx.len()).map(|i| ctx.get_raw(i)).collect();
+    Ok(chain_hash(&prev_hash, &fields))
+}
+
+pub fn register_functions(conn: &Connection) -> Result<(), String> {
+    conn.create_scalar_function(
+        "chain_hash",
+        -1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        chain_hash_sql,
+    )
+    .map_err(|e| format!("Failed to register chain_hash(): {}", e))?;
+
+    register_override(conn, Arc::new(AtomicBool::new(false)))?;
+
+    Ok(())
+}
+
+fn column_list(columns: &[&str], row: &str) -> String {
+    columns
+        .iter()
+        .map(|c| format!("{}.{}", row, c))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+// AFTER INSERT trigger that appends a new row to its table's chain
+fn seal_trigger(table: &str, columns: &[&str]) -> String {
+    let last_hash = format!(
+        "COALESCE((SELECT hash FROM {table} WHERE chain_seq = (SELECT MAX(chain_seq) FROM {table})), '')",
+        table = table
+    );
+
+    format!(
+        r#"
+        DROP TRIGGER IF EXISTS ledger_{table}_seal;
+        CREATE TRIGGER ledger_{table}_seal
+        AFTER INSERT ON {table}
+        WHEN NEW.hash IS NULL
+        BEGIN
+            UPDATE {table} SET
+                chain_seq = (SELECT COALESCE(MAX(chain_seq), 0) + 1 FROM {table}),
+                prev_hash = {last_hash},
+                hash = chain_hash({last_hash}, {fields})
+            WHERE id = NEW.id;
+        END;
+        "#,
+        table = table,
+        last_hash = last_hash,
+        fields = column_list(columns, "NEW"),
+    )
+}
+
+pub fn install_triggers(conn: &Connection) -> Result<(), String> {
+    let item_fields = column_list(SALE_ITEM_HASH_COLUMNS, "NEW");
+
+    let sql = format!(
+        r#"
+        {sales_seal}
+        {audit_seal}
+        DROP TRIGGER IF EXISTS ledger_sale_items_seal;
+        CREATE TRIGGER ledger_sale_items_seal
+        AFTER INSERT ON sale_items
+        WHEN NEW.hash IS NULL
+        BEGIN
+            UPDATE sale_items SET
+                hash = chain_hash(COALESCE((SELECT hash FROM sales WHERE id = NEW.sale_id), ''), {item_fields})
+            WHERE id = NEW.id;
+        END;
+
+        DROP TRIGGER IF EXISTS ledger_sales_no_update;
+        CREATE TRIGGER ledger_sales_no_update
+        BEFORE UPDATE ON sales
+        WHEN OLD.hash IS NOT NULL AND NOT ledger_override()
+        BEGIN
+            SELECT RAISE(ABORT, 'Completed sales cannot be edited; use void or refund');
+        END;
+
+        DROP TRIGGER IF EXISTS ledger_sales_no_delete;
+        CREATE TRIGGER ledger_sales_no_delete
+        BEFORE DELETE ON sales
+        WHEN OLD.hash IS NOT NULL AND NOT ledger_override()
+        BEGIN
+            SELECT RAISE(ABORT, 'Sales cannot be deleted; use void or refund');
+        END;
+
+        DROP TRIGGER IF EXISTS ledger_sale_items_no_update;
+        CREATE TRIGGER ledger_sale_items_no_update
+        BEFORE UPDATE ON sale_items
+        WHEN OLD.hash IS NOT NULL AND NOT ledger_override()
+        BEGIN
+            SELECT RAISE(ABORT, 'Items of a completed sale cannot be edited');
+        END;
+
+        DROP TRIGGER IF EXISTS ledger_sale_items_no_delete;
+        CREATE TRIGGER ledger_sale_items_no_delete
+        BEFORE DELETE ON sale_items
+        WHEN OLD.hash IS NOT NULL AND NOT ledger_override()
+        BEGIN
+            SELECT RAISE(ABORT, 'Items of a completed sale cannot be deleted');
+        END;
+
+        DROP TRIGGER IF EXISTS ledger_audit_log_no_update;
+        CREATE TRIGGER ledger_audit_log_no_update
+        BEFORE UPDATE ON audit_log
+        WHEN OLD.hash IS NOT NULL AND NOT ledger_override()
+        BEGIN
+            SELECT RAISE(ABORT, 'The audit log is append-only');
+        END;
+
+        DROP TRIGGER IF EXISTS ledger_audit_log_no_delete;
+        CREATE TRIGGER ledger_audit_log_no_delete
+        BEFORE DELETE ON audit_log
+        WHEN NOT ledger_override()
+        BEGIN
+            SELECT RAISE(ABORT, 'The audit log is append-only');
+        END;
+        "#,
+        sales_seal = seal_trigger("sales", SALE_HASH_COLUMNS),
+        audit_seal = seal_trigger("audit_log", AUDIT_HASH_COLUMNS),
+        item_fields = item_fields,
+    );
+
+    conn.execute_batch(&sql)
+        .map_err(|e| format!("Failed to install ledger triggers: {}", e))
+}
+
+// Chain rows that predate the ledger, oldest first, so existing databases
+// start with a complete chain.
+pub fn seal_existing(conn: &Connection) -> Result<(), String> {
+    let chains = [
+        ("sales", "created_at", SALE_HASH_COLUMNS),
+        ("audit_log", "timestamp", AUDIT_HASH_COLUMNS),
+    ];
+
+    for (table, order_column, columns) in chains {
+        let pending: Vec<String> = {
+            let mut stmt = conn
+                .prepare(&format!(
+                    "SELECT id FROM {} WHERE hash IS NULL ORDER BY {}, rowid",
+                    table, order_column
+                ))
+                .map_err(|e| e.to_string())?;
+            let ids = stmt
+                .query_map([], |row| row.get(0))
+                .map_err(|e| e.to_string())?
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| e.to_string())?;
+            ids
+        };
+
+        for id in pending {
+            conn.execute(
+                &format!(
+                    "UPDATE {table} SET
+                        chain_seq = (SELECT COALESCE(MAX(chain_seq), 0) + 1 FROM {table}),
+                        prev_hash = COALESCE((SELECT hash FROM {table} WHERE chain_seq = (SELECT MAX(chain_seq) FROM {table})), ''),
+                        hash = chain_hash(
+                            COALESCE((SELECT hash FROM {table} WHERE chain_seq = (SELECT MAX(chain_seq) FROM {table})), ''),
+                            {fields}
+                        )
+                     WHERE id = ?",
+                    table = table,
+                    fields = columns.join(", ")
+                ),
+                params![id],
+            )
+            .map_err(|e| format!("Failed to seal {} {}: {}", table, id, e))?;
+        }
+    }
+
+    conn.execute(
+        &format!(
+            "UPDATE sale_items SET hash = chain_hash(COALESCE((SELECT hash FROM sales WHERE sales.id = sale_items.sale_id), ''), {})
+             WHERE hash IS NULL",
+            SALE_ITEM_HASH_COLUMNS.join(", ")
+        ),
+        [],
+    )
+    .map_err(|e| format!("Failed to seal sale items: {}", e))?;
+
+    Ok(())
+}
+
+// Walk one chain in order, recomputing every link. Returns the number of
+// rows checked, or the first link that does not match.
+fn verify_chain(
+    conn: &Connection,
+    table: &str,
+    columns: &[&str],
+) -> Result<Result<i64, BrokenLink>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT id, chain_seq, prev_hash, hash, {} FROM {} WHERE chain_seq IS NOT NULL ORDER BY chain_seq",
+            columns.join(", "),
+            table
+        ))
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+
+    let mut expected_seq = 1;
+    let mut expected_prev = String::new();
+    let mut checked = 0;
+
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let id: String = row.get(0).map_err(|e| e.to_string())?;
+        let seq: i64 = row.get(1).map_err(|e| e.to_string())?;
+        let prev_hash: Option<String> = row.get(2).map_err(|e| e.to_string())?;
+        let hash: Option<String> = row.get(3).map_err(|e| e.to_string())?;
+
+        let broken = |reason: String| BrokenLink {
+            chain: table.to_string(),
+            chain_seq: Some(seq),
+            record_id: id.clone(),
+            reason,
+        };
+
+        if seq != expected_seq {
+            return Ok(Err(broken(format!(
+                "Sequence gap: expected {} but found {} (rows removed)",
+                expected_seq, seq
+            ))));
+        }
+        if prev_hash.as_deref() != Some(expected_prev.as_str()) {
+            return Ok(Err(broken("Previous hash does not match the prior link".to_string())));
+        }
+
+        let fields: Vec<ValueRef<'_>> = (0..columns.len())
+            .map(|i| row.get_ref_unwrap(i + 4))
+            .collect();
+        let computed = chain_hash(&expected_prev, &fields);
+        if hash.as_deref() != Some(computed.as_str()) {
+            return Ok(Err(broken("Content does not match its stored hash".to_string())));
+        }
+
+        expected_prev = computed;
+        expected_seq += 1;
+        checked += 1;
+    }
+
+    let unsealed: i64 = conn
+        .query_row(
+            &format!("SELECT COUNT(*) FROM {} WHERE hash IS NULL OR chain_seq IS NULL", table),
+            [],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if unsealed > 0 {
+        let id: String = conn
+            .query_row(
+                &format!("SELECT id FROM {} WHERE hash IS NULL OR chain_seq IS NULL LIMIT 1", table),
+                [],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        return Ok(Err(BrokenLink {
+            chain: table.to_string(),
+            chain_seq: None,
+            record_id: id,
+            reason: "Row is missing from the chain".to_string(),
+        }));
+    }
+
+    Ok(Ok(checked))
+}
+
+fn verify_sale_items(conn: &Connection) -> Result<Result<i64, BrokenLink>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT sale_items.hash, COALESCE(sales.hash, ''), {}
+             FROM sale_items LEFT JOIN sales ON sales.id = sale_items.sale_id
+             ORDER BY sale_items.rowid",
+            column_list(SALE_ITEM_HASH_COLUMNS, "sale_items")
+        ))
+        .map_err(|e| e.to_string())?;
+    let mut rows = stmt.query([]).map_err(|e| e.to_string())?;
+    let mut checked = 0;
+
+    while let Some(row) = rows.next().map_err(|e| e.to_string())? {
+        let hash: Option<String> = row.get(0).map_err(|e| e.to_string())?;
+        let sale_hash: String = row.get(1).map_err(|e| e.to_string())?;
+        let fields: Vec<ValueRef<'_>> = (0..SALE_ITEM_HASH_COLUMNS.len())
+            .map(|i| row.get_ref_unwrap(i + 2))
+            .collect();
+
+        if hash.as_deref() != Some(chain_hash(&sale_hash, &fields).as_str()) {
+            let id: String = row.get(2).map_err(|e| e.to_string())?;
+            return Ok(Err(BrokenLink {
+                chain: "sale_items".to_string(),
+                chain_seq: None,
+                record_id: id,
+                reason: "Item does not match its stored hash".to_string(),
+            }));
+        }
+        checked += 1;
+    }
+
+    Ok(Ok(checked))
+}
+
+pub fn verify_ledger(conn: &Connection) -> Result<LedgerReport, String> {
+    let mut report = LedgerReport {
+        ok: true,
+        sales_checked: 0,
+        sale_items_checked: 0,
+        audit_entries_checked: 0,
+        first_broken_link: None,
+    };
+
+    match verify_chain(conn, "sales", SALE_HASH_COLUMNS)? {
+        Ok(checked) => report.sales_checked = checked,
+        Err(link) => report.first_broken_link = Some(link),
+    }
+
+    if report.first_broken_link.is_none() {
+        match verify_sale_items(conn)? {
+            Ok(checked) => report.sale_items_checked = checked,
+            Err(link) => report.first_broken_link = Some(link),
+        }
+    }
+
+    if report.first_broken_link.is_none() {
+        match verify_chain(conn, "audit_log", AUDIT_HASH_COLUMNS)? {
+            Ok(checked) => report.audit_entries_checked = checked,
+            Err(link) => report.first_broken_link = Some(link),
+        }
+    }
+
+    report.ok = report.first_broken_link.is_none();
+    Ok(report)
+}
@@ -0,0 +1,398 @@
+use crate::database;
+use crate::inventory::{self, StockMovement};
+use crate::session;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+// Lot for stock that arrives without one, e.g. a stocktake overage
+const UNASSIGNED_LOT: &str = "NO-LOT";
+// Lot holding the stock a product had when tracking was switched on
+const OPENING_LOT: &str = "OPENING";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LotInput {
+    pub lot_number: String,
+    // YYYY-MM-DD; lots without one never expire
+    #[serde(default)]
+    pub expiry_date: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Lot {
+    pub id: String,
+    pub product_id: String,
+    pub lot_number: String,
+    pub expiry_date: Option<String>,
+    pub quantity_received: i64,
+    pub quantity_remaining: i64,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExpiryAlert {
+    pub lot_id: String,
+    pub product_id: String,
+    pub product_name: String,
+    pub lot_number: String,
+    pub expiry_date: String,
+    pub quantity_remaining: i64,
+    // Negative once the lot has expired
+    pub days_left: i64,
+    pub expired: bool,
+}
+
+fn today() -> String {
+    chrono::Local::now().format("%Y-%m-%d").to_string()
+}
+
+fn tracks_lots(conn: &Connection, product_id: &str) -> Result<bool, String> {
+    conn.query_row("SELECT track_lots FROM products WHERE id = ?", params![product_id], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Product {} not found", product_id))
+}
+
+fn validate_expiry(expiry_date: Option<&str>) -> Result<(), String> {
+    if let Some(date) = expiry_date {
+        chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d").map_err(|_| format!("Invalid expiry date: {}", date))?;
+    }
+    Ok(())
+}
+
+// Turn lot tracking on or off and set how many days ahead of expiry a lot
+// is flagged. Stock already on hand goes into an opening lot.
+pub fn set_lot_tracking(conn: &Connection, product_id: &str, track_lots: bool, expiry_warning_days: i64) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    if expiry_warning_days < 0 {
+        return Err("Expiry warning days cannot be negative".to_string());
+    }
+    let updated = conn
+        .execute(
+            "UPDATE products SET track_lots = ?, expiry_warning_days = ?, updated_at = ? WHERE id = ?",
+            params![track_lots, expiry_warning_days, chrono::Utc::now().to_rfc3339(), product_id],
+        )
+        .map_err(|e| format!("Failed to update lot tracking: {}", e))?;
+    if updated == 0 {
+        return Err(format!("Product {} not found", product_id));
+    }
+
+    if track_lots {
+        let untracked: i64 = conn
+            .query_row(
+                "SELECT p.stock - COALESCE((SELECT SUM(quantity_remaining) FROM product_lots WHERE product_id = p.id), 0)
+                 FROM products p WHERE p.id = ?",
+                params![product_id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if untracked > 0 {
+            add_to_lot(
+                conn,
+                product_id,
+                &LotInput {
+                    lot_number: OPENING_LOT.to_string(),
+                    expiry_date: None,
+                },
+                untracked,
+                None,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn find_lot(conn: &Connection, product_id: &str, lot_number: &str) -> Result<Option<String>, String> {
+    conn.query_row(
+        "SELECT id FROM product_lots WHERE product_id = ? AND lot_number = ?",
+        params![product_id, lot_number],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+fn add_to_lot(conn: &Connection, product_id: &str, lot: &LotInput, quantity: i64, movement_id: Option<&str>) -> Result<String, String> {
+    let lot_number = lot.lot_number.trim();
+    if lot_number.is_empty() {
+        return Err("Lot number is required".to_string());
+    }
+    validate_expiry(lot.expiry_date.as_deref())?;
+
+    let lot_id = match find_lot(conn, product_id, lot_number)? {
+        Some(lot_id) => {
+            conn.execute(
+                "UPDATE product_lots SET quantity_received = quantity_received + ?1,
+                                         quantity_remaining = quantity_remaining + ?1,
+                                         expiry_date = COALESCE(?2, expiry_date)
+                 WHERE id = ?3",
+                params![quantity, lot.expiry_date, lot_id],
+            )
+            .map_err(|e| format!("Failed to update lot {}: {}", lot_number, e))?;
+            lot_id
+        }
+        None => {
+            let lot_id = database::generate_id("lot");
+            conn.execute(
+                "INSERT INTO product_lots (id, product_id, lot_number, expiry_date, quantity_received, quantity_remaining, created_at)
+                 VALUES (?, ?, ?, ?, ?, ?, ?)",
+                params![lot_id, product_id, lot_number, lot.expiry_date, quantity, quantity, chrono::Utc::now().to_rfc3339()],
+            )
+            .map_err(|e| format!("Failed to create lot {}: {}", lot_number, e))?;
+            lot_id
+        }
+    };
+
+    if let Some(movement_id) = movement_id {
+        link(conn, &lot_id, movement_id, quantity)?;
+    }
+    Ok(lot_id)
+}
+
+fn link(conn: &Connection, lot_id: &str, movement_id: &str, quantity: i64) -> Result<(), String> {
+    conn.execute(
+        "INSERT INTO lot_movements (id, lot_id, movement_id, quantity) VALUES (?, ?, ?, ?)",
+        params![database::generate_id("lotmov"), lot_id, movement_id, quantity],
+    )
+    .map_err(|e| format!("Failed to record lot movement: {}", e))?;
+    Ok(())
+}
+
+fn change_remaining(conn: &Connection, lot_id: &str, movement_id: &str, quantity: i64) -> Result<(), String> {
+    conn.execute(
+        "UPDATE product_lots SET quantity_remaining = quantity_remaining + ? WHERE id = ?",
+        params![quantity, lot_id],
+    )
+    .map_err(|e| e.to_string())?;
+    link(conn, lot_id, movement_id, quantity)
+}
+
+// Take units out of a product's lots, earliest expiry first. Sales skip
+// expired lots and fail when the unexpired stock runs short; other outflows
+// (shrinkage, write-offs) take whatever is there.
+fn consume_fefo(conn: &Connection, movement: &StockMovement, quantity: i64) -> Result<(), String> {
+    let selling = movement.movement_type == inventory::SALE;
+    let lots: Vec<(String, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, quantity_remaining FROM product_lots
+                 WHERE product_id = ?1 AND quantity_remaining > 0
+                   AND (?2 = 0 OR expiry_date IS NULL OR expiry_date >= ?3)
+                 ORDER BY expiry_date IS NULL, expiry_date, created_at",
+            )
+            .map_err(|e| e.to_string())?;
+        let lots = stmt
+            .query_map(params![movement.product_id, selling, today()], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        lots
+    };
+
+    let available: i64 = lots.iter().map(|(_, remaining)| remaining).sum();
+    if selling && available < quantity {
+        let name: String = conn
+            .query_row("SELECT name FROM products WHERE id = ?", params![movement.product_id], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        return Err(format!("Only {} unexpired units of {} in stock", available, name));
+    }
+
+    let mut needed = quantity;
+    for (lot_id, remaining) in lots {
+        if needed == 0 {
+            break;
+        }
+        let taken = needed.min(remaining);
+        change_remaining(conn, &lot_id, &movement.id, -taken)?;
+        needed -= taken;
+    }
+    Ok(())
+}
+
+// Put units back into the lots a sale drew them from, freshest first
+fn restore_to_sale_lots(conn: &Connection, movement: &StockMovement, sale_id: &str, quantity: i64) -> Result<(), String> {
+    let lots: Vec<(String, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT lm.lot_id, -SUM(lm.quantity)
+                 FROM lot_movements lm
+                 JOIN inventory_movements m ON m.id = lm.movement_id
+                 JOIN product_lots l ON l.id = lm.lot_id
+                 WHERE m.product_id = ?1
+                   AND ((m.movement_type IN ('sale', 'void') AND m.reference_id = ?2)
+                        OR (m.movement_type = 'return' AND m.reference_id IN (SELECT id FROM returns WHERE sale_id = ?2)))
+                 GROUP BY lm.lot_id HAVING SUM(lm.quantity) < 0
+                 ORDER BY l.expiry_date IS NULL DESC, l.expiry_date DESC",
+            )
+            .map_err(|e| e.to_string())?;
+        let lots = stmt
+            .query_map(params![movement.product_id, sale_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        lots
+    };
+
+    let mut left = quantity;
+    for (lot_id, taken) in lots {
+        if left == 0 {
+            break;
+        }
+        let restored = left.min(taken);
+        change_remaining(conn, &lot_id, &movement.id, restored)?;
+        left -= restored;
+    }
+    if left > 0 {
+        add_to_lot(conn, &movement.product_id, &unassigned(), left, Some(&movement.id))?;
+    }
+    Ok(())
+}
+
+fn unassigned() -> LotInput {
+    LotInput {
+        lot_number: UNASSIGNED_LOT.to_string(),
+        expiry_date: None,
+    }
+}
+
+// Keep a lot-tracked product's lots in step with a stock movement
+pub fn follow_movement(conn: &Connection, movement: &StockMovement, lot: Option<&LotInput>) -> Result<(), String> {
+    if !tracks_lots(conn, &movement.product_id)? {
+        return Ok(());
+    }
+
+    if movement.quantity < 0 {
+        let quantity = -movement.quantity;
+        return match lot {
+            Some(lot) => {
+                let lot_id = find_lot(conn, &movement.product_id, lot.lot_number.trim())?
+                    .ok_or_else(|| format!("Lot {} not found", lot.lot_number))?;
+                let remaining: i64 = conn
+                    .query_row("SELECT quantity_remaining FROM product_lots WHERE id = ?", params![lot_id], |row| row.get(0))
+                    .map_err(|e| e.to_string())?;
+                if remaining < quantity {
+                    return Err(format!("Lot {} only has {} left", lot.lot_number, remaining));
+                }
+                change_remaining(conn, &lot_id, &movement.id, -quantity)
+            }
+            None => consume_fefo(conn, movement, quantity),
+        };
+    }
+
+    let sale_id: Option<String> = match movement.movement_type.as_str() {
+        inventory::VOID => movement.reference_id.clone(),
+        inventory::RETURN => conn
+            .query_row(
+                "SELECT sale_id FROM returns WHERE id = ?",
+                params![movement.reference_id],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?,
+        _ => None,
+    };
+
+    match (lot, sale_id) {
+        (Some(lot), _) => add_to_lot(conn, &movement.product_id, lot, movement.quantity, Some(&movement.id)).map(|_| ()),
+        (None, Some(sale_id)) => restore_to_sale_lots(conn, movement, &sale_id, movement.quantity),
+        (None, None) if movement.movement_type == inventory::IN => {
+            Err("Lot-tracked products need a lot number when received".to_string())
+        }
+        (None, None) => add_to_lot(conn, &movement.product_id, &unassigned(), movement.quantity, Some(&movement.id)).map(|_| ()),
+    }
+}
+
+pub fn list_lots(conn: &Connection, product_id: &str) -> Result<Vec<Lot>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, product_id, lot_number, expiry_date, quantity_received, quantity_remaining, created_at
+             FROM product_lots WHERE product_id = ?
+             ORDER BY expiry_date IS NULL, expiry_date, created_at",
+        )
+        .map_err(|e| e.to_string())?;
+    let lots = stmt
+        .query_map(params![product_id], |row| {
+            Ok(Lot {
+                id: row.get(0)?,
+                product_id: row.get(1)?,
+                lot_number: row.get(2)?,
+                expiry_date: row.get(3)?,
+                quantity_received: row.get(4)?,
+                quantity_remaining: row.get(5)?,
+                created_at: row.get(6)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(lots)
+}
+
+// Lots with stock left that expire within their product's warning window,
+// or already have
+pub fn expiry_alerts(conn: &Connection) -> Result<Vec<ExpiryAlert>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT l.id, l.product_id, p.name, l.lot_number, l.expiry_date, l.quantity_remaining,
+                    CAST(julianday(l.expiry_date) - julianday(?1) AS INTEGER)
+             FROM product_lots l JOIN products p ON p.id = l.product_id
+             WHERE l.quantity_remaining > 0 AND l.expiry_date IS NOT NULL
+               AND l.expiry_date <= date(?1, '+' || p.expiry_warning_days || ' days')
+             ORDER BY l.expiry_date, p.name",
+        )
+        .map_err(|e| e.to_string())?;
+    let alerts = stmt
+        .query_map(params![today()], |row| {
+            let days_left: i64 = row.get(6)?;
+            Ok(ExpiryAlert {
+                lot_id: row.get(0)?,
+                product_id: row.get(1)?,
+                product_name: row.get(2)?,
+                lot_number: row.get(3)?,
+                expiry_date: row.get(4)?,
+                quantity_remaining: row.get(5)?,
+                days_left,
+                expired: days_left < 0,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(alerts)
+}
+
+// Take every expired lot's remaining units out of stock
+pub fn write_off_expired(conn: &Connection) -> Result<Vec<StockMovement>, String> {
+    session::require_role(&["admin", "manager"])?;
+    let expired: Vec<(String, String, String, Option<String>, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, product_id, lot_number, expiry_date, quantity_remaining FROM product_lots
+                 WHERE quantity_remaining > 0 AND expiry_date < ? ORDER BY expiry_date",
+            )
+            .map_err(|e| e.to_string())?;
+        let expired = stmt
+            .query_map(params![today()], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        expired
+    };
+
+    let mut movements = Vec::new();
+    for (lot_id, product_id, lot_number, expiry_date, remaining) in expired {
+        movements.push(inventory::record_into_lot(
+            conn,
+            &product_id,
+            inventory::EXPIRED,
+            -remaining,
+            Some(&lot_id),
+            Some(&format!("Lot {} expired", lot_number)),
+            &LotInput { lot_number, expiry_date },
+        )?);
+    }
+
+    if !movements.is_empty() {
+        println!("🗑️ {} expired lot(s) written off", movements.len());
+    }
+    Ok(movements)
+}
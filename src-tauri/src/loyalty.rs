@@ -0,0 +1,469 @@
+use crate::database;
+use crate::money::{self, FULL_BP};
+use crate::session;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoyaltyProgram {
+    pub enabled: bool,
+    // Points earned per whole currency unit spent
+    pub points_per_unit: i64,
+    // What one point is worth when redeemed, in minor units
+    pub point_value_minor: i64,
+    // Points lapse this many days after they are earned; 0 keeps them forever
+    pub expiry_days: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LedgerEntry {
+    pub id: String,
+    pub entry_type: String,
+    pub points: i64,
+    pub sale_id: Option<String>,
+    pub return_id: Option<String>,
+    pub expires_at: Option<String>,
+    pub note: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoyaltyAccount {
+    pub customer_id: String,
+    pub balance: i64,
+    pub balance_value_minor: i64,
+    pub entries: Vec<LedgerEntry>,
+}
+
+// Points given back or taken away when part of a sale is returned
+#[derive(Debug, Default)]
+pub struct ReturnAdjustment {
+    pub points_reversed: i64,
+    pub points_restored: i64,
+    // Share of the refund settled by restoring points rather than money
+    pub restored_value_minor: i64,
+}
+
+pub fn install_defaults(conn: &Connection) -> Result<(), String> {
+    conn.execute(
+        "INSERT OR IGNORE INTO loyalty_program (id, enabled, points_per_unit, point_value_minor, expiry_days, updated_at)
+         VALUES (1, 0, 1, 1, 365, ?)",
+        params![chrono::Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| format!("Failed to create loyalty program: {}", e))?;
+    Ok(())
+}
+
+pub fn get_program(conn: &Connection) -> Result<LoyaltyProgram, String> {
+    conn.query_row(
+        "SELECT enabled, points_per_unit, point_value_minor, expiry_days FROM loyalty_program WHERE id = 1",
+        [],
+        |row| {
+            Ok(LoyaltyProgram {
+                enabled: row.get(0)?,
+                points_per_unit: row.get(1)?,
+                point_value_minor: row.get(2)?,
+                expiry_days: row.get(3)?,
+            })
+        },
+    )
+    .map_err(|e| format!("Failed to load loyalty program: {}", e))
+}
+
+pub fn save_program(conn: &Connection, program: &LoyaltyProgram) -> Result<LoyaltyProgram, String> {
+    session::require_role(&["admin", "manager"])?;
+    if program.points_per_unit < 0 || program.point_value_minor <= 0 || program.expiry_days < 0 {
+        return Err("Loyalty rates must be positive".to_string());
+    }
+    conn.execute(
+        "UPDATE loyalty_program SET enabled = ?, points_per_unit = ?, point_value_minor = ?, expiry_days = ?, updated_at = ?
+         WHERE id = 1",
+        params![
+            program.enabled,
+            program.points_per_unit,
+            program.point_value_minor,
+            program.expiry_days,
+            chrono::Utc::now().to_rfc3339()
+        ],
+    )
+    .map_err(|e| format!("Failed to save loyalty program: {}", e))?;
+    get_program(conn)
+}
+
+pub fn set_category_multiplier(conn: &Connection, category_id: &str, multiplier_bp: i64) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    if multiplier_bp < 0 {
+        return Err("Multiplier cannot be negative".to_string());
+    }
+    let updated = conn
+        .execute(
+            "UPDATE categories SET loyalty_multiplier_bp = ?, updated_at = ? WHERE id = ?",
+            params![multiplier_bp, chrono::Utc::now().to_rfc3339(), category_id],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err(format!("Category {} not found", category_id));
+    }
+    Ok(())
+}
+
+pub fn balance(conn: &Connection, customer_id: &str) -> Result<i64, String> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(points), 0) FROM loyalty_ledger WHERE customer_id = ?",
+        params![customer_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+fn sync_balance(conn: &Connection, customer_id: &str) -> Result<(), String> {
+    let points = balance(conn, customer_id)?;
+    money::mirror_customer_balance(conn, customer_id, "loyalty_points", points)
+}
+
+struct NewEntry<'a> {
+    customer_id: &'a str,
+    entry_type: &'a str,
+    points: i64,
+    sale_id: Option<&'a str>,
+    return_id: Option<&'a str>,
+    note: Option<String>,
+}
+
+// Positive entries keep a `remaining` count that redemptions, reversals
+// and expiry draw down, oldest points first
+fn add_entry(conn: &Connection, program: &LoyaltyProgram, entry: NewEntry<'_>) -> Result<(), String> {
+    if entry.points == 0 {
+        return Ok(());
+    }
+    let now = chrono::Utc::now();
+
+    let (remaining, expires_at) = if entry.points > 0 {
+        let expires_at = (program.expiry_days > 0)
+            .then(|| (now + chrono::Duration::days(program.expiry_days)).to_rfc3339());
+        (entry.points, expires_at)
+    } else {
+        consume(conn, entry.customer_id, -entry.points)?;
+        (0, None)
+    };
+
+    conn.execute(
+        "INSERT INTO loyalty_ledger (id, customer_id, entry_type, points, remaining, sale_id, return_id, expires_at, note, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            database::generate_id("loy"),
+            entry.customer_id,
+            entry.entry_type,
+            entry.points,
+            remaining,
+            entry.sale_id,
+            entry.return_id,
+            expires_at,
+            entry.note,
+            now.to_rfc3339()
+        ],
+    )
+    .map_err(|e| format!("Failed to record loyalty points: {}", e))?;
+
+    sync_balance(conn, entry.customer_id)
+}
+
+fn consume(conn: &Connection, customer_id: &str, mut points: i64) -> Result<(), String> {
+    let lots: Vec<(String, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, remaining FROM loyalty_ledger
+                 WHERE customer_id = ? AND remaining > 0
+                 ORDER BY COALESCE(expires_at, '9999'), created_at",
+            )
+            .map_err(|e| e.to_string())?;
+        let lots = stmt
+            .query_map(params![customer_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        lots
+    };
+
+    for (id, remaining) in lots {
+        if points == 0 {
+            break;
+        }
+        let used = remaining.min(points);
+        conn.execute(
+            "UPDATE loyalty_ledger SET remaining = remaining - ? WHERE id = ?",
+            params![used, id],
+        )
+        .map_err(|e| e.to_string())?;
+        points -= used;
+    }
+    Ok(())
+}
+
+// Lapse every point past its expiry date that has not been used
+pub fn expire_points(conn: &Connection) -> Result<usize, String> {
+    let expired: Vec<(String, String, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, customer_id, remaining FROM loyalty_ledger
+                 WHERE remaining > 0 AND expires_at IS NOT NULL AND expires_at <= ?",
+            )
+            .map_err(|e| e.to_string())?;
+        let expired = stmt
+            .query_map(params![chrono::Utc::now().to_rfc3339()], |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        expired
+    };
+
+    for (id, customer_id, remaining) in &expired {
+        conn.execute("UPDATE loyalty_ledger SET remaining = 0 WHERE id = ?", params![id])
+            .map_err(|e| e.to_string())?;
+        conn.execute(
+            "INSERT INTO loyalty_ledger (id, customer_id, entry_type, points, remaining, note, created_at)
+             VALUES (?, ?, 'expire', ?, 0, ?, ?)",
+            params![
+                database::generate_id("loy"),
+                customer_id,
+                -remaining,
+                format!("Expired from {}", id),
+                chrono::Utc::now().to_rfc3339()
+            ],
+        )
+        .map_err(|e| format!("Failed to expire loyalty points: {}", e))?;
+        sync_balance(conn, customer_id)?;
+    }
+
+    if !expired.is_empty() {
+        println!("⏰ {} loyalty point lot(s) expired", expired.len());
+    }
+    Ok(expired.len())
+}
+
+pub fn account(conn: &Connection, customer_id: &str) -> Result<LoyaltyAccount, String> {
+    expire_points(conn)?;
+    let program = get_program(conn)?;
+    let balance = balance(conn, customer_id)?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, entry_type, points, sale_id, return_id, expires_at, note, created_at
+             FROM loyalty_ledger WHERE customer_id = ? ORDER BY created_at DESC, rowid DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let entries = stmt
+        .query_map(params![customer_id], |row| {
+            Ok(LedgerEntry {
+                id: row.get(0)?,
+                entry_type: row.get(1)?,
+                points: row.get(2)?,
+                sale_id: row.get(3)?,
+                return_id: row.get(4)?,
+                expires_at: row.get(5)?,
+                note: row.get(6)?,
+                created_at: row.get(7)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(LoyaltyAccount {
+        customer_id: customer_id.to_string(),
+        balance,
+        balance_value_minor: balance.max(0) * program.point_value_minor,
+        entries,
+    })
+}
+
+// Pay part of a sale with points. The amount is rounded up to whole points.
+pub fn redeem(conn: &Connection, customer_id: Option<&str>, sale_id: &str, amount_minor: i64) -> Result<i64, String> {
+    let customer_id = customer_id.ok_or_else(|| "Paying with points needs a customer on the sale".to_string())?;
+    let program = get_program(conn)?;
+    if !program.enabled {
+        return Err("The loyalty program is not enabled".to_string());
+    }
+
+    expire_points(conn)?;
+    let points = (amount_minor + program.point_value_minor - 1) / program.point_value_minor;
+    let available = balance(conn, customer_id)?;
+    if points > available {
+        return Err(format!("Customer has {} points; {} needed", available, points));
+    }
+
+    add_entry(
+        conn,
+        &program,
+        NewEntry {
+            customer_id,
+            entry_type: "redeem",
+            points: -points,
+            sale_id: Some(sale_id),
+            return_id: None,
+            note: None,
+        },
+    )?;
+    Ok(points)
+}
+
+// Award points for a sale. Each line earns on what was paid for it, scaled
+// by its category's multiplier, or the nearest ancestor's that isn't 1x; the
+// part paid with points earns nothing.
+pub fn earn_for_sale(
+    conn: &Connection,
+    sale_id: &str,
+    customer_id: Option<&str>,
+    minor_units_per_unit: i64,
+) -> Result<i64, String> {
+    let customer_id = match customer_id {
+        Some(id) => id,
+        None => return Ok(0),
+    };
+    let program = get_program(conn)?;
+    if !program.enabled || program.points_per_unit == 0 {
+        return Ok(0);
+    }
+
+    let (weighted, total, paid_with_points): (i64, i64, i64) = conn
+        .query_row(
+            "SELECT
+                COALESCE((SELECT SUM(COALESCE(si.paid_minor, si.total_minor - si.discount_minor)
+                                     * COALESCE(d.loyalty_multiplier_bp, ?2) / ?2)
+                          FROM sale_items si
+                          JOIN products p ON p.id = si.product_id
+                          LEFT JOIN category_defaults d ON d.category_id = p.category_id
+                          WHERE si.sale_id = ?1), 0),
+                (SELECT total_minor FROM sales WHERE id = ?1),
+                COALESCE((SELECT SUM(amount_minor) FROM payments WHERE sale_id = ?1 AND method = 'loyalty'), 0)",
+            params![sale_id, FULL_BP],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .map_err(|e| e.to_string())?;
+    if total <= 0 {
+        return Ok(0);
+    }
+
+    let earning = weighted as i128 * (total - paid_with_points) as i128 / total as i128;
+    let points = (earning * program.points_per_unit as i128 / minor_units_per_unit as i128) as i64;
+
+    add_entry(
+        conn,
+        &program,
+        NewEntry {
+            customer_id,
+            entry_type: "earn",
+            points,
+            sale_id: Some(sale_id),
+            return_id: None,
+            note: None,
+        },
+    )?;
+    Ok(points)
+}
+
+fn sale_points(conn: &Connection, sale_id: &str, entry_types: &str) -> Result<i64, String> {
+    conn.query_row(
+        &format!(
+            "SELECT COALESCE(SUM(points), 0) FROM loyalty_ledger WHERE sale_id = ? AND entry_type IN ({})",
+            entry_types
+        ),
+        params![sale_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+// Undo the loyalty side of a return or void. `refund_minor` of
+// `sale_total_minor` is being given back; `everything` settles any rounding.
+pub fn reverse_for_sale(
+    conn: &Connection,
+    sale_id: &str,
+    return_id: Option<&str>,
+    refund_minor: i64,
+    sale_total_minor: i64,
+    everything: bool,
+) -> Result<ReturnAdjustment, String> {
+    let customer_id: Option<String> = conn
+        .query_row("SELECT customer_id FROM sales WHERE id = ?", params![sale_id], |row| row.get(0))
+        .optional()
+        .map_err(|e| e.to_string())?
+        .flatten();
+    let customer_id = match customer_id {
+        Some(id) => id,
+        None => return Ok(ReturnAdjustment::default()),
+    };
+    let program = get_program(conn)?;
+
+    let share = |amount: i64| -> i64 {
+        if everything || sale_total_minor == 0 {
+            amount
+        } else {
+            (amount as i128 * refund_minor as i128 / sale_total_minor as i128) as i64
+        }
+    };
+
+    // Earned points still standing for this sale, and points spent on it
+    // that have not been given back yet
+    let earned = sale_points(conn, sale_id, "'earn', 'reverse'")?;
+    let redeemed = -sale_points(conn, sale_id, "'redeem', 'restore'")?;
+    let points_paid: i64 = conn
+        .query_row(
+            "SELECT COALESCE(SUM(amount_minor), 0) FROM payments WHERE sale_id = ? AND method = 'loyalty'",
+            params![sale_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    let restored_before: i64 = conn
+        .query_row(
+            "SELECT COALESCE(SUM(loyalty_refund_minor), 0) FROM returns WHERE sale_id = ?",
+            params![sale_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    let (points_reversed, points_restored, restored_value_minor) = if everything {
+        (earned, redeemed, points_paid - restored_before)
+    } else {
+        let total_earned = sale_points(conn, sale_id, "'earn'")?;
+        let total_redeemed = -sale_points(conn, sale_id, "'redeem'")?;
+        (
+            share(total_earned).min(earned),
+            share(total_redeemed).min(redeemed),
+            share(points_paid).min(points_paid - restored_before),
+        )
+    };
+
+    add_entry(
+        conn,
+        &program,
+        NewEntry {
+            customer_id: &customer_id,
+            entry_type: "reverse",
+            points: -points_reversed,
+            sale_id: Some(sale_id),
+            return_id,
+            note: None,
+        },
+    )?;
+    add_entry(
+        conn,
+        &program,
+        NewEntry {
+            customer_id: &customer_id,
+            entry_type: "restore",
+            points: points_restored,
+            sale_id: Some(sale_id),
+            return_id,
+            note: None,
+        },
+    )?;
+
+    Ok(ReturnAdjustment {
+        points_reversed,
+        points_restored,
+        restored_value_minor,
+    })
+}
@@ -1,17 +1,48 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+mod audit;
+mod barcode;
+mod carts;
+mod categories;
 mod commands;
+mod config;
+mod costing;
 mod database;
+mod inventory;
+mod kits;
+mod ledger;
+mod lots;
+mod loyalty;
+mod money;
+mod payments;
 mod printer;
+mod promotions;
+mod purchasing;
+mod receivables;
+mod replenishment;
+mod returns;
+mod sales;
+mod search;
+mod session;
+mod shifts;
+mod stocktake;
+mod stored_value;
+mod tax;
+mod training;
+mod units;
+mod variants;
 
 fn main() {
     tauri::Builder::default()
-        .setup(|_app| {
+        .setup(|app| {
             // Initialize database
             database::init_database()?;
             
             // Setup printer
             printer::init_printer()?;
+
+            // Tell the UI when products run low
+            replenishment::watch_low_stock(app.handle().clone());
             
             println!("✅ Glass POS initialized successfully!");
             Ok(())
@@ -27,9 +58,155 @@ fn main() {
                         
             // User management
             commands::login_user,
+            commands::logout_user,
             commands::get_users,
             commands::create_user,
+
+            // Store profiles
+            commands::list_store_profiles,
+            commands::get_active_profile,
+
+            // Training mode
+            commands::get_training_status,
+            commands::enter_training_mode,
+            commands::exit_training_mode,
+            commands::reset_training_database,
+
+            // Audit trail
+            commands::get_audit_log,
+            commands::verify_ledger,
             
+            // Product lookup
+            commands::search_products,
+            commands::lookup_barcode,
+            commands::add_product_barcode,
+            commands::remove_product_barcode,
+            commands::list_product_barcodes,
+
+            // Returns
+            commands::create_return,
+            commands::get_return,
+            commands::print_credit_receipt,
+
+            // Promotions
+            commands::price_cart,
+            commands::list_promotions,
+            commands::save_promotion,
+
+            // Tax
+            commands::list_tax_classes,
+            commands::save_tax_class,
+            commands::set_product_tax,
+
+            // Loyalty
+            commands::get_loyalty_program,
+            commands::save_loyalty_program,
+            commands::set_category_loyalty_multiplier,
+            commands::get_loyalty_account,
+
+            // Inventory
+            commands::stock_history,
+            commands::adjust_stock,
+            commands::verify_stock,
+
+            // Stocktakes
+            commands::open_stocktake,
+            commands::list_stocktakes,
+            commands::record_stock_count,
+            commands::get_stocktake_report,
+            commands::post_stocktake,
+            commands::cancel_stocktake,
+
+            // Lots and expiry
+            commands::set_lot_tracking,
+            commands::list_product_lots,
+            commands::get_expiry_alerts,
+            commands::write_off_expired_lots,
+
+            // Units of measure and packs
+            commands::set_product_unit,
+            commands::add_product_pack,
+            commands::list_product_packs,
+            commands::remove_product_pack,
+
+            // Costing
+            commands::get_costing_settings,
+            commands::set_costing_method,
+            commands::get_inventory_valuation,
+
+            // Kits
+            commands::set_kit_components,
+            commands::get_kit,
+            commands::list_kits,
+            commands::assemble_kits,
+
+            // Categories
+            commands::get_category_tree,
+            commands::move_category,
+            commands::merge_category,
+            commands::set_category_defaults,
+            commands::set_product_promotions,
+            commands::set_low_stock_threshold,
+            commands::inherit_low_stock_threshold,
+            commands::get_category_sales,
+
+            // Product variants
+            commands::create_variant_matrix,
+            commands::list_product_variants,
+            commands::update_product_variant,
+            commands::get_variant_sales,
+
+            // Purchasing
+            commands::create_purchase_order,
+            commands::list_purchase_orders,
+            commands::get_purchase_order,
+            commands::send_purchase_order,
+            commands::close_purchase_order,
+            commands::receive_goods,
+            commands::record_supplier_invoice,
+            commands::pay_supplier_invoice,
+            commands::list_payables,
+
+            // Replenishment
+            commands::get_low_stock_alerts,
+            commands::set_supplier_lead_time,
+            commands::set_supplier_product,
+            commands::list_supplier_products,
+            commands::remove_supplier_product,
+            commands::get_reorder_suggestions,
+            commands::create_reorder_purchase_orders,
+
+            // Customer accounts
+            commands::set_customer_credit_limit,
+            commands::receive_account_payment,
+            commands::get_customer_account,
+            commands::get_receivables_aging,
+            commands::get_customer_statement,
+            commands::print_customer_statement,
+
+            // Gift cards and store credit
+            commands::issue_gift_card,
+            commands::top_up_gift_card,
+            commands::check_gift_card_balance,
+            commands::expire_gift_cards,
+            commands::get_stored_value_history,
+            commands::get_customer_store_credit,
+
+            // Sales and payments
+            commands::create_sale,
+            commands::print_sale_receipt,
+            commands::get_sale_payments,
+            commands::get_shift_reconciliation,
+
+            // Parked carts
+            commands::park_cart,
+            commands::list_parked_carts,
+            commands::recall_cart,
+
+            // Voids
+            commands::void_sale,
+            commands::list_voided_sales,
+
             // Printer commands
             commands::print_receipt,
             commands::get_printers,
@@ -0,0 +1,18 @@
+use rusqlite::{params, Connection};
+
+// Rates, percentages and multipliers are stored in basis points: 1500 = 15%,
+// 20000 = double
+pub const FULL_BP: i64 = 10_000;
+
+// The customers row keeps a copy of each of the customer's ledger balances
+// (store credit, loyalty points, account balance) for screens that list
+// customers without summing their ledgers. Each ledger refreshes its copy
+// here after every entry; the ledger itself stays the record.
+pub fn mirror_customer_balance(conn: &Connection, customer_id: &str, column: &str, balance: i64) -> Result<(), String> {
+    conn.execute(
+        &format!("UPDATE customers SET {} = ?, updated_at = ? WHERE id = ?", column),
+        params![balance, chrono::Utc::now().to_rfc3339(), customer_id],
+    )
+    .map_err(|e| format!("Failed to update customers.{}: {}", column, e))?;
+    Ok(())
+}
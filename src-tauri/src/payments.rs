@@ -0,0 +1,249 @@
+use crate::database;
+use crate::loyalty;
+use crate::receivables;
+use crate::stored_value;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+
+pub const CASH: &str = "cash";
+pub const CARD: &str = "card";
+pub const WALLET: &str = "wallet";
+pub const STORE_CREDIT: &str = "store_credit";
+pub const GIFT_CARD: &str = "gift_card";
+pub const LOYALTY: &str = "loyalty";
+// Charged to the customer's account and paid later
+pub const ON_ACCOUNT: &str = "on_account";
+
+pub const TENDER_METHODS: &[&str] = &[CASH, CARD, WALLET, STORE_CREDIT, GIFT_CARD, LOYALTY, ON_ACCOUNT];
+
+// sales.payment_method when more than one kind of tender was used
+pub const SPLIT: &str = "split";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Tender {
+    pub method: String,
+    pub amount_minor: i64,
+    // Card authorisation, wallet transaction or gift card number
+    #[serde(default)]
+    pub reference: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PaymentRecord {
+    pub id: String,
+    pub sale_id: String,
+    pub method: String,
+    // What the tender contributed to the sale, after change
+    pub amount_minor: i64,
+    // What the customer handed over
+    pub tendered_minor: i64,
+    pub change_minor: i64,
+    pub reference: Option<String>,
+    pub created_at: String,
+}
+
+// Tenders checked against a sale total, ready to be written
+#[derive(Debug)]
+pub struct Settlement {
+    pub lines: Vec<SettledTender>,
+    pub change_minor: i64,
+}
+
+#[derive(Debug)]
+pub struct SettledTender {
+    pub tender: Tender,
+    pub applied_minor: i64,
+    pub change_minor: i64,
+}
+
+impl Settlement {
+    // A single method, or "split" when several kinds were used
+    pub fn payment_method(&self) -> String {
+        let first = &self.lines[0].tender.method;
+        if self.lines.iter().all(|l| &l.tender.method == first) {
+            first.clone()
+        } else {
+            SPLIT.to_string()
+        }
+    }
+}
+
+// Tenders must cover the total exactly, except that cash may overpay and
+// the difference is handed back as change. Card, wallet, store credit, gift
+// card and loyalty amounts can never exceed what is still owed.
+pub fn settle(total_minor: i64, tenders: &[Tender]) -> Result<Settlement, String> {
+    if tenders.is_empty() {
+        return Err("At least one payment is required".to_string());
+    }
+
+    for tender in tenders {
+        if !TENDER_METHODS.contains(&tender.method.as_str()) {
+            return Err(format!("Unknown payment method: {}", tender.method));
+        }
+        if tender.amount_minor <= 0 {
+            return Err("Payment amounts must be positive".to_string());
+        }
+        let has_reference = matches!(tender.reference.as_deref(), Some(r) if !r.trim().is_empty());
+        if tender.method == GIFT_CARD && !has_reference {
+            return Err("Gift card payments need the card number".to_string());
+        }
+    }
+
+    let non_cash: i64 = tenders.iter().filter(|t| t.method != CASH).map(|t| t.amount_minor).sum();
+    let cash: i64 = tenders.iter().filter(|t| t.method == CASH).map(|t| t.amount_minor).sum();
+
+    if non_cash > total_minor {
+        return Err(format!(
+            "Non-cash payments of {} exceed the total of {}",
+            non_cash, total_minor
+        ));
+    }
+    if non_cash + cash < total_minor {
+        return Err(format!(
+            "Payments of {} do not cover the total of {}",
+            non_cash + cash,
+            total_minor
+        ));
+    }
+
+    // Change comes out of the cash tenders, last one first
+    let change_minor = non_cash + cash - total_minor;
+    let mut remaining_change = change_minor;
+    let mut lines: Vec<SettledTender> = tenders
+        .iter()
+        .map(|t| SettledTender {
+            tender: t.clone(),
+            applied_minor: t.amount_minor,
+            change_minor: 0,
+        })
+        .collect();
+
+    for line in lines.iter_mut().rev().filter(|l| l.tender.method == CASH) {
+        if remaining_change == 0 {
+            break;
+        }
+        let given = remaining_change.min(line.applied_minor);
+        line.applied_minor -= given;
+        line.change_minor = given;
+        remaining_change -= given;
+    }
+
+    Ok(Settlement { lines, change_minor })
+}
+
+pub fn record_payments(
+    conn: &Connection,
+    sale_id: &str,
+    customer_id: Option<&str>,
+    settlement: &Settlement,
+) -> Result<Vec<PaymentRecord>, String> {
+    let created_at = chrono::Utc::now().to_rfc3339();
+    let mut records = Vec::new();
+
+    for line in &settlement.lines {
+        match line.tender.method.as_str() {
+            STORE_CREDIT => stored_value::use_store_credit(conn, customer_id, line.applied_minor, sale_id)?,
+            GIFT_CARD => {
+                let code = line.tender.reference.as_deref().unwrap_or_default();
+                stored_value::redeem_gift_card(conn, code, line.applied_minor, sale_id)?;
+            }
+            ON_ACCOUNT => receivables::charge_sale(conn, customer_id, sale_id, line.applied_minor)?,
+            LOYALTY => {
+                loyalty::redeem(conn, customer_id, sale_id, line.applied_minor)?;
+            }
+            _ => {}
+        }
+
+        let record = PaymentRecord {
+            id: database::generate_id("pay"),
+            sale_id: sale_id.to_string(),
+            method: line.tender.method.clone(),
+            amount_minor: line.applied_minor,
+            tendered_minor: line.tender.amount_minor,
+            change_minor: line.change_minor,
+            reference: line.tender.reference.clone(),
+            created_at: created_at.clone(),
+        };
+        conn.execute(
+            "INSERT INTO payments (id, sale_id, method, amount_minor, tendered_minor, change_minor, reference, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                record.id,
+                record.sale_id,
+                record.method,
+                record.amount_minor,
+                record.tendered_minor,
+                record.change_minor,
+                record.reference,
+                record.created_at
+            ],
+        )
+        .map_err(|e| format!("Failed to record payment: {}", e))?;
+        records.push(record);
+    }
+
+    Ok(records)
+}
+
+pub fn payments_for_sale(conn: &Connection, sale_id: &str) -> Result<Vec<PaymentRecord>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, sale_id, method, amount_minor, tendered_minor, change_minor, reference, created_at
+             FROM payments WHERE sale_id = ? ORDER BY rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let payments = stmt
+        .query_map(params![sale_id], |row| {
+            Ok(PaymentRecord {
+                id: row.get(0)?,
+                sale_id: row.get(1)?,
+                method: row.get(2)?,
+                amount_minor: row.get(3)?,
+                tendered_minor: row.get(4)?,
+                change_minor: row.get(5)?,
+                reference: row.get(6)?,
+                created_at: row.get(7)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(payments)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tender(method: &str, amount_minor: i64) -> Tender {
+        Tender {
+            method: method.to_string(),
+            amount_minor,
+            reference: None,
+        }
+    }
+
+    #[test]
+    fn change_comes_from_the_last_cash_tenders() {
+        let tenders = [tender(CASH, 100), tender(CARD, 300), tender(CASH, 150)];
+        let settlement = settle(350, &tenders).unwrap();
+        assert_eq!(settlement.change_minor, 200);
+        let applied: Vec<(i64, i64)> = settlement.lines.iter().map(|l| (l.applied_minor, l.change_minor)).collect();
+        assert_eq!(applied, vec![(50, 50), (300, 0), (0, 150)]);
+        assert_eq!(settlement.payment_method(), SPLIT);
+    }
+
+    #[test]
+    fn exact_tenders_give_no_change() {
+        let settlement = settle(500, &[tender(CASH, 200), tender(CASH, 300)]).unwrap();
+        assert_eq!(settlement.change_minor, 0);
+        assert_eq!(settlement.payment_method(), CASH);
+    }
+
+    #[test]
+    fn only_cash_may_overpay() {
+        assert!(settle(500, &[tender(CARD, 600)]).is_err());
+        assert!(settle(500, &[tender(CARD, 300), tender(CASH, 100)]).is_err());
+        assert!(settle(500, &[tender(CASH, -100), tender(CASH, 600)]).is_err());
+    }
+}
@@ -1,5 +1,11 @@
 use serde::{Deserialize, Serialize};
-use std::process::Command;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use crate::training::TRAINING_BANNER;
+
+const RECEIPT_WIDTH: usize = 42;
+// Columns of a monospaced A4 page
+const STATEMENT_WIDTH: usize = 80;
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Receipt {
@@ -12,13 +18,70 @@ pub struct Receipt {
     pub discount: f64,
     pub total: f64,
     pub currency: String,
+    // Printed above the items, e.g. "CREDIT NOTE RET-000012"
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub footer: Option<String>,
+    // Tax per rate; when empty the single `tax` figure is printed
+    #[serde(default)]
+    pub tax_lines: Vec<ReceiptTaxLine>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReceiptTaxLine {
+    pub label: String,
+    pub rate_percent: f64,
+    pub taxable: f64,
+    pub tax: f64,
+}
+
+// Store details for receipts the backend builds itself
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ReceiptHeader {
+    pub business_name: String,
+    pub address: Option<String>,
+    pub phone: Option<String>,
+    pub currency: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ReceiptItem {
     pub name: String,
-    pub quantity: i32,
+    pub quantity: f64,
     pub price: f64,
+    // Printed after the quantity, e.g. "kg" or "case"
+    #[serde(default)]
+    pub unit: Option<String>,
+}
+
+// A customer account statement, printed on A4 rather than the receipt roll
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatementDocument {
+    pub business_name: String,
+    pub address: Option<String>,
+    pub phone: Option<String>,
+    pub currency: String,
+    pub customer_name: String,
+    pub customer_address: Option<String>,
+    pub statement_date: String,
+    pub period: String,
+    pub credit_limit: f64,
+    pub opening_balance: f64,
+    pub lines: Vec<StatementLine>,
+    pub closing_balance: f64,
+    // Amount owed by age: 0-30, 31-60, 61-90 and over 90 days
+    pub aging: [f64; 4],
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatementLine {
+    pub date: String,
+    pub reference: String,
+    pub description: String,
+    pub debit: f64,
+    pub credit: f64,
+    pub balance: f64,
 }
 
 pub fn init_printer() -> Result<(), String> {
@@ -26,18 +89,200 @@ pub fn init_printer() -> Result<(), String> {
     Ok(())
 }
 
-pub fn print_receipt(receipt: Receipt) -> Result<String, String> {
+fn receipt_line(left: &str, right: &str) -> String {
+    let used = left.chars().count() + right.chars().count();
+    let padding = RECEIPT_WIDTH.saturating_sub(used).max(1);
+    format!("{}{}{}", left, " ".repeat(padding), right)
+}
+
+fn centered(text: &str) -> String {
+    let padding = RECEIPT_WIDTH.saturating_sub(text.chars().count()) / 2;
+    format!("{}{}", " ".repeat(padding), text)
+}
+
+// Render a receipt as plain text for the receipt printer
+pub fn format_receipt(receipt: &Receipt, training: bool) -> String {
+    let mut lines = Vec::new();
+    let rule = "-".repeat(RECEIPT_WIDTH);
+
+    if training {
+        lines.push(centered(TRAINING_BANNER));
+        lines.push(rule.clone());
+    }
+
+    lines.push(centered(&receipt.business_name));
+    if let Some(address) = &receipt.address {
+        lines.push(centered(address));
+    }
+    if let Some(phone) = &receipt.phone {
+        lines.push(centered(phone));
+    }
+    lines.push(rule.clone());
+
+    if let Some(title) = &receipt.title {
+        lines.push(centered(title));
+        lines.push(rule.clone());
+    }
+
+    for item in &receipt.items {
+        lines.push(item.name.clone());
+        let quantity = match &item.unit {
+            Some(unit) => format!("{} {}", item.quantity, unit),
+            None => item.quantity.to_string(),
+        };
+        lines.push(receipt_line(
+            &format!("  {} x {:.2}", quantity, item.price),
+            &format!("{:.2}", item.quantity * item.price),
+        ));
+    }
+
+    lines.push(rule.clone());
+    lines.push(receipt_line("Subtotal", &format!("{:.2}", receipt.subtotal)));
+    if receipt.discount != 0.0 {
+        lines.push(receipt_line("Discount", &format!("-{:.2}", receipt.discount)));
+    }
+    if receipt.tax_lines.is_empty() {
+        lines.push(receipt_line("Tax", &format!("{:.2}", receipt.tax)));
+    }
+    for tax_line in &receipt.tax_lines {
+        lines.push(receipt_line(
+            &format!("{} {}% on {:.2}", tax_line.label, tax_line.rate_percent, tax_line.taxable),
+            &format!("{:.2}", tax_line.tax),
+        ));
+    }
+    lines.push(receipt_line(
+        "TOTAL",
+        &format!("{:.2} {}", receipt.total, receipt.currency),
+    ));
+
+    if let Some(footer) = &receipt.footer {
+        lines.push(rule.clone());
+        lines.push(centered(footer));
+    }
+
+    if training {
+        lines.push(rule);
+        lines.push(centered(TRAINING_BANNER));
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn statement_line(left: &str, right: &str) -> String {
+    let used = left.chars().count() + right.chars().count();
+    let padding = STATEMENT_WIDTH.saturating_sub(used).max(1);
+    format!("{}{}{}", left, " ".repeat(padding), right)
+}
+
+fn amount_or_blank(amount: f64) -> String {
+    if amount == 0.0 { String::new() } else { format!("{:.2}", amount) }
+}
+
+// Render a customer statement as plain text for an A4 printer
+pub fn format_statement(statement: &StatementDocument, training: bool) -> String {
+    let mut lines = Vec::new();
+    let rule = "=".repeat(STATEMENT_WIDTH);
+    let thin_rule = "-".repeat(STATEMENT_WIDTH);
+
+    if training {
+        lines.push(TRAINING_BANNER.to_string());
+        lines.push(rule.clone());
+    }
+
+    lines.push(statement_line(&statement.business_name, "STATEMENT OF ACCOUNT"));
+    lines.push(statement_line(
+        statement.address.as_deref().unwrap_or_default(),
+        &format!("Date: {}", statement.statement_date),
+    ));
+    if let Some(phone) = &statement.phone {
+        lines.push(phone.clone());
+    }
+    lines.push(rule.clone());
+
+    lines.push(statement_line(
+        &format!("To: {}", statement.customer_name),
+        &format!("Period: {}", statement.period),
+    ));
+    if let Some(address) = &statement.customer_address {
+        lines.push(format!("    {}", address));
+    }
+    lines.push(format!("Credit limit: {:.2} {}", statement.credit_limit, statement.currency));
+    lines.push(thin_rule.clone());
+
+    lines.push(format!(
+        "{:<12}{:<14}{:<18}{:>12}{:>12}{:>12}",
+        "Date", "Reference", "Description", "Debit", "Credit", "Balance"
+    ));
+    lines.push(thin_rule.clone());
+    lines.push(format!(
+        "{:<12}{:<14}{:<18}{:>12}{:>12}{:>12.2}",
+        "", "", "Opening balance", "", "", statement.opening_balance
+    ));
+    for line in &statement.lines {
+        let description: String = line.description.chars().take(17).collect();
+        let reference: String = line.reference.chars().take(13).collect();
+        lines.push(format!(
+            "{:<12}{:<14}{:<18}{:>12}{:>12}{:>12.2}",
+            line.date,
+            reference,
+            description,
+            amount_or_blank(line.debit),
+            amount_or_blank(line.credit),
+            line.balance
+        ));
+    }
+    lines.push(thin_rule.clone());
+    lines.push(statement_line(
+        "AMOUNT DUE",
+        &format!("{:.2} {}", statement.closing_balance, statement.currency),
+    ));
+    lines.push(rule.clone());
+
+    lines.push(format!("{:>20}{:>20}{:>20}{:>20}", "0-30 days", "31-60 days", "61-90 days", "Over 90 days"));
+    lines.push(format!(
+        "{:>20.2}{:>20.2}{:>20.2}{:>20.2}",
+        statement.aging[0], statement.aging[1], statement.aging[2], statement.aging[3]
+    ));
+
+    if training {
+        lines.push(rule);
+        lines.push(TRAINING_BANNER.to_string());
+    }
+
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+pub fn print_receipt(receipt: Receipt, training: bool) -> Result<String, String> {
     // Safe printing implementation for Linux
     println!("📄 Printing receipt: {:?}", receipt);
-    
+    let text = format_receipt(&receipt, training);
+    send_to_printer(&text)?;
+    Ok("Receipt printed successfully".to_string())
+}
+
+pub fn print_statement(statement: &StatementDocument, training: bool) -> Result<String, String> {
+    println!("📄 Printing statement for {}", statement.customer_name);
+    send_to_printer(&format_statement(statement, training))?;
+    Ok("Statement printed successfully".to_string())
+}
+
+fn send_to_printer(text: &str) -> Result<(), String> {
     // Use CUPS directly without creating windows
-    match Command::new("lp")
+    let mut child = Command::new("lp")
         .arg("-")
-        .spawn() 
-    {
-        Ok(_) => Ok("Receipt printed successfully".to_string()),
-        Err(e) => Err(format!("Print failed: {}", e))
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Print failed: {}", e))?;
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(text.as_bytes())
+            .map_err(|e| format!("Print failed: {}", e))?;
     }
+
+    Ok(())
 }
 
 pub fn get_available_printers() -> Result<Vec<String>, String> {
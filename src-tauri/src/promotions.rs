@@ -0,0 +1,636 @@
+use crate::categories;
+use crate::database;
+use crate::money::FULL_BP;
+use crate::session;
+use chrono::{DateTime, Datelike, Local, NaiveTime};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+pub const PERCENT_OFF: &str = "percent_off";
+pub const AMOUNT_OFF: &str = "amount_off";
+pub const BUY_X_GET_Y: &str = "buy_x_get_y";
+pub const BUNDLE_PRICE: &str = "bundle_price";
+
+const KINDS: &[&str] = &[PERCENT_OFF, AMOUNT_OFF, BUY_X_GET_Y, BUNDLE_PRICE];
+const SCOPES: &[&str] = &["product", "category", "basket"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Promotion {
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    pub kind: String,
+    pub scope: String,
+    // Product or category id; unused for basket promotions
+    #[serde(default)]
+    pub target_id: Option<String>,
+    // percent_off, and the discount on the "get" items of buy_x_get_y
+    // (0 means free)
+    #[serde(default)]
+    pub percent_bp: i64,
+    // amount_off per unit (once for basket scope), or the bundle price
+    #[serde(default)]
+    pub amount_minor: i64,
+    #[serde(default)]
+    pub buy_quantity: i64,
+    #[serde(default)]
+    pub get_quantity: i64,
+    #[serde(default)]
+    pub bundle_quantity: i64,
+    // Cart subtotal needed before the promotion applies
+    #[serde(default)]
+    pub min_basket_minor: i64,
+    #[serde(default)]
+    pub customer_group: Option<String>,
+    #[serde(default)]
+    pub starts_at: Option<String>,
+    #[serde(default)]
+    pub ends_at: Option<String>,
+    // ISO weekdays, e.g. "5,6" for Friday and Saturday
+    #[serde(default)]
+    pub days_of_week: Option<String>,
+    // "HH:MM" local time; a window may run past midnight
+    #[serde(default)]
+    pub start_time: Option<String>,
+    #[serde(default)]
+    pub end_time: Option<String>,
+    // Higher priority promotions are evaluated first
+    #[serde(default)]
+    pub priority: i64,
+    // Non-stackable promotions only apply to lines with no other promotion
+    // and stop anything else applying after them
+    #[serde(default)]
+    pub stackable: bool,
+    #[serde(default = "default_true")]
+    pub is_active: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Clone)]
+pub struct CartLine {
+    pub product_id: String,
+    pub quantity: i64,
+    pub price_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppliedPromotion {
+    pub promotion_id: String,
+    pub name: String,
+    pub discount_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PricedLine {
+    pub product_id: String,
+    pub quantity: i64,
+    pub price_minor: i64,
+    pub gross_minor: i64,
+    pub discount_minor: i64,
+    pub net_minor: i64,
+    pub promotions: Vec<AppliedPromotion>,
+    // The product's category and every category above it
+    #[serde(skip)]
+    categories: Vec<String>,
+    #[serde(skip)]
+    promotions_allowed: bool,
+    #[serde(skip)]
+    locked: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PricedCart {
+    pub lines: Vec<PricedLine>,
+    pub subtotal_minor: i64,
+    pub discount_minor: i64,
+    pub total_minor: i64,
+}
+
+fn parse_time(value: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(value, "%H:%M").map_err(|_| format!("Invalid time {}, expected HH:MM", value))
+}
+
+fn validate(promotion: &Promotion) -> Result<(), String> {
+    if promotion.name.trim().is_empty() {
+        return Err("Promotion name is required".to_string());
+    }
+    if !KINDS.contains(&promotion.kind.as_str()) {
+        return Err(format!("Unknown promotion kind: {}", promotion.kind));
+    }
+    if !SCOPES.contains(&promotion.scope.as_str()) {
+        return Err(format!("Unknown promotion scope: {}", promotion.scope));
+    }
+    if promotion.scope != "basket" && promotion.target_id.is_none() {
+        return Err(format!("A {} promotion needs a target", promotion.scope));
+    }
+
+    match promotion.kind.as_str() {
+        PERCENT_OFF if promotion.percent_bp <= 0 || promotion.percent_bp > FULL_BP => {
+            Err("Percentage must be between 0 and 100%".to_string())
+        }
+        AMOUNT_OFF if promotion.amount_minor <= 0 => Err("Discount amount must be positive".to_string()),
+        BUY_X_GET_Y if promotion.buy_quantity <= 0 || promotion.get_quantity <= 0 => {
+            Err("Buy and get quantities must be positive".to_string())
+        }
+        BUY_X_GET_Y if promotion.percent_bp < 0 || promotion.percent_bp > FULL_BP => {
+            Err("Percentage must be between 0 and 100%".to_string())
+        }
+        BUNDLE_PRICE if promotion.bundle_quantity < 2 || promotion.amount_minor <= 0 => {
+            Err("A bundle needs at least two items and a price".to_string())
+        }
+        _ => Ok(()),
+    }?;
+
+    for time in [&promotion.start_time, &promotion.end_time].into_iter().flatten() {
+        parse_time(time)?;
+    }
+    if let Some(days) = &promotion.days_of_week {
+        for day in days.split(',') {
+            match day.trim().parse::<u32>() {
+                Ok(1..=7) => {}
+                _ => return Err(format!("Invalid day of week: {}", day)),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn promotion_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Promotion> {
+    Ok(Promotion {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        kind: row.get(2)?,
+        scope: row.get(3)?,
+        target_id: row.get(4)?,
+        percent_bp: row.get(5)?,
+        amount_minor: row.get(6)?,
+        buy_quantity: row.get(7)?,
+        get_quantity: row.get(8)?,
+        bundle_quantity: row.get(9)?,
+        min_basket_minor: row.get(10)?,
+        customer_group: row.get(11)?,
+        starts_at: row.get(12)?,
+        ends_at: row.get(13)?,
+        days_of_week: row.get(14)?,
+        start_time: row.get(15)?,
+        end_time: row.get(16)?,
+        priority: row.get(17)?,
+        stackable: row.get(18)?,
+        is_active: row.get(19)?,
+    })
+}
+
+const PROMOTION_COLUMNS: &str = "id, name, kind, scope, target_id, percent_bp, amount_minor, buy_quantity, get_quantity,
+     bundle_quantity, min_basket_minor, customer_group, starts_at, ends_at, days_of_week, start_time, end_time,
+     priority, stackable, is_active";
+
+pub fn list_promotions(conn: &Connection) -> Result<Vec<Promotion>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM promotions ORDER BY priority DESC, created_at",
+            PROMOTION_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let promotions = stmt
+        .query_map([], promotion_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(promotions)
+}
+
+// Create a promotion, or replace it when the id already exists
+pub fn save_promotion(conn: &Connection, promotion: &Promotion) -> Result<Promotion, String> {
+    session::require_role(&["admin", "manager"])?;
+    validate(promotion)?;
+
+    let mut promotion = promotion.clone();
+    if promotion.id.is_empty() {
+        promotion.id = database::generate_id("promo");
+    }
+    let now = chrono::Utc::now().to_rfc3339();
+
+    conn.execute(
+        "INSERT INTO promotions (id, name, kind, scope, target_id, percent_bp, amount_minor, buy_quantity, get_quantity,
+                                 bundle_quantity, min_basket_minor, customer_group, starts_at, ends_at, days_of_week,
+                                 start_time, end_time, priority, stackable, is_active, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21)
+         ON CONFLICT(id) DO UPDATE SET
+            name = excluded.name, kind = excluded.kind, scope = excluded.scope, target_id = excluded.target_id,
+            percent_bp = excluded.percent_bp, amount_minor = excluded.amount_minor,
+            buy_quantity = excluded.buy_quantity, get_quantity = excluded.get_quantity,
+            bundle_quantity = excluded.bundle_quantity, min_basket_minor = excluded.min_basket_minor,
+            customer_group = excluded.customer_group, starts_at = excluded.starts_at, ends_at = excluded.ends_at,
+            days_of_week = excluded.days_of_week, start_time = excluded.start_time, end_time = excluded.end_time,
+            priority = excluded.priority, stackable = excluded.stackable, is_active = excluded.is_active,
+            updated_at = excluded.created_at",
+        params![
+            promotion.id,
+            promotion.name,
+            promotion.kind,
+            promotion.scope,
+            promotion.target_id,
+            promotion.percent_bp,
+            promotion.amount_minor,
+            promotion.buy_quantity,
+            promotion.get_quantity,
+            promotion.bundle_quantity,
+            promotion.min_basket_minor,
+            promotion.customer_group,
+            promotion.starts_at,
+            promotion.ends_at,
+            promotion.days_of_week,
+            promotion.start_time,
+            promotion.end_time,
+            promotion.priority,
+            promotion.stackable,
+            promotion.is_active,
+            now
+        ],
+    )
+    .map_err(|e| format!("Failed to save promotion: {}", e))?;
+
+    println!("🏷️ Promotion saved: {}", promotion.name);
+    Ok(promotion)
+}
+
+// Dates may be given as YYYY-MM-DD (whole day) or a full local timestamp
+fn within_dates(promotion: &Promotion, now: &DateTime<Local>) -> bool {
+    let today = now.format("%Y-%m-%d").to_string();
+    let timestamp = now.format("%Y-%m-%dT%H:%M:%S").to_string();
+
+    let started = match &promotion.starts_at {
+        Some(start) => start.as_str() <= timestamp.as_str(),
+        None => true,
+    };
+    let not_ended = match &promotion.ends_at {
+        Some(end) if end.len() == 10 => end.as_str() >= today.as_str(),
+        Some(end) => end.as_str() >= timestamp.as_str(),
+        None => true,
+    };
+    started && not_ended
+}
+
+fn within_hours(promotion: &Promotion, now: &DateTime<Local>) -> bool {
+    if let Some(days) = &promotion.days_of_week {
+        let weekday = now.weekday().number_from_monday().to_string();
+        if !days.split(',').any(|d| d.trim() == weekday) {
+            return false;
+        }
+    }
+
+    let start = promotion.start_time.as_deref().and_then(|t| parse_time(t).ok());
+    let end = promotion.end_time.as_deref().and_then(|t| parse_time(t).ok());
+    let time = now.time();
+    match (start, end) {
+        (Some(start), Some(end)) if start <= end => time >= start && time < end,
+        (Some(start), Some(end)) => time >= start || time < end,
+        (Some(start), None) => time >= start,
+        (None, Some(end)) => time < end,
+        (None, None) => true,
+    }
+}
+
+fn applies_now(promotion: &Promotion, now: &DateTime<Local>, customer_group: Option<&str>) -> bool {
+    let group_matches = match &promotion.customer_group {
+        Some(group) => customer_group == Some(group.as_str()),
+        None => true,
+    };
+    group_matches && within_dates(promotion, now) && within_hours(promotion, now)
+}
+
+// Share an amount over lines in proportion to their weights. The last line
+// takes the rounding so the parts always add up.
+pub fn allocate(total: i64, weights: &[(usize, i64)]) -> Vec<(usize, i64)> {
+    let weight_sum: i64 = weights.iter().map(|(_, w)| w).sum();
+    if weight_sum == 0 || total == 0 {
+        return Vec::new();
+    }
+
+    let mut remaining = total;
+    let mut shares = Vec::with_capacity(weights.len());
+    for (n, (index, weight)) in weights.iter().enumerate() {
+        let share = if n == weights.len() - 1 {
+            remaining
+        } else {
+            (total as i128 * *weight as i128 / weight_sum as i128) as i64
+        };
+        remaining -= share;
+        shares.push((*index, share));
+    }
+    shares
+}
+
+fn net(line: &PricedLine) -> i64 {
+    line.gross_minor - line.discount_minor
+}
+
+// The eligible lines as (line, net, units). Units are counted per line
+// rather than listed one by one, so a huge quantity costs no more to price
+// than a small one.
+fn unit_groups(lines: &[PricedLine], eligible: &[usize]) -> Vec<(usize, i64, i64)> {
+    eligible
+        .iter()
+        .map(|&index| (index, &lines[index]))
+        .filter(|(_, line)| line.quantity > 0)
+        .map(|(index, line)| (index, net(line), line.quantity))
+        .collect()
+}
+
+// Orders groups by unit price, cross-multiplying so a net that doesn't
+// divide evenly by its units is still compared exactly
+fn by_unit_price(a: &(usize, i64, i64), b: &(usize, i64, i64)) -> std::cmp::Ordering {
+    (a.1 as i128 * b.2 as i128).cmp(&(b.1 as i128 * a.2 as i128))
+}
+
+// The first `count` units of the groups, in the order given, as (line,
+// value, units). A line's net is shared over its units with allocate, so
+// taking every unit is worth exactly the net.
+fn take_units(groups: Vec<(usize, i64, i64)>, count: i64) -> Vec<(usize, i64, i64)> {
+    let mut left = count;
+    let mut taken = Vec::new();
+    for (index, net, units) in groups {
+        if left <= 0 {
+            break;
+        }
+        let take = units.min(left);
+        let value = allocate(net, &[(index, take), (index, units - take)])
+            .first()
+            .map_or(0, |&(_, share)| share);
+        taken.push((index, value, take));
+        left -= take;
+    }
+    taken
+}
+
+fn discounts_for(promotion: &Promotion, lines: &[PricedLine], eligible: &[usize]) -> Vec<(usize, i64)> {
+    let by_value: Vec<(usize, i64)> = eligible.iter().map(|&i| (i, net(&lines[i]))).collect();
+    let basket = promotion.scope == "basket";
+
+    match promotion.kind.as_str() {
+        PERCENT_OFF if basket => {
+            let total: i64 = by_value.iter().map(|(_, v)| v).sum();
+            allocate(total * promotion.percent_bp / FULL_BP, &by_value)
+        }
+        PERCENT_OFF => by_value
+            .iter()
+            .map(|&(i, value)| (i, value * promotion.percent_bp / FULL_BP))
+            .collect(),
+        AMOUNT_OFF if basket => {
+            let total: i64 = by_value.iter().map(|(_, v)| v).sum();
+            allocate(promotion.amount_minor.min(total), &by_value)
+        }
+        AMOUNT_OFF => eligible
+            .iter()
+            .map(|&i| (i, promotion.amount_minor * lines[i].quantity))
+            .collect(),
+        BUY_X_GET_Y => {
+            // The cheapest units are the ones given away
+            let mut groups = unit_groups(lines, eligible);
+            groups.sort_by(by_unit_price);
+            let units: i64 = groups.iter().map(|(_, _, units)| units).sum();
+            let group = promotion.buy_quantity + promotion.get_quantity;
+            let free = units / group * promotion.get_quantity;
+            let percent = if promotion.percent_bp == 0 { FULL_BP } else { promotion.percent_bp };
+            take_units(groups, free)
+                .into_iter()
+                .map(|(i, value, _)| (i, value * percent / FULL_BP))
+                .collect()
+        }
+        BUNDLE_PRICE => {
+            let mut groups = unit_groups(lines, eligible);
+            groups.sort_by(|a, b| by_unit_price(b, a));
+            let units: i64 = groups.iter().map(|(_, _, units)| units).sum();
+            let bundles = units / promotion.bundle_quantity;
+            let bundled: Vec<(usize, i64)> = take_units(groups, bundles * promotion.bundle_quantity)
+                .into_iter()
+                .map(|(i, value, _)| (i, value))
+                .collect();
+            let value: i64 = bundled.iter().map(|(_, v)| v).sum();
+            let saving = value - bundles * promotion.amount_minor;
+            if saving <= 0 {
+                Vec::new()
+            } else {
+                allocate(saving, &bundled)
+            }
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn active_promotions(conn: &Connection) -> Result<Vec<Promotion>, String> {
+    let mut stmt = conn
+        .prepare_cached(&format!(
+            "SELECT {} FROM promotions WHERE is_active = 1 ORDER BY priority DESC, created_at",
+            PROMOTION_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let promotions = stmt
+        .query_map([], promotion_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(promotions)
+}
+
+// Apply every promotion running at `now` to the cart. Each till calls this
+// through create_sale (or price_cart for a preview) so they all agree.
+pub fn price_cart(
+    conn: &Connection,
+    cart: &[CartLine],
+    customer_id: Option<&str>,
+    now: DateTime<Local>,
+) -> Result<PricedCart, String> {
+    let mut lines = Vec::with_capacity(cart.len());
+    for line in cart {
+        let (category_id, promotions_allowed): (Option<String>, bool) = conn
+            .query_row(
+                "SELECT p.category_id, COALESCE(p.promotions_allowed, d.promotions_allowed, 1)
+                 FROM products p LEFT JOIN category_defaults d ON d.category_id = p.category_id
+                 WHERE p.id = ?",
+                params![line.product_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Product {} not found", line.product_id))?;
+        let categories = match category_id {
+            Some(category_id) => categories::ancestors(conn, &category_id)?,
+            None => Vec::new(),
+        };
+        let gross_minor = line.price_minor * line.quantity;
+        lines.push(PricedLine {
+            product_id: line.product_id.clone(),
+            quantity: line.quantity,
+            price_minor: line.price_minor,
+            gross_minor,
+            discount_minor: 0,
+            net_minor: gross_minor,
+            promotions: Vec::new(),
+            categories,
+            promotions_allowed,
+            locked: false,
+        });
+    }
+
+    let customer_group: Option<String> = match customer_id {
+        Some(id) => conn
+            .query_row("SELECT customer_group FROM customers WHERE id = ?", params![id], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?
+            .flatten(),
+        None => None,
+    };
+
+    let subtotal_minor: i64 = lines.iter().map(|l| l.gross_minor).sum();
+
+    for promotion in active_promotions(conn)? {
+        if !applies_now(&promotion, &now, customer_group.as_deref()) || subtotal_minor < promotion.min_basket_minor {
+            continue;
+        }
+
+        let eligible: Vec<usize> = lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| {
+                let in_scope = match promotion.scope.as_str() {
+                    "product" => promotion.target_id.as_deref() == Some(line.product_id.as_str()),
+                    "category" => promotion.target_id.as_ref().is_some_and(|id| line.categories.contains(id)),
+                    _ => true,
+                };
+                in_scope
+                    && line.promotions_allowed
+                    && !line.locked
+                    && (promotion.stackable || line.promotions.is_empty())
+            })
+            .map(|(i, _)| i)
+            .collect();
+        if eligible.is_empty() {
+            continue;
+        }
+
+        for (index, discount) in discounts_for(&promotion, &lines, &eligible) {
+            let line = &mut lines[index];
+            let discount = discount.min(net(line));
+            if discount <= 0 {
+                continue;
+            }
+            line.discount_minor += discount;
+            line.net_minor = net(line);
+            line.promotions.push(AppliedPromotion {
+                promotion_id: promotion.id.clone(),
+                name: promotion.name.clone(),
+                discount_minor: discount,
+            });
+            if !promotion.stackable {
+                line.locked = true;
+            }
+        }
+    }
+
+    let discount_minor: i64 = lines.iter().map(|l| l.discount_minor).sum();
+    Ok(PricedCart {
+        lines,
+        subtotal_minor,
+        discount_minor,
+        total_minor: subtotal_minor - discount_minor,
+    })
+}
+
+pub fn record_line_promotions(conn: &Connection, sale_item_id: &str, applied: &[AppliedPromotion]) -> Result<(), String> {
+    for applied in applied {
+        conn.execute(
+            "INSERT INTO sale_item_promotions (id, sale_item_id, promotion_id, promotion_name, discount_minor)
+             VALUES (?, ?, ?, ?, ?)",
+            params![
+                database::generate_id("sip"),
+                sale_item_id,
+                applied.promotion_id,
+                applied.name,
+                applied.discount_minor
+            ],
+        )
+        .map_err(|e| format!("Failed to record promotion: {}", e))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn last_line_takes_the_remainder() {
+        assert_eq!(allocate(100, &[(0, 1), (1, 1), (2, 1)]), vec![(0, 33), (1, 33), (2, 34)]);
+        assert_eq!(allocate(10, &[(4, 333), (7, 667)]), vec![(4, 3), (7, 7)]);
+    }
+
+    #[test]
+    fn shares_add_up_to_the_total() {
+        let weights = [(0, 199), (1, 1), (2, 3_001), (3, 77)];
+        for total in [1, 17, 999, 123_457] {
+            let shares = allocate(total, &weights);
+            assert_eq!(shares.iter().map(|(_, s)| s).sum::<i64>(), total);
+        }
+    }
+
+    #[test]
+    fn nothing_to_share() {
+        assert!(allocate(0, &[(0, 5)]).is_empty());
+        assert!(allocate(100, &[(0, 0), (1, 0)]).is_empty());
+        assert!(allocate(100, &[]).is_empty());
+    }
+
+    fn line(quantity: i64, gross_minor: i64, discount_minor: i64) -> PricedLine {
+        PricedLine {
+            product_id: "p".to_string(),
+            quantity,
+            price_minor: gross_minor / quantity,
+            gross_minor,
+            discount_minor,
+            net_minor: gross_minor - discount_minor,
+            promotions: Vec::new(),
+            categories: Vec::new(),
+            promotions_allowed: true,
+            locked: false,
+        }
+    }
+
+    fn promotion(value: serde_json::Value) -> Promotion {
+        let mut value = value;
+        value["name"] = "test".into();
+        value["scope"] = "product".into();
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn free_units_keep_an_earlier_discount_remainder() {
+        // 2699 over 3 units: the two free units are worth 1799, not 2 x 899
+        let lines = [line(3, 3000, 301)];
+        let free = promotion(serde_json::json!({"kind": BUY_X_GET_Y, "buy_quantity": 1, "get_quantity": 2}));
+        assert_eq!(discounts_for(&free, &lines, &[0]), vec![(0, 1799)]);
+
+        let lines = [line(3, 3000, 0)];
+        let whole = promotion(serde_json::json!({"kind": BUY_X_GET_Y, "buy_quantity": 0, "get_quantity": 3}));
+        assert_eq!(discounts_for(&whole, &lines, &[0]), vec![(0, 3000)]);
+    }
+
+    #[test]
+    fn bundles_value_units_at_their_share_of_the_net() {
+        let lines = [line(4, 1000, 1)];
+        let bundle = promotion(serde_json::json!({"kind": BUNDLE_PRICE, "bundle_quantity": 4, "amount_minor": 900}));
+        assert_eq!(discounts_for(&bundle, &lines, &[0]), vec![(0, 99)]);
+    }
+
+    #[test]
+    fn cheapest_units_are_compared_exactly() {
+        // 1000 over 3 units is dearer than 333 for one, though both floor to 333
+        let lines = [line(3, 1000, 0), line(1, 333, 0)];
+        let free = promotion(serde_json::json!({"kind": BUY_X_GET_Y, "buy_quantity": 3, "get_quantity": 1}));
+        assert_eq!(discounts_for(&free, &lines, &[0, 1]), vec![(1, 333)]);
+    }
+}
@@ -0,0 +1,567 @@
+use crate::database;
+use crate::inventory;
+use crate::lots::LotInput;
+use crate::session;
+use crate::units;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+pub const DRAFT: &str = "draft";
+pub const SENT: &str = "sent";
+pub const PARTIALLY_RECEIVED: &str = "partially_received";
+pub const RECEIVED: &str = "received";
+pub const CANCELLED: &str = "cancelled";
+
+// expenses.category of a supplier invoice
+pub const SUPPLIER_INVOICE: &str = "supplier_invoice";
+
+const PURCHASING_ROLES: &[&str] = &["admin", "manager"];
+
+#[derive(Debug, Deserialize)]
+pub struct PurchaseOrderRequest {
+    pub supplier_id: String,
+    pub items: Vec<OrderLine>,
+    #[serde(default)]
+    pub expected_at: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OrderLine {
+    pub product_id: String,
+    pub quantity: i64,
+    // Defaults to the product's current cost
+    #[serde(default)]
+    pub unit_cost_minor: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PurchaseOrderItem {
+    pub id: String,
+    pub product_id: String,
+    pub product_name: String,
+    pub quantity_ordered: i64,
+    pub quantity_received: i64,
+    // Negative once more arrived than was ordered
+    pub quantity_outstanding: i64,
+    pub unit_cost_minor: i64,
+    pub total_minor: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PurchaseOrder {
+    pub id: String,
+    pub po_number: String,
+    pub supplier_id: String,
+    pub supplier_name: String,
+    pub status: String,
+    pub expected_at: Option<String>,
+    pub notes: Option<String>,
+    pub total_minor: i64,
+    pub received_minor: i64,
+    pub invoiced_minor: i64,
+    pub items: Vec<PurchaseOrderItem>,
+    pub created_by: String,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReceiveRequest {
+    pub order_id: String,
+    pub items: Vec<ReceiveLine>,
+    // Accept more than the outstanding quantity of a line
+    #[serde(default)]
+    pub allow_over_receipt: bool,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReceiveLine {
+    pub order_item_id: String,
+    pub quantity: i64,
+    // The cost on the delivery note when it differs from the order
+    #[serde(default)]
+    pub unit_cost_minor: Option<i64>,
+    // Lot number and expiry; required for lot-tracked products
+    #[serde(default)]
+    pub lot: Option<LotInput>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReceivedItem {
+    pub order_item_id: String,
+    pub product_id: String,
+    pub quantity: i64,
+    pub unit_cost_minor: i64,
+    // Units beyond what was still outstanding on the line
+    pub over_quantity: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GoodsReceipt {
+    pub id: String,
+    pub receipt_number: String,
+    pub order_id: String,
+    pub order_status: String,
+    pub items: Vec<ReceivedItem>,
+    pub total_minor: i64,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SupplierInvoiceRequest {
+    pub order_id: String,
+    pub invoice_number: String,
+    pub amount_minor: i64,
+    #[serde(default)]
+    pub due_date: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Payable {
+    pub expense_id: String,
+    pub supplier_id: String,
+    pub supplier_name: String,
+    pub order_id: Option<String>,
+    pub po_number: Option<String>,
+    pub invoice_number: Option<String>,
+    pub amount_minor: i64,
+    // For an order's invoice: what its goods receipts are worth at cost, and
+    // everything invoiced against the order less that. A non-zero variance
+    // is checked with the supplier before paying.
+    pub received_minor: Option<i64>,
+    pub variance_minor: Option<i64>,
+    pub due_date: Option<String>,
+    pub payment_status: String,
+    pub overdue: bool,
+    pub created_at: String,
+}
+
+pub fn create_purchase_order(conn: &Connection, request: &PurchaseOrderRequest) -> Result<PurchaseOrder, String> {
+    let user = session::require_role(PURCHASING_ROLES)?;
+    if request.items.is_empty() {
+        return Err("A purchase order needs at least one item".to_string());
+    }
+    let supplier_exists: bool = conn
+        .query_row("SELECT COUNT(*) > 0 FROM suppliers WHERE id = ?", params![request.supplier_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if !supplier_exists {
+        return Err(format!("Supplier {} not found", request.supplier_id));
+    }
+
+    let order_id = database::generate_id("po");
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM purchase_orders", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let po_number = format!("PO-{:05}", count + 1);
+    let created_at = chrono::Utc::now().to_rfc3339();
+
+    conn.execute(
+        "INSERT INTO purchase_orders (id, po_number, supplier_id, status, expected_at, notes, created_by, created_at)
+         VALUES (?, ?, ?, 'draft', ?, ?, ?, ?)",
+        params![order_id, po_number, request.supplier_id, request.expected_at, request.notes, user.id, created_at],
+    )
+    .map_err(|e| format!("Failed to create purchase order: {}", e))?;
+
+    for line in &request.items {
+        if line.quantity <= 0 {
+            return Err("Order quantities must be positive".to_string());
+        }
+        let cost_minor: i64 = conn
+            .query_row("SELECT cost_minor FROM products WHERE id = ?", params![line.product_id], |row| row.get(0))
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Product {} not found", line.product_id))?;
+        let unit_cost_minor = line.unit_cost_minor.unwrap_or(cost_minor);
+        if unit_cost_minor < 0 {
+            return Err("Unit costs cannot be negative".to_string());
+        }
+
+        conn.execute(
+            "INSERT INTO purchase_order_items (id, order_id, product_id, quantity_ordered, unit_cost_minor)
+             VALUES (?, ?, ?, ?, ?)",
+            params![database::generate_id("poitem"), order_id, line.product_id, line.quantity, unit_cost_minor],
+        )
+        .map_err(|e| format!("Failed to add purchase order item: {}", e))?;
+    }
+
+    println!("📝 Purchase order {} drafted by {}", po_number, user.username);
+    get_purchase_order(conn, &order_id)
+}
+
+fn load_items(conn: &Connection, order_id: &str) -> Result<Vec<PurchaseOrderItem>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT i.id, i.product_id, p.name, i.quantity_ordered, i.quantity_received, i.unit_cost_minor,
+                    p.quantity_decimals
+             FROM purchase_order_items i JOIN products p ON p.id = i.product_id
+             WHERE i.order_id = ? ORDER BY i.rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let items = stmt
+        .query_map(params![order_id], |row| {
+            let quantity_ordered: i64 = row.get(3)?;
+            let quantity_received: i64 = row.get(4)?;
+            let unit_cost_minor: i64 = row.get(5)?;
+            Ok(PurchaseOrderItem {
+                id: row.get(0)?,
+                product_id: row.get(1)?,
+                product_name: row.get(2)?,
+                quantity_ordered,
+                quantity_received,
+                quantity_outstanding: quantity_ordered - quantity_received,
+                unit_cost_minor,
+                total_minor: units::line_total(unit_cost_minor, quantity_ordered, row.get(6)?),
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(items)
+}
+
+pub fn get_purchase_order(conn: &Connection, order_id: &str) -> Result<PurchaseOrder, String> {
+    let mut order = conn
+        .query_row(
+            "SELECT o.id, o.po_number, o.supplier_id, s.name, o.status, o.expected_at, o.notes, o.created_by, o.created_at,
+                    (SELECT COALESCE(SUM(line_total(ri.unit_cost_minor, ri.quantity, p.quantity_decimals)), 0)
+                     FROM goods_receipt_items ri JOIN goods_receipts r ON r.id = ri.receipt_id
+                     JOIN products p ON p.id = ri.product_id
+                     WHERE r.order_id = o.id),
+                    (SELECT COALESCE(SUM(amount_minor), 0) FROM expenses WHERE purchase_order_id = o.id)
+             FROM purchase_orders o JOIN suppliers s ON s.id = o.supplier_id
+             WHERE o.id = ?",
+            params![order_id],
+            |row| {
+                Ok(PurchaseOrder {
+                    id: row.get(0)?,
+                    po_number: row.get(1)?,
+                    supplier_id: row.get(2)?,
+                    supplier_name: row.get(3)?,
+                    status: row.get(4)?,
+                    expected_at: row.get(5)?,
+                    notes: row.get(6)?,
+                    total_minor: 0,
+                    received_minor: row.get(9)?,
+                    invoiced_minor: row.get(10)?,
+                    items: Vec::new(),
+                    created_by: row.get(7)?,
+                    created_at: row.get(8)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Purchase order {} not found", order_id))?;
+
+    order.items = load_items(conn, order_id)?;
+    order.total_minor = order.items.iter().map(|i| i.total_minor).sum();
+    Ok(order)
+}
+
+pub fn list_purchase_orders(conn: &Connection, status: Option<&str>) -> Result<Vec<PurchaseOrder>, String> {
+    let ids: Vec<String> = {
+        let mut stmt = conn
+            .prepare("SELECT id FROM purchase_orders WHERE ?1 IS NULL OR status = ?1 ORDER BY created_at DESC")
+            .map_err(|e| e.to_string())?;
+        let ids = stmt
+            .query_map(params![status], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        ids
+    };
+
+    ids.iter().map(|id| get_purchase_order(conn, id)).collect()
+}
+
+fn set_status(conn: &Connection, order_id: &str, from: &[&str], to: &str) -> Result<PurchaseOrder, String> {
+    let order = get_purchase_order(conn, order_id)?;
+    if !from.contains(&order.status.as_str()) {
+        return Err(format!("Purchase order {} is {} and cannot become {}", order.po_number, order.status, to));
+    }
+    conn.execute(
+        "UPDATE purchase_orders SET status = ?, updated_at = ? WHERE id = ?",
+        params![to, chrono::Utc::now().to_rfc3339(), order_id],
+    )
+    .map_err(|e| format!("Failed to update purchase order: {}", e))?;
+    get_purchase_order(conn, order_id)
+}
+
+pub fn send_purchase_order(conn: &Connection, order_id: &str) -> Result<PurchaseOrder, String> {
+    session::require_role(PURCHASING_ROLES)?;
+    set_status(conn, order_id, &[DRAFT], SENT)
+}
+
+// Nothing more is expected: an order with no deliveries is cancelled, one
+// that was partly delivered is closed short as received
+pub fn close_purchase_order(conn: &Connection, order_id: &str) -> Result<PurchaseOrder, String> {
+    session::require_role(PURCHASING_ROLES)?;
+    let order = get_purchase_order(conn, order_id)?;
+    match order.status.as_str() {
+        PARTIALLY_RECEIVED => set_status(conn, order_id, &[PARTIALLY_RECEIVED], RECEIVED),
+        _ => set_status(conn, order_id, &[DRAFT, SENT], CANCELLED),
+    }
+}
+
+// Book a delivery against an order: stock comes in through the movement
+// ledger and each product takes the cost it was received at
+pub fn receive_goods(conn: &Connection, request: &ReceiveRequest) -> Result<GoodsReceipt, String> {
+    let user = session::require_user()?;
+    if request.items.is_empty() {
+        return Err("Nothing to receive".to_string());
+    }
+
+    let order = get_purchase_order(conn, &request.order_id)?;
+    if !matches!(order.status.as_str(), SENT | PARTIALLY_RECEIVED) {
+        return Err(format!("Purchase order {} is {} and cannot be received", order.po_number, order.status));
+    }
+
+    let receipt_id = database::generate_id("grn");
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM goods_receipts", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let receipt_number = format!("GRN-{:05}", count + 1);
+    let created_at = chrono::Utc::now().to_rfc3339();
+
+    conn.execute(
+        "INSERT INTO goods_receipts (id, receipt_number, order_id, received_by, notes, created_at)
+         VALUES (?, ?, ?, ?, ?, ?)",
+        params![receipt_id, receipt_number, order.id, user.id, request.notes, created_at],
+    )
+    .map_err(|e| format!("Failed to record goods receipt: {}", e))?;
+
+    let mut items = Vec::new();
+    let mut total_minor = 0;
+    for line in &request.items {
+        if line.quantity <= 0 {
+            return Err("Received quantities must be positive".to_string());
+        }
+        // Re-read so two lines for the same order item add up
+        let ordered = load_items(conn, &order.id)?
+            .into_iter()
+            .find(|i| i.id == line.order_item_id)
+            .ok_or_else(|| format!("Item {} is not on {}", line.order_item_id, order.po_number))?;
+
+        let over_quantity = (line.quantity - ordered.quantity_outstanding.max(0)).max(0);
+        if over_quantity > 0 && !request.allow_over_receipt {
+            return Err(format!(
+                "Receiving {} of {} is {} more than the {} outstanding",
+                line.quantity, ordered.product_name, over_quantity, ordered.quantity_outstanding.max(0)
+            ));
+        }
+        let unit_cost_minor = line.unit_cost_minor.unwrap_or(ordered.unit_cost_minor);
+        if unit_cost_minor < 0 {
+            return Err("Unit costs cannot be negative".to_string());
+        }
+
+        conn.execute(
+            "INSERT INTO goods_receipt_items (id, receipt_id, order_item_id, product_id, quantity, unit_cost_minor, over_quantity)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![
+                database::generate_id("grnitem"),
+                receipt_id,
+                ordered.id,
+                ordered.product_id,
+                line.quantity,
+                unit_cost_minor,
+                over_quantity
+            ],
+        )
+        .map_err(|e| format!("Failed to record received item: {}", e))?;
+
+        conn.execute(
+            "UPDATE purchase_order_items SET quantity_received = quantity_received + ? WHERE id = ?",
+            params![line.quantity, ordered.id],
+        )
+        .map_err(|e| e.to_string())?;
+
+        // The receipt's cost is what the new cost layer is valued at
+        conn.execute(
+            "UPDATE products SET cost_minor = ?, updated_at = ? WHERE id = ?",
+            params![unit_cost_minor, created_at, ordered.product_id],
+        )
+        .map_err(|e| format!("Failed to update cost of {}: {}", ordered.product_name, e))?;
+
+        match &line.lot {
+            Some(lot) => inventory::record_into_lot(
+                conn,
+                &ordered.product_id,
+                inventory::IN,
+                line.quantity,
+                Some(&receipt_id),
+                Some(&order.po_number),
+                lot,
+            )?,
+            None => inventory::record(
+                conn,
+                &ordered.product_id,
+                inventory::IN,
+                line.quantity,
+                Some(&receipt_id),
+                Some(&order.po_number),
+            )?,
+        };
+        let decimals = units::product_unit(conn, &ordered.product_id)?.quantity_decimals;
+        total_minor += units::line_total(unit_cost_minor, line.quantity, decimals);
+
+        items.push(ReceivedItem {
+            order_item_id: ordered.id,
+            product_id: ordered.product_id,
+            quantity: line.quantity,
+            unit_cost_minor,
+            over_quantity,
+        });
+    }
+
+    let complete = load_items(conn, &order.id)?.iter().all(|i| i.quantity_outstanding <= 0);
+    let order_status = if complete { RECEIVED } else { PARTIALLY_RECEIVED };
+    conn.execute(
+        "UPDATE purchase_orders SET status = ?, updated_at = ? WHERE id = ?",
+        params![order_status, created_at, order.id],
+    )
+    .map_err(|e| format!("Failed to update purchase order: {}", e))?;
+
+    println!("📦 {} received against {} ({})", receipt_number, order.po_number, order_status);
+    Ok(GoodsReceipt {
+        id: receipt_id,
+        receipt_number,
+        order_id: order.id,
+        order_status: order_status.to_string(),
+        total_minor,
+        items,
+        created_at,
+    })
+}
+
+// A supplier's bill for an order, kept in expenses as an unpaid payable
+pub fn record_supplier_invoice(conn: &Connection, request: &SupplierInvoiceRequest) -> Result<Payable, String> {
+    let user = session::require_role(PURCHASING_ROLES)?;
+    if request.amount_minor <= 0 {
+        return Err("Invoice amount must be positive".to_string());
+    }
+    if request.invoice_number.trim().is_empty() {
+        return Err("Supplier invoices need the supplier's invoice number".to_string());
+    }
+
+    let order = get_purchase_order(conn, &request.order_id)?;
+    if order.status == CANCELLED {
+        return Err(format!("Purchase order {} is cancelled", order.po_number));
+    }
+    let duplicate: bool = conn
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM expenses WHERE supplier_id = ? AND receipt_number = ? AND category = ?",
+            params![order.supplier_id, request.invoice_number.trim(), SUPPLIER_INVOICE],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    if duplicate {
+        return Err(format!("Invoice {} from {} is already recorded", request.invoice_number, order.supplier_name));
+    }
+
+    let expense_id = database::generate_id("exp");
+    conn.execute(
+        "INSERT INTO expenses (id, category, description, amount_minor, supplier_id, user_id, receipt_number,
+                               purchase_order_id, due_date, payment_status, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, 'unpaid', ?)",
+        params![
+            expense_id,
+            SUPPLIER_INVOICE,
+            format!("Invoice {} for {}", request.invoice_number.trim(), order.po_number),
+            request.amount_minor,
+            order.supplier_id,
+            user.id,
+            request.invoice_number.trim(),
+            order.id,
+            request.due_date,
+            chrono::Utc::now().to_rfc3339()
+        ],
+    )
+    .map_err(|e| format!("Failed to record supplier invoice: {}", e))?;
+
+    let payable = payable(conn, &expense_id)?;
+    if let (Some(received), Some(variance)) = (payable.received_minor, payable.variance_minor) {
+        if variance != 0 {
+            println!(
+                "⚠️ {} is invoiced {} against {} received",
+                order.po_number,
+                received + variance,
+                received
+            );
+        }
+    }
+    Ok(payable)
+}
+
+pub fn pay_supplier_invoice(conn: &Connection, expense_id: &str) -> Result<Payable, String> {
+    session::require_role(PURCHASING_ROLES)?;
+    let updated = conn
+        .execute(
+            "UPDATE expenses SET payment_status = 'paid', paid_at = ?1, updated_at = ?1
+             WHERE id = ?2 AND payment_status = 'unpaid'",
+            params![chrono::Utc::now().to_rfc3339(), expense_id],
+        )
+        .map_err(|e| format!("Failed to pay supplier invoice: {}", e))?;
+    if updated == 0 {
+        return Err(format!("No unpaid invoice {}", expense_id));
+    }
+    payable(conn, expense_id)
+}
+
+const PAYABLE_SELECT: &str = "SELECT e.id, e.supplier_id, s.name, e.purchase_order_id, o.po_number, e.receipt_number,
+        e.amount_minor, e.due_date, e.payment_status, e.created_at,
+        (SELECT COALESCE(SUM(line_total(ri.unit_cost_minor, ri.quantity, p.quantity_decimals)), 0)
+         FROM goods_receipt_items ri JOIN goods_receipts r ON r.id = ri.receipt_id
+         JOIN products p ON p.id = ri.product_id
+         WHERE r.order_id = e.purchase_order_id),
+        (SELECT COALESCE(SUM(amount_minor), 0) FROM expenses WHERE purchase_order_id = e.purchase_order_id)
+     FROM expenses e
+     JOIN suppliers s ON s.id = e.supplier_id
+     LEFT JOIN purchase_orders o ON o.id = e.purchase_order_id";
+
+fn payable_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Payable> {
+    let due_date: Option<String> = row.get(7)?;
+    let payment_status: String = row.get(8)?;
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let order_id: Option<String> = row.get(3)?;
+    let (received_minor, invoiced_minor): (i64, i64) = (row.get(10)?, row.get(11)?);
+    let received_minor = order_id.as_ref().map(|_| received_minor);
+    Ok(Payable {
+        expense_id: row.get(0)?,
+        supplier_id: row.get(1)?,
+        supplier_name: row.get(2)?,
+        po_number: row.get(4)?,
+        invoice_number: row.get(5)?,
+        amount_minor: row.get(6)?,
+        variance_minor: received_minor.map(|received| invoiced_minor - received),
+        received_minor,
+        order_id,
+        overdue: payment_status == "unpaid" && due_date.as_deref().is_some_and(|due| due < today.as_str()),
+        due_date,
+        payment_status,
+        created_at: row.get(9)?,
+    })
+}
+
+fn payable(conn: &Connection, expense_id: &str) -> Result<Payable, String> {
+    conn.query_row(&format!("{} WHERE e.id = ?", PAYABLE_SELECT), params![expense_id], payable_from_row)
+        .map_err(|e| e.to_string())
+}
+
+// Unpaid supplier invoices, soonest due first
+pub fn list_payables(conn: &Connection) -> Result<Vec<Payable>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "{} WHERE e.payment_status = 'unpaid' ORDER BY e.due_date IS NULL, e.due_date, e.created_at",
+            PAYABLE_SELECT
+        ))
+        .map_err(|e| e.to_string())?;
+    let payables = stmt
+        .query_map([], payable_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(payables)
+}
@@ -0,0 +1,575 @@
+use crate::database;
+use crate::money;
+use crate::payments;
+use crate::printer::{ReceiptHeader, StatementDocument, StatementLine};
+use crate::session;
+use crate::shifts;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+// Payments against an account balance can be taken in these
+const PAYMENT_METHODS: &[&str] = &[payments::CASH, payments::CARD, payments::WALLET];
+
+// Upper bounds in days of the first three aging buckets; older is over 90
+const AGING_BUCKETS: [i64; 3] = [30, 60, 90];
+
+#[derive(Debug, Serialize)]
+pub struct AccountEntry {
+    pub id: String,
+    pub customer_id: String,
+    pub entry_type: String,
+    // Positive entries add to what the customer owes
+    pub amount_minor: i64,
+    pub balance_after_minor: i64,
+    pub sale_id: Option<String>,
+    pub return_id: Option<String>,
+    pub payment_method: Option<String>,
+    pub reference: Option<String>,
+    pub note: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccountPayment {
+    pub customer_id: String,
+    pub amount_minor: i64,
+    pub method: String,
+    #[serde(default)]
+    pub reference: Option<String>,
+    #[serde(default)]
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct Aging {
+    pub current_minor: i64,
+    pub days_31_60_minor: i64,
+    pub days_61_90_minor: i64,
+    pub over_90_minor: i64,
+    pub total_minor: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CustomerAging {
+    pub customer_id: String,
+    pub customer_name: String,
+    pub credit_limit_minor: i64,
+    pub balance_minor: i64,
+    pub aging: Aging,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatementEntry {
+    pub date: String,
+    pub entry_type: String,
+    pub reference: String,
+    pub amount_minor: i64,
+    pub balance_minor: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Statement {
+    pub customer_id: String,
+    pub customer_name: String,
+    pub customer_address: Option<String>,
+    pub credit_limit_minor: i64,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub opening_balance_minor: i64,
+    pub entries: Vec<StatementEntry>,
+    pub closing_balance_minor: i64,
+    pub aging: Aging,
+}
+
+pub fn balance(conn: &Connection, customer_id: &str) -> Result<i64, String> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(amount_minor), 0) FROM customer_account_entries WHERE customer_id = ?",
+        params![customer_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+fn credit_limit(conn: &Connection, customer_id: &str) -> Result<i64, String> {
+    conn.query_row(
+        "SELECT credit_limit_minor FROM customers WHERE id = ?",
+        params![customer_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Customer {} not found", customer_id))
+}
+
+pub fn set_credit_limit(conn: &Connection, customer_id: &str, limit_minor: i64) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    if limit_minor < 0 {
+        return Err("Credit limit cannot be negative".to_string());
+    }
+    let updated = conn
+        .execute(
+            "UPDATE customers SET credit_limit_minor = ?, updated_at = ? WHERE id = ?",
+            params![limit_minor, chrono::Utc::now().to_rfc3339(), customer_id],
+        )
+        .map_err(|e| format!("Failed to set credit limit: {}", e))?;
+    if updated == 0 {
+        return Err(format!("Customer {} not found", customer_id));
+    }
+    Ok(())
+}
+
+struct Posting<'a> {
+    customer_id: &'a str,
+    entry_type: &'a str,
+    amount_minor: i64,
+    sale_id: Option<&'a str>,
+    return_id: Option<&'a str>,
+    payment_method: Option<&'a str>,
+    reference: Option<&'a str>,
+    note: Option<&'a str>,
+}
+
+fn post(conn: &Connection, posting: Posting<'_>) -> Result<AccountEntry, String> {
+    let balance_after_minor = balance(conn, posting.customer_id)? + posting.amount_minor;
+    let user_id = session::current_user_id();
+    let shift_id = match &user_id {
+        Some(user_id) => shifts::open_shift_for(conn, user_id)?,
+        None => None,
+    };
+
+    let entry = AccountEntry {
+        id: database::generate_id("acct"),
+        customer_id: posting.customer_id.to_string(),
+        entry_type: posting.entry_type.to_string(),
+        amount_minor: posting.amount_minor,
+        balance_after_minor,
+        sale_id: posting.sale_id.map(str::to_string),
+        return_id: posting.return_id.map(str::to_string),
+        payment_method: posting.payment_method.map(str::to_string),
+        reference: posting.reference.map(str::to_string),
+        note: posting.note.map(str::to_string),
+        created_at: chrono::Utc::now().to_rfc3339(),
+    };
+    conn.execute(
+        "INSERT INTO customer_account_entries (id, customer_id, entry_type, amount_minor, balance_after_minor, sale_id,
+                                               return_id, shift_id, user_id, payment_method, reference, note, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            entry.id,
+            entry.customer_id,
+            entry.entry_type,
+            entry.amount_minor,
+            entry.balance_after_minor,
+            entry.sale_id,
+            entry.return_id,
+            shift_id,
+            user_id,
+            entry.payment_method,
+            entry.reference,
+            entry.note,
+            entry.created_at
+        ],
+    )
+    .map_err(|e| format!("Failed to post to customer account: {}", e))?;
+
+    money::mirror_customer_balance(conn, &entry.customer_id, "account_balance_minor", balance_after_minor)?;
+
+    Ok(entry)
+}
+
+// Put (part of) a sale on the customer's account, within their credit limit
+pub fn charge_sale(conn: &Connection, customer_id: Option<&str>, sale_id: &str, amount_minor: i64) -> Result<(), String> {
+    let customer_id = customer_id.ok_or_else(|| "Selling on account needs a customer on the sale".to_string())?;
+    let limit = credit_limit(conn, customer_id)?;
+    if limit == 0 {
+        return Err("This customer cannot buy on account".to_string());
+    }
+    let owed = balance(conn, customer_id)?;
+    if owed + amount_minor > limit {
+        return Err(format!("Credit limit of {} exceeded: {} already owed", limit, owed));
+    }
+
+    post(
+        conn,
+        Posting {
+            customer_id,
+            entry_type: "charge",
+            amount_minor,
+            sale_id: Some(sale_id),
+            return_id: None,
+            payment_method: None,
+            reference: None,
+            note: None,
+        },
+    )?;
+    Ok(())
+}
+
+pub fn receive_payment(conn: &Connection, payment: &AccountPayment) -> Result<AccountEntry, String> {
+    session::require_user()?;
+    if payment.amount_minor <= 0 {
+        return Err("Payment amount must be positive".to_string());
+    }
+    if !PAYMENT_METHODS.contains(&payment.method.as_str()) {
+        return Err(format!("Account payments cannot be taken by {}", payment.method));
+    }
+    let owed = balance(conn, &payment.customer_id)?;
+    if payment.amount_minor > owed {
+        return Err(format!("Payment is more than the {} owed", owed));
+    }
+
+    let entry = post(
+        conn,
+        Posting {
+            customer_id: &payment.customer_id,
+            entry_type: "payment",
+            amount_minor: -payment.amount_minor,
+            sale_id: None,
+            return_id: None,
+            payment_method: Some(&payment.method),
+            reference: payment.reference.as_deref(),
+            note: payment.note.as_deref(),
+        },
+    )?;
+    println!("🧾 Account payment of {} from customer {}", payment.amount_minor, payment.customer_id);
+    Ok(entry)
+}
+
+// Whatever of a sale is still on account, with the customer it was charged to
+fn outstanding_for_sale(conn: &Connection, sale_id: &str) -> Result<Option<(String, i64)>, String> {
+    conn.query_row(
+        "SELECT customer_id, SUM(amount_minor) FROM customer_account_entries
+         WHERE sale_id = ? GROUP BY customer_id",
+        params![sale_id],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+// Take returned goods off the account the sale was charged to
+pub fn credit_return(conn: &Connection, sale_id: &str, return_id: &str, amount_minor: i64) -> Result<(), String> {
+    let (customer_id, _) = outstanding_for_sale(conn, sale_id)?
+        .ok_or_else(|| "The sale was not charged to an account".to_string())?;
+    post(
+        conn,
+        Posting {
+            customer_id: &customer_id,
+            entry_type: "return",
+            amount_minor: -amount_minor,
+            sale_id: Some(sale_id),
+            return_id: Some(return_id),
+            payment_method: None,
+            reference: None,
+            note: None,
+        },
+    )?;
+    Ok(())
+}
+
+// Cancel what a voided sale put on account
+pub fn reverse_sale(conn: &Connection, sale_id: &str) -> Result<(), String> {
+    if let Some((customer_id, outstanding)) = outstanding_for_sale(conn, sale_id)? {
+        if outstanding > 0 {
+            post(
+                conn,
+                Posting {
+                    customer_id: &customer_id,
+                    entry_type: "void",
+                    amount_minor: -outstanding,
+                    sale_id: Some(sale_id),
+                    return_id: None,
+                    payment_method: None,
+                    reference: None,
+                    note: None,
+                },
+            )?;
+        }
+    }
+    Ok(())
+}
+
+// Age the balance by charge date. Payments and credits settle the oldest
+// charges first, so what is left open is the most recent.
+pub fn aging(conn: &Connection, customer_id: &str, as_of: chrono::DateTime<chrono::Utc>) -> Result<Aging, String> {
+    let entries: Vec<(i64, String)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT amount_minor, created_at FROM customer_account_entries
+                 WHERE customer_id = ? AND created_at <= ? ORDER BY created_at, rowid",
+            )
+            .map_err(|e| e.to_string())?;
+        let entries = stmt
+            .query_map(params![customer_id, as_of.to_rfc3339()], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        entries
+    };
+    age_entries(&entries, as_of)
+}
+
+// Age (amount, created_at) account entries as of a moment
+fn age_entries(entries: &[(i64, String)], as_of: chrono::DateTime<chrono::Utc>) -> Result<Aging, String> {
+    let mut credits: i64 = entries.iter().filter(|(amount, _)| *amount < 0).map(|(amount, _)| -amount).sum();
+    let mut aging = Aging::default();
+
+    for (amount, created_at) in entries.iter().filter(|(amount, _)| *amount > 0) {
+        let settled = credits.min(*amount);
+        credits -= settled;
+        let open = amount - settled;
+        if open == 0 {
+            continue;
+        }
+
+        let charged_at = chrono::DateTime::parse_from_rfc3339(created_at).map_err(|e| e.to_string())?;
+        let days = (as_of - charged_at.with_timezone(&chrono::Utc)).num_days();
+        let bucket = if days <= AGING_BUCKETS[0] {
+            &mut aging.current_minor
+        } else if days <= AGING_BUCKETS[1] {
+            &mut aging.days_31_60_minor
+        } else if days <= AGING_BUCKETS[2] {
+            &mut aging.days_61_90_minor
+        } else {
+            &mut aging.over_90_minor
+        };
+        *bucket += open;
+        aging.total_minor += open;
+    }
+
+    // Credit beyond all charges shows up as a negative current balance
+    aging.current_minor -= credits;
+    aging.total_minor -= credits;
+    Ok(aging)
+}
+
+pub fn aging_report(conn: &Connection) -> Result<Vec<CustomerAging>, String> {
+    session::require_role(&["admin", "manager"])?;
+    let customers: Vec<(String, String, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, name, credit_limit_minor FROM customers
+                 WHERE id IN (SELECT customer_id FROM customer_account_entries)
+                 ORDER BY name",
+            )
+            .map_err(|e| e.to_string())?;
+        let customers = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        customers
+    };
+
+    let now = chrono::Utc::now();
+    let mut report = Vec::new();
+    for (customer_id, customer_name, credit_limit_minor) in customers {
+        let aging = aging(conn, &customer_id, now)?;
+        if aging.total_minor == 0 {
+            continue;
+        }
+        report.push(CustomerAging {
+            balance_minor: aging.total_minor,
+            customer_id,
+            customer_name,
+            credit_limit_minor,
+            aging,
+        });
+    }
+    Ok(report)
+}
+
+pub fn entries(conn: &Connection, customer_id: &str) -> Result<Vec<AccountEntry>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, customer_id, entry_type, amount_minor, balance_after_minor, sale_id, return_id,
+                    payment_method, reference, note, created_at
+             FROM customer_account_entries WHERE customer_id = ? ORDER BY created_at, rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let entries = stmt
+        .query_map(params![customer_id], |row| {
+            Ok(AccountEntry {
+                id: row.get(0)?,
+                customer_id: row.get(1)?,
+                entry_type: row.get(2)?,
+                amount_minor: row.get(3)?,
+                balance_after_minor: row.get(4)?,
+                sale_id: row.get(5)?,
+                return_id: row.get(6)?,
+                payment_method: row.get(7)?,
+                reference: row.get(8)?,
+                note: row.get(9)?,
+                created_at: row.get(10)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(entries)
+}
+
+// Movements between `from` and `to` (timestamps, either end open) with the
+// balance brought forward and the aging as at the end of the period
+pub fn statement(conn: &Connection, customer_id: &str, from: Option<&str>, to: Option<&str>) -> Result<Statement, String> {
+    let (customer_name, customer_address, credit_limit_minor): (String, Option<String>, i64) = conn
+        .query_row(
+            "SELECT name, address, credit_limit_minor FROM customers WHERE id = ?",
+            params![customer_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Customer {} not found", customer_id))?;
+
+    let opening_balance_minor: i64 = match from {
+        Some(from) => conn
+            .query_row(
+                "SELECT COALESCE(SUM(amount_minor), 0) FROM customer_account_entries
+                 WHERE customer_id = ? AND created_at < ?",
+                params![customer_id, from],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?,
+        None => 0,
+    };
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT e.created_at, e.entry_type, e.amount_minor,
+                    COALESCE(r.return_number, s.sale_number, e.reference, ''), e.payment_method
+             FROM customer_account_entries e
+             LEFT JOIN sales s ON s.id = e.sale_id
+             LEFT JOIN returns r ON r.id = e.return_id
+             WHERE e.customer_id = ?1 AND (?2 IS NULL OR e.created_at >= ?2) AND (?3 IS NULL OR e.created_at <= ?3)
+             ORDER BY e.created_at, e.rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let mut running = opening_balance_minor;
+    let entries = stmt
+        .query_map(params![customer_id, from, to], |row| {
+            let amount_minor: i64 = row.get(2)?;
+            running += amount_minor;
+            let entry_type: String = row.get(1)?;
+            let method: Option<String> = row.get(4)?;
+            Ok(StatementEntry {
+                date: row.get(0)?,
+                entry_type: match method {
+                    Some(method) => format!("{} ({})", entry_type, method),
+                    None => entry_type,
+                },
+                reference: row.get(3)?,
+                amount_minor,
+                balance_minor: running,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let as_of = match to {
+        Some(to) => chrono::DateTime::parse_from_rfc3339(to)
+            .map_err(|_| format!("Invalid statement end: {}", to))?
+            .with_timezone(&chrono::Utc),
+        None => chrono::Utc::now(),
+    };
+
+    Ok(Statement {
+        customer_id: customer_id.to_string(),
+        customer_name,
+        customer_address,
+        credit_limit_minor,
+        from: from.map(str::to_string),
+        to: to.map(str::to_string),
+        opening_balance_minor,
+        closing_balance_minor: running,
+        entries,
+        aging: aging(conn, customer_id, as_of)?,
+    })
+}
+
+fn date_part(timestamp: &str) -> String {
+    timestamp.chars().take(10).collect()
+}
+
+pub fn statement_document(statement: &Statement, header: ReceiptHeader) -> StatementDocument {
+    let major = |minor: i64| minor as f64 / 100.0;
+    let period = format!(
+        "{} to {}",
+        statement.from.as_deref().map(date_part).unwrap_or_else(|| "start".to_string()),
+        statement.to.as_deref().map(date_part).unwrap_or_else(|| "today".to_string())
+    );
+
+    StatementDocument {
+        business_name: header.business_name,
+        address: header.address,
+        phone: header.phone,
+        currency: header.currency,
+        customer_name: statement.customer_name.clone(),
+        customer_address: statement.customer_address.clone(),
+        statement_date: chrono::Local::now().format("%Y-%m-%d").to_string(),
+        period,
+        credit_limit: major(statement.credit_limit_minor),
+        opening_balance: major(statement.opening_balance_minor),
+        lines: statement
+            .entries
+            .iter()
+            .map(|entry| StatementLine {
+                date: date_part(&entry.date),
+                reference: entry.reference.clone(),
+                description: entry.entry_type.clone(),
+                debit: major(entry.amount_minor.max(0)),
+                credit: major((-entry.amount_minor).max(0)),
+                balance: major(entry.balance_minor),
+            })
+            .collect(),
+        closing_balance: major(statement.closing_balance_minor),
+        aging: [
+            major(statement.aging.current_minor),
+            major(statement.aging.days_31_60_minor),
+            major(statement.aging.days_61_90_minor),
+            major(statement.aging.over_90_minor),
+        ],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone, Utc};
+
+    fn aged(days: &[(i64, i64)]) -> Aging {
+        let as_of = Utc.with_ymd_and_hms(2024, 6, 30, 12, 0, 0).unwrap();
+        let entries: Vec<(i64, String)> = days
+            .iter()
+            .map(|&(amount, days)| (amount, (as_of - Duration::days(days)).to_rfc3339()))
+            .collect();
+        age_entries(&entries, as_of).unwrap()
+    }
+
+    #[test]
+    fn bucket_edges() {
+        let aging = aged(&[(1, 30), (10, 31), (100, 60), (1_000, 61), (10_000, 90), (100_000, 91)]);
+        assert_eq!(aging.current_minor, 1);
+        assert_eq!(aging.days_31_60_minor, 110);
+        assert_eq!(aging.days_61_90_minor, 11_000);
+        assert_eq!(aging.over_90_minor, 100_000);
+        assert_eq!(aging.total_minor, 111_111);
+    }
+
+    #[test]
+    fn credits_settle_oldest_charges_first() {
+        let aging = aged(&[(500, 100), (300, 10), (-600, 5)]);
+        assert_eq!(aging.over_90_minor, 0);
+        assert_eq!(aging.current_minor, 200);
+        assert_eq!(aging.total_minor, 200);
+    }
+
+    #[test]
+    fn overpayment_is_a_negative_current_balance() {
+        let aging = aged(&[(500, 45), (-700, 1)]);
+        assert_eq!(aging.days_31_60_minor, 0);
+        assert_eq!(aging.current_minor, -200);
+        assert_eq!(aging.total_minor, -200);
+    }
+}
@@ -0,0 +1,487 @@
+use crate::categories;
+use crate::database;
+use crate::purchasing::{self, OrderLine, PurchaseOrder, PurchaseOrderRequest};
+use crate::session;
+use crate::units;
+use rusqlite::{params, Connection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+pub const LOW_STOCK_EVENT: &str = "low-stock";
+const WATCH_INTERVAL: Duration = Duration::from_secs(60);
+// Sales history the reorder report looks at by default
+const DEFAULT_VELOCITY_DAYS: i64 = 28;
+
+// Low-stock thresholds (a product's own or its category's) are in whole
+// units, as the product form shows them; supplier min/max levels and
+// suggested quantities are fixed-point like stock and purchase order lines.
+#[derive(Debug, Clone, Serialize)]
+pub struct LowStockAlert {
+    pub product_id: String,
+    pub name: String,
+    pub sku: String,
+    pub stock: i64,
+    pub threshold: i64,
+    pub unit: String,
+    pub quantity_decimals: i64,
+    pub out_of_stock: bool,
+}
+
+// Sent to the UI when products drop to their threshold or recover
+#[derive(Debug, Clone, Serialize)]
+pub struct LowStockEvent {
+    pub alerts: Vec<LowStockAlert>,
+    pub cleared: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SupplierProductRequest {
+    pub supplier_id: String,
+    pub product_id: String,
+    #[serde(default)]
+    pub supplier_sku: Option<String>,
+    // Falls back to the supplier's lead time
+    #[serde(default)]
+    pub lead_time_days: Option<i64>,
+    // Falls back to the product's low-stock threshold
+    #[serde(default)]
+    pub min_stock: Option<i64>,
+    // Falls back to the reorder point plus one lead time of sales
+    #[serde(default)]
+    pub max_stock: Option<i64>,
+    // Case size orders are rounded up to
+    #[serde(default)]
+    pub order_multiple: Option<i64>,
+    // Falls back to the product's cost
+    #[serde(default)]
+    pub unit_cost_minor: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SupplierProduct {
+    pub id: String,
+    pub supplier_id: String,
+    pub supplier_name: String,
+    pub product_id: String,
+    pub product_name: String,
+    pub supplier_sku: Option<String>,
+    pub lead_time_days: Option<i64>,
+    pub min_stock: Option<i64>,
+    pub max_stock: Option<i64>,
+    pub order_multiple: i64,
+    pub unit_cost_minor: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ReorderQuery {
+    #[serde(default)]
+    pub velocity_days: Option<i64>,
+    #[serde(default)]
+    pub supplier_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReorderSuggestion {
+    pub product_id: String,
+    pub product_name: String,
+    pub sku: String,
+    pub supplier_id: String,
+    pub supplier_name: String,
+    pub stock: i64,
+    // Still outstanding on open purchase orders
+    pub on_order: i64,
+    // Net of voids and returns, over the velocity window
+    pub sold: i64,
+    pub daily_velocity: f64,
+    pub lead_time_days: i64,
+    pub reorder_point: i64,
+    pub target_stock: i64,
+    pub quantity: i64,
+    pub quantity_decimals: i64,
+    pub unit_cost_minor: i64,
+    pub total_minor: i64,
+}
+
+// Where a product is bought from and on what terms
+struct SupplyLink {
+    product_id: String,
+    product_name: String,
+    sku: String,
+    stock: i64,
+    threshold: i64,
+    quantity_decimals: i64,
+    supplier_id: String,
+    supplier_name: String,
+    lead_time_days: i64,
+    min_stock: Option<i64>,
+    max_stock: Option<i64>,
+    order_multiple: i64,
+    unit_cost_minor: i64,
+}
+
+// Products whose own stock is what gets sold and reordered: not variant
+// parents, and not kits, which sell from their components
+const STOCKED_PRODUCT: &str = "p.is_active = 1
+       AND NOT EXISTS (SELECT 1 FROM products v WHERE v.parent_id = p.id)
+       AND NOT EXISTS (SELECT 1 FROM kit_components k WHERE k.kit_id = p.id)";
+
+pub fn low_stock_alerts(conn: &Connection) -> Result<Vec<LowStockAlert>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT p.id, p.name, p.sku, p.stock, {}, p.unit, p.quantity_decimals
+             FROM products p LEFT JOIN category_defaults d ON d.category_id = p.category_id
+             WHERE {} ORDER BY p.stock > 0, p.name",
+            categories::EFFECTIVE_LOW_STOCK_THRESHOLD,
+            STOCKED_PRODUCT
+        ))
+        .map_err(|e| e.to_string())?;
+    let alerts = stmt
+        .query_map([], |row| {
+            let quantity_decimals: i64 = row.get(6)?;
+            let threshold: i64 = row.get(4)?;
+            let stock: i64 = row.get(3)?;
+            Ok(LowStockAlert {
+                product_id: row.get(0)?,
+                name: row.get(1)?,
+                sku: row.get(2)?,
+                stock,
+                threshold: threshold * units::scale(quantity_decimals),
+                unit: row.get(5)?,
+                quantity_decimals,
+                out_of_stock: stock <= 0,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(alerts.into_iter().filter(|a| a.stock <= a.threshold).collect())
+}
+
+fn check_low_stock(alerted: &mut HashSet<String>) -> Result<Option<LowStockEvent>, String> {
+    let db = database::get_db()?;
+    if !db.config().low_stock_alerts() {
+        alerted.clear();
+        return Ok(None);
+    }
+    let conn = db.get_connection();
+    let alerts = low_stock_alerts(&conn)?;
+    drop(conn);
+
+    let low: HashSet<String> = alerts.iter().map(|a| a.product_id.clone()).collect();
+    let cleared: Vec<String> = alerted.difference(&low).cloned().collect();
+    let new_alerts: Vec<LowStockAlert> = alerts.into_iter().filter(|a| !alerted.contains(&a.product_id)).collect();
+    *alerted = low;
+
+    if new_alerts.is_empty() && cleared.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(LowStockEvent {
+        alerts: new_alerts,
+        cleared,
+    }))
+}
+
+// Background job: every minute, tell the UI which products have newly run
+// low and which have been restocked, unless low_stock_alerts is off in the
+// config
+pub fn watch_low_stock(app: AppHandle) {
+    std::thread::spawn(move || {
+        let mut alerted = HashSet::new();
+        loop {
+            match check_low_stock(&mut alerted) {
+                Ok(Some(event)) => {
+                    println!(
+                        "📉 {} product(s) low on stock, {} restocked",
+                        event.alerts.len(),
+                        event.cleared.len()
+                    );
+                    if let Err(e) = app.emit(LOW_STOCK_EVENT, event) {
+                        eprintln!("⚠️ Failed to send low-stock event: {}", e);
+                    }
+                }
+                Ok(None) => {}
+                Err(e) => eprintln!("⚠️ Low-stock check failed: {}", e),
+            }
+            std::thread::sleep(WATCH_INTERVAL);
+        }
+    });
+}
+
+pub fn set_supplier_lead_time(conn: &Connection, supplier_id: &str, lead_time_days: i64) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    if lead_time_days < 0 {
+        return Err("Lead time cannot be negative".to_string());
+    }
+    let updated = conn
+        .execute(
+            "UPDATE suppliers SET lead_time_days = ?, updated_at = ? WHERE id = ?",
+            params![lead_time_days, chrono::Utc::now().to_rfc3339(), supplier_id],
+        )
+        .map_err(|e| e.to_string())?;
+    if updated == 0 {
+        return Err(format!("Supplier {} not found", supplier_id));
+    }
+    Ok(())
+}
+
+pub fn set_supplier_product(conn: &Connection, request: &SupplierProductRequest) -> Result<SupplierProduct, String> {
+    session::require_role(&["admin", "manager"])?;
+    let supplier_exists: bool = conn
+        .query_row("SELECT COUNT(*) > 0 FROM suppliers WHERE id = ?", params![request.supplier_id], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    if !supplier_exists {
+        return Err(format!("Supplier {} not found", request.supplier_id));
+    }
+    units::product_unit(conn, &request.product_id)?;
+    if request.lead_time_days.is_some_and(|d| d < 0) {
+        return Err("Lead time cannot be negative".to_string());
+    }
+    if request.min_stock.is_some_and(|m| m < 0) || request.max_stock.is_some_and(|m| m <= 0) {
+        return Err("Stock levels must be positive".to_string());
+    }
+    if let (Some(min), Some(max)) = (request.min_stock, request.max_stock) {
+        if max < min {
+            return Err("Maximum stock cannot be below the minimum".to_string());
+        }
+    }
+    let order_multiple = request.order_multiple.unwrap_or(1);
+    if order_multiple <= 0 {
+        return Err("Order multiple must be positive".to_string());
+    }
+    if request.unit_cost_minor.is_some_and(|c| c < 0) {
+        return Err("Costs cannot be negative".to_string());
+    }
+
+    let supplier_sku = request.supplier_sku.as_deref().map(str::trim).filter(|s| !s.is_empty());
+    conn.execute(
+        "INSERT INTO supplier_products (id, supplier_id, product_id, supplier_sku, lead_time_days, min_stock, max_stock,
+                                        order_multiple, unit_cost_minor, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT (supplier_id, product_id) DO UPDATE SET
+             supplier_sku = excluded.supplier_sku, lead_time_days = excluded.lead_time_days,
+             min_stock = excluded.min_stock, max_stock = excluded.max_stock,
+             order_multiple = excluded.order_multiple, unit_cost_minor = excluded.unit_cost_minor",
+        params![
+            database::generate_id("supprod"),
+            request.supplier_id,
+            request.product_id,
+            supplier_sku,
+            request.lead_time_days,
+            request.min_stock,
+            request.max_stock,
+            order_multiple,
+            request.unit_cost_minor,
+            chrono::Utc::now().to_rfc3339()
+        ],
+    )
+    .map_err(|e| format!("Failed to save supplier product: {}", e))?;
+
+    list_supplier_products(conn, Some(&request.supplier_id), Some(&request.product_id))?
+        .pop()
+        .ok_or_else(|| "Supplier product not saved".to_string())
+}
+
+pub fn list_supplier_products(
+    conn: &Connection,
+    supplier_id: Option<&str>,
+    product_id: Option<&str>,
+) -> Result<Vec<SupplierProduct>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT sp.id, sp.supplier_id, s.name, sp.product_id, p.name, sp.supplier_sku, sp.lead_time_days,
+                    sp.min_stock, sp.max_stock, sp.order_multiple, sp.unit_cost_minor
+             FROM supplier_products sp
+             JOIN suppliers s ON s.id = sp.supplier_id
+             JOIN products p ON p.id = sp.product_id
+             WHERE (?1 IS NULL OR sp.supplier_id = ?1) AND (?2 IS NULL OR sp.product_id = ?2)
+             ORDER BY s.name, p.name",
+        )
+        .map_err(|e| e.to_string())?;
+    let products = stmt
+        .query_map(params![supplier_id, product_id], |row| {
+            Ok(SupplierProduct {
+                id: row.get(0)?,
+                supplier_id: row.get(1)?,
+                supplier_name: row.get(2)?,
+                product_id: row.get(3)?,
+                product_name: row.get(4)?,
+                supplier_sku: row.get(5)?,
+                lead_time_days: row.get(6)?,
+                min_stock: row.get(7)?,
+                max_stock: row.get(8)?,
+                order_multiple: row.get(9)?,
+                unit_cost_minor: row.get(10)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(products)
+}
+
+pub fn remove_supplier_product(conn: &Connection, supplier_id: &str, product_id: &str) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    let removed = conn
+        .execute(
+            "DELETE FROM supplier_products WHERE supplier_id = ? AND product_id = ?",
+            params![supplier_id, product_id],
+        )
+        .map_err(|e| format!("Failed to remove supplier product: {}", e))?;
+    if removed == 0 {
+        return Err("Product is not listed for this supplier".to_string());
+    }
+    Ok(())
+}
+
+// Each stocked product with the supplier it is bought from: the one with
+// the shortest lead time among its supplier_products, else products.supplier_id
+fn supply_links(conn: &Connection) -> Result<Vec<SupplyLink>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT p.id, p.name, p.sku, p.stock, {}, p.quantity_decimals,
+                    s.id, s.name, COALESCE(sp.lead_time_days, s.lead_time_days), sp.min_stock, sp.max_stock,
+                    COALESCE(sp.order_multiple, 1), COALESCE(sp.unit_cost_minor, p.cost_minor)
+             FROM products p
+             JOIN suppliers s
+               ON s.id IN (SELECT supplier_id FROM supplier_products WHERE product_id = p.id)
+               OR (s.id = p.supplier_id AND NOT EXISTS (SELECT 1 FROM supplier_products WHERE product_id = p.id))
+             LEFT JOIN supplier_products sp ON sp.product_id = p.id AND sp.supplier_id = s.id
+             LEFT JOIN category_defaults d ON d.category_id = p.category_id
+             WHERE {}
+             ORDER BY p.name, p.id, COALESCE(sp.lead_time_days, s.lead_time_days), sp.rowid",
+            categories::EFFECTIVE_LOW_STOCK_THRESHOLD,
+            STOCKED_PRODUCT
+        ))
+        .map_err(|e| e.to_string())?;
+    let links: Vec<SupplyLink> = stmt
+        .query_map([], |row| {
+            Ok(SupplyLink {
+                product_id: row.get(0)?,
+                product_name: row.get(1)?,
+                sku: row.get(2)?,
+                stock: row.get(3)?,
+                threshold: row.get(4)?,
+                quantity_decimals: row.get(5)?,
+                supplier_id: row.get(6)?,
+                supplier_name: row.get(7)?,
+                lead_time_days: row.get(8)?,
+                min_stock: row.get(9)?,
+                max_stock: row.get(10)?,
+                order_multiple: row.get(11)?,
+                unit_cost_minor: row.get(12)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut seen = HashSet::new();
+    Ok(links.into_iter().filter(|l| seen.insert(l.product_id.clone())).collect())
+}
+
+fn ceil_div(value: i64, divisor: i64) -> i64 {
+    (value + divisor - 1) / divisor
+}
+
+// Products at or below their reorder point, with how much to order to bring
+// them back up to their target. The reorder point is the supplier minimum or
+// the sales expected over the lead time, whichever is higher.
+pub fn reorder_suggestions(conn: &Connection, query: &ReorderQuery) -> Result<Vec<ReorderSuggestion>, String> {
+    let velocity_days = query.velocity_days.unwrap_or(DEFAULT_VELOCITY_DAYS);
+    if velocity_days <= 0 {
+        return Err("Sales velocity needs a period of at least one day".to_string());
+    }
+    let since = (chrono::Utc::now() - chrono::Duration::days(velocity_days)).to_rfc3339();
+
+    let mut suggestions = Vec::new();
+    for link in supply_links(conn)? {
+        if query.supplier_id.as_deref().is_some_and(|id| id != link.supplier_id) {
+            continue;
+        }
+        let (sold, on_order): (i64, i64) = conn
+            .query_row(
+                "SELECT
+                     (SELECT -COALESCE(SUM(quantity), 0) FROM inventory_movements
+                      WHERE product_id = ?1 AND movement_type IN ('sale', 'void', 'return') AND created_at >= ?2),
+                     (SELECT COALESCE(SUM(MAX(i.quantity_ordered - i.quantity_received, 0)), 0)
+                      FROM purchase_order_items i JOIN purchase_orders o ON o.id = i.order_id
+                      WHERE i.product_id = ?1 AND o.status IN (?3, ?4, ?5))",
+                params![
+                    link.product_id,
+                    since,
+                    purchasing::DRAFT,
+                    purchasing::SENT,
+                    purchasing::PARTIALLY_RECEIVED
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|e| e.to_string())?;
+        let sold = sold.max(0);
+
+        let lead_demand = ceil_div(sold * link.lead_time_days, velocity_days);
+        let min_stock = link.min_stock.unwrap_or(link.threshold * units::scale(link.quantity_decimals));
+        let reorder_point = min_stock.max(lead_demand);
+        let position = link.stock + on_order;
+        if position > reorder_point {
+            continue;
+        }
+        let target_stock = link.max_stock.unwrap_or(reorder_point + lead_demand).max(reorder_point);
+        let quantity = ceil_div(target_stock - position, link.order_multiple) * link.order_multiple;
+        if quantity <= 0 {
+            continue;
+        }
+
+        suggestions.push(ReorderSuggestion {
+            daily_velocity: sold as f64 / units::scale(link.quantity_decimals) as f64 / velocity_days as f64,
+            total_minor: units::line_total(link.unit_cost_minor, quantity, link.quantity_decimals),
+            product_id: link.product_id,
+            product_name: link.product_name,
+            sku: link.sku,
+            supplier_id: link.supplier_id,
+            supplier_name: link.supplier_name,
+            stock: link.stock,
+            on_order,
+            sold,
+            lead_time_days: link.lead_time_days,
+            reorder_point,
+            target_stock,
+            quantity,
+            quantity_decimals: link.quantity_decimals,
+            unit_cost_minor: link.unit_cost_minor,
+        });
+    }
+    Ok(suggestions)
+}
+
+// Turn the suggestions into one draft purchase order per supplier
+pub fn draft_purchase_orders(conn: &Connection, query: &ReorderQuery) -> Result<Vec<PurchaseOrder>, String> {
+    let suggestions = reorder_suggestions(conn, query)?;
+    let mut supplier_ids: Vec<&str> = Vec::new();
+    for suggestion in &suggestions {
+        if !supplier_ids.contains(&suggestion.supplier_id.as_str()) {
+            supplier_ids.push(&suggestion.supplier_id);
+        }
+    }
+
+    let mut orders = Vec::new();
+    for supplier_id in supplier_ids {
+        let request = PurchaseOrderRequest {
+            supplier_id: supplier_id.to_string(),
+            items: suggestions
+                .iter()
+                .filter(|s| s.supplier_id == supplier_id)
+                .map(|s| OrderLine {
+                    product_id: s.product_id.clone(),
+                    quantity: s.quantity,
+                    unit_cost_minor: Some(s.unit_cost_minor),
+                })
+                .collect(),
+            expected_at: None,
+            notes: Some("Reorder suggestion".to_string()),
+        };
+        orders.push(purchasing::create_purchase_order(conn, &request)?);
+    }
+    Ok(orders)
+}
@@ -0,0 +1,568 @@
+use crate::database;
+use crate::inventory;
+use crate::ledger;
+use crate::loyalty;
+use crate::payments::{self, PaymentRecord};
+use crate::printer::{Receipt, ReceiptHeader, ReceiptItem};
+use crate::promotions;
+use crate::receivables;
+use crate::session;
+use crate::shifts;
+use crate::stored_value;
+use crate::units;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+pub const REFUND_ORIGINAL: &str = "original";
+pub const REFUND_STORE_CREDIT: &str = "store_credit";
+
+#[derive(Debug, Deserialize)]
+pub struct ReturnRequest {
+    pub sale_id: String,
+    pub items: Vec<ReturnLine>,
+    // "original" (default) refunds to the sale's payment method
+    #[serde(default)]
+    pub refund_method: Option<String>,
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReturnLine {
+    pub sale_item_id: String,
+    pub quantity: i64,
+    // Damaged goods go to products.damaged_stock instead of sellable stock
+    #[serde(default)]
+    pub damaged: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReturnedItem {
+    pub id: String,
+    pub sale_item_id: String,
+    pub product_id: String,
+    pub product_name: String,
+    // Fixed-point in the product's base unit
+    pub quantity: i64,
+    pub unit: String,
+    pub quantity_decimals: i64,
+    pub amount_minor: i64,
+    pub damaged: bool,
+}
+
+// Part of a return's money refund given back through one tender
+#[derive(Debug, Serialize)]
+pub struct TenderRefund {
+    pub id: String,
+    // The sale's payment being refunded; none when store credit was chosen
+    pub payment_id: Option<String>,
+    pub method: String,
+    pub amount_minor: i64,
+    pub reference: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReturnRecord {
+    pub id: String,
+    pub return_number: String,
+    pub sale_id: String,
+    pub user_id: String,
+    pub shift_id: Option<String>,
+    pub refund_method: String,
+    pub refund_minor: i64,
+    // Part of refund_minor given back as loyalty points, not money
+    pub loyalty_refund_minor: i64,
+    pub points_reversed: i64,
+    // Points paid with on the sale that went back to the customer
+    pub points_restored: i64,
+    pub reason: Option<String>,
+    pub items: Vec<ReturnedItem>,
+    pub refunds: Vec<TenderRefund>,
+    pub created_at: String,
+}
+
+struct SaleHeader {
+    sale_number: String,
+    customer_id: Option<String>,
+    total_minor: i64,
+    payment_method: String,
+    status: String,
+}
+
+struct SoldItem {
+    product_id: String,
+    product_name: String,
+    unit: String,
+    quantity_decimals: i64,
+    quantity: i64,
+    total_minor: i64,
+    returned: i64,
+}
+
+fn load_sale(conn: &Connection, sale_id: &str) -> Result<SaleHeader, String> {
+    conn.query_row(
+        "SELECT sale_number, customer_id, total_minor, payment_method, status FROM sales WHERE id = ?",
+        params![sale_id],
+        |row| {
+            Ok(SaleHeader {
+                sale_number: row.get(0)?,
+                customer_id: row.get(1)?,
+                total_minor: row.get(2)?,
+                payment_method: row.get(3)?,
+                status: row.get(4)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Sale {} not found", sale_id))
+}
+
+fn load_sold_item(conn: &Connection, sale_id: &str, sale_item_id: &str) -> Result<SoldItem, String> {
+    conn.query_row(
+        "SELECT si.product_id, p.name, si.quantity, COALESCE(si.paid_minor, si.total_minor - si.discount_minor),
+                COALESCE((SELECT SUM(ri.quantity) FROM return_items ri WHERE ri.sale_item_id = si.id), 0),
+                p.unit, p.quantity_decimals
+         FROM sale_items si JOIN products p ON p.id = si.product_id
+         WHERE si.id = ? AND si.sale_id = ?",
+        params![sale_item_id, sale_id],
+        |row| {
+            Ok(SoldItem {
+                product_id: row.get(0)?,
+                product_name: row.get(1)?,
+                quantity: row.get(2)?,
+                total_minor: row.get(3)?,
+                returned: row.get(4)?,
+                unit: row.get(5)?,
+                quantity_decimals: row.get(6)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Item {} is not part of sale {}", sale_item_id, sale_id))
+}
+
+fn items_total(conn: &Connection, sale_id: &str) -> Result<i64, String> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(COALESCE(paid_minor, total_minor - discount_minor)), 0) FROM sale_items WHERE sale_id = ?",
+        params![sale_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+fn refunded_so_far(conn: &Connection, sale_id: &str) -> Result<i64, String> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(refund_minor), 0) FROM returns WHERE sale_id = ?",
+        params![sale_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+fn fully_returned(conn: &Connection, sale_id: &str) -> Result<bool, String> {
+    let outstanding: i64 = conn
+        .query_row(
+            "SELECT COUNT(*) FROM sale_items si
+             WHERE si.sale_id = ?
+               AND si.quantity > COALESCE((SELECT SUM(ri.quantity) FROM return_items ri WHERE ri.sale_item_id = si.id), 0)",
+            params![sale_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+    Ok(outstanding == 0)
+}
+
+// Refund for part of a line. The sale-level discount and tax are shared out
+// in proportion to each line's value so that returning everything refunds
+// exactly the sale total.
+fn line_refund(item: &SoldItem, quantity: i64, sale_total: i64, items_total: i64) -> i64 {
+    if item.quantity == 0 || items_total == 0 {
+        return 0;
+    }
+    let line_value = item.total_minor as i128 * quantity as i128 / item.quantity as i128;
+    (line_value * sale_total as i128 / items_total as i128) as i64
+}
+
+// Returns made before refunds were recorded per tender get a single refund
+// row in the method the return was made with
+pub fn install(conn: &Connection) -> Result<(), String> {
+    let migrated = conn
+        .execute(
+            "INSERT INTO return_refunds (id, return_id, method, amount_minor, created_at)
+             SELECT 'rfd-' || id, id, refund_method, refund_minor - loyalty_refund_minor, created_at
+             FROM returns
+             WHERE refund_minor > loyalty_refund_minor AND id NOT IN (SELECT return_id FROM return_refunds)",
+            [],
+        )
+        .map_err(|e| format!("Failed to migrate return refunds: {}", e))?;
+    if migrated > 0 {
+        println!("↩️ Refund tenders recorded for {} earlier return(s)", migrated);
+    }
+    Ok(())
+}
+
+// Share a money refund over the sale's tenders in proportion to what each
+// still has to give back, so that once the sale is fully returned every
+// tender has been refunded exactly what it paid. Points are restored by
+// loyalty::reverse_for_sale, so loyalty tenders are left out.
+fn tender_shares(conn: &Connection, sale_id: &str, money_refund_minor: i64) -> Result<Vec<(PaymentRecord, i64)>, String> {
+    let mut tenders = Vec::new();
+    for payment in payments::payments_for_sale(conn, sale_id)? {
+        if payment.method == payments::LOYALTY {
+            continue;
+        }
+        let refunded: i64 = conn
+            .query_row(
+                "SELECT COALESCE(SUM(amount_minor), 0) FROM return_refunds WHERE payment_id = ?",
+                params![payment.id],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if payment.amount_minor > refunded {
+            let outstanding = payment.amount_minor - refunded;
+            tenders.push((payment, outstanding));
+        }
+    }
+
+    let weights: Vec<(usize, i64)> = tenders.iter().enumerate().map(|(i, (_, w))| (i, *w)).collect();
+    let shares = promotions::allocate(money_refund_minor, &weights);
+    let mut tenders: Vec<Option<PaymentRecord>> = tenders.into_iter().map(|(p, _)| Some(p)).collect();
+    Ok(shares
+        .into_iter()
+        .filter(|(_, share)| *share != 0)
+        .filter_map(|(index, share)| tenders[index].take().map(|p| (p, share)))
+        .collect())
+}
+
+fn record_refund(
+    conn: &Connection,
+    return_id: &str,
+    payment: Option<&PaymentRecord>,
+    method: &str,
+    amount_minor: i64,
+    created_at: &str,
+) -> Result<TenderRefund, String> {
+    let refund = TenderRefund {
+        id: database::generate_id("rfd"),
+        payment_id: payment.map(|p| p.id.clone()),
+        method: method.to_string(),
+        amount_minor,
+        reference: payment.and_then(|p| p.reference.clone()),
+    };
+    conn.execute(
+        "INSERT INTO return_refunds (id, return_id, payment_id, method, amount_minor, reference, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params![refund.id, return_id, refund.payment_id, refund.method, refund.amount_minor, refund.reference, created_at],
+    )
+    .map_err(|e| format!("Failed to record refund: {}", e))?;
+    Ok(refund)
+}
+
+pub fn create_return(conn: &Connection, request: &ReturnRequest) -> Result<ReturnRecord, String> {
+    let user = session::require_user()?;
+
+    if request.items.is_empty() {
+        return Err("Nothing to return".to_string());
+    }
+
+    let sale = load_sale(conn, &request.sale_id)?;
+    if !matches!(sale.status.as_str(), "completed" | "partially_refunded") {
+        return Err(format!("Sale {} is {} and cannot be returned", sale.sale_number, sale.status));
+    }
+
+    let refund_method = match request.refund_method.as_deref() {
+        None | Some(REFUND_ORIGINAL) => sale.payment_method.clone(),
+        Some(REFUND_STORE_CREDIT) => {
+            if sale.customer_id.is_none() {
+                return Err("Store credit needs a sale with a customer".to_string());
+            }
+            REFUND_STORE_CREDIT.to_string()
+        }
+        Some(other) => return Err(format!("Unknown refund method: {}", other)),
+    };
+
+    let items_total = items_total(conn, &request.sale_id)?;
+    let created_at = chrono::Utc::now().to_rfc3339();
+    let return_id = database::generate_id("ret");
+
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM returns", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let return_number = format!("RET-{:06}", count + 1);
+    let shift_id = shifts::open_shift_for(conn, &user.id)?;
+
+    conn.execute(
+        "INSERT INTO returns (id, return_number, sale_id, user_id, shift_id, refund_method, refund_minor, reason, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, 0, ?, ?)",
+        params![return_id, return_number, request.sale_id, user.id, shift_id, refund_method, request.reason, created_at],
+    )
+    .map_err(|e| format!("Failed to create return: {}", e))?;
+
+    let mut items = Vec::new();
+    let mut refund_minor = 0;
+
+    for line in &request.items {
+        if line.quantity <= 0 {
+            return Err("Return quantities must be positive".to_string());
+        }
+
+        let sold = load_sold_item(conn, &request.sale_id, &line.sale_item_id)?;
+        let returnable = sold.quantity - sold.returned;
+        if line.quantity > returnable {
+            return Err(format!(
+                "Cannot return {} of {}: only {} of {} sold remain returnable",
+                units::format_quantity(line.quantity, sold.quantity_decimals),
+                sold.product_name,
+                units::format_quantity(returnable, sold.quantity_decimals),
+                units::format_quantity(sold.quantity, sold.quantity_decimals)
+            ));
+        }
+
+        let amount_minor = line_refund(&sold, line.quantity, sale.total_minor, items_total);
+        let item = ReturnedItem {
+            id: database::generate_id("reti"),
+            sale_item_id: line.sale_item_id.clone(),
+            product_id: sold.product_id.clone(),
+            product_name: sold.product_name.clone(),
+            quantity: line.quantity,
+            unit: sold.unit.clone(),
+            quantity_decimals: sold.quantity_decimals,
+            amount_minor,
+            damaged: line.damaged,
+        };
+
+        conn.execute(
+            "INSERT INTO return_items (id, return_id, sale_item_id, product_id, quantity, amount_minor, damaged)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![item.id, return_id, item.sale_item_id, item.product_id, item.quantity, item.amount_minor, item.damaged],
+        )
+        .map_err(|e| format!("Failed to record returned item: {}", e))?;
+
+        inventory::record(
+            conn,
+            &item.product_id,
+            inventory::RETURN,
+            item.quantity,
+            Some(&return_id),
+            request.reason.as_deref(),
+        )?;
+        if line.damaged {
+            inventory::write_off_damaged(conn, &item.product_id, item.quantity, Some(&return_id))?;
+        }
+
+        refund_minor += amount_minor;
+        items.push(item);
+    }
+
+    // The last return of a sale absorbs rounding so refunds add up to the total
+    let fully_returned = fully_returned(conn, &request.sale_id)?;
+    if fully_returned {
+        let previous = refunded_so_far(conn, &request.sale_id)?;
+        refund_minor = sale.total_minor - previous;
+    }
+
+    // Points earned on the returned goods are taken back, and any points
+    // spent on them are restored instead of being refunded as money
+    let loyalty = loyalty::reverse_for_sale(
+        conn,
+        &request.sale_id,
+        Some(&return_id),
+        refund_minor,
+        sale.total_minor,
+        fully_returned,
+    )?;
+    let money_refund_minor = refund_minor - loyalty.restored_value_minor;
+
+    conn.execute(
+        "UPDATE returns SET refund_minor = ?, loyalty_refund_minor = ?, points_reversed = ?, points_restored = ?
+         WHERE id = ?",
+        params![
+            refund_minor,
+            loyalty.restored_value_minor,
+            loyalty.points_reversed,
+            loyalty.points_restored,
+            return_id
+        ],
+    )
+    .map_err(|e| e.to_string())?;
+
+    // Refund through the sale's own tenders, or all to store credit when the
+    // customer chose it. Stored value and account balances go back into
+    // their ledgers rather than over the counter.
+    let mut refunds = Vec::new();
+    if money_refund_minor > 0 {
+        if refund_method == REFUND_STORE_CREDIT {
+            refunds.push(record_refund(conn, &return_id, None, REFUND_STORE_CREDIT, money_refund_minor, &created_at)?);
+        } else {
+            let shares = tender_shares(conn, &request.sale_id, money_refund_minor)?;
+            if shares.is_empty() {
+                refunds.push(record_refund(conn, &return_id, None, &refund_method, money_refund_minor, &created_at)?);
+            }
+            for (payment, amount_minor) in &shares {
+                refunds.push(record_refund(conn, &return_id, Some(payment), &payment.method, *amount_minor, &created_at)?);
+            }
+        }
+    }
+
+    for refund in &refunds {
+        match (refund.method.as_str(), sale.customer_id.as_deref()) {
+            (payments::STORE_CREDIT, Some(customer_id)) => {
+                stored_value::credit_customer(conn, customer_id, refund.amount_minor, &return_id)?
+            }
+            (payments::GIFT_CARD, _) => {
+                let code = refund.reference.as_deref().unwrap_or_default();
+                stored_value::refund_to_gift_card(conn, &request.sale_id, code, refund.amount_minor, &return_id)?
+            }
+            (payments::ON_ACCOUNT, _) => {
+                receivables::credit_return(conn, &request.sale_id, &return_id, refund.amount_minor)?
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(shift_id) = &shift_id {
+        shifts::record_refund(conn, shift_id, money_refund_minor)?;
+    }
+
+    let status = if fully_returned { "refunded" } else { "partially_refunded" };
+    ledger::sanctioned(conn, || {
+        conn.execute(
+            "UPDATE sales SET status = ?, updated_at = ? WHERE id = ?",
+            params![status, created_at, request.sale_id],
+        )
+        .map_err(|e| format!("Failed to update sale status: {}", e))
+    })?;
+
+    println!("↩️ Return {} for sale {}: {} refunded", return_number, sale.sale_number, refund_minor);
+
+    Ok(ReturnRecord {
+        id: return_id,
+        return_number,
+        sale_id: request.sale_id.clone(),
+        user_id: user.id,
+        shift_id,
+        refund_method,
+        refund_minor,
+        loyalty_refund_minor: loyalty.restored_value_minor,
+        points_reversed: loyalty.points_reversed,
+        points_restored: loyalty.points_restored,
+        reason: request.reason.clone(),
+        items,
+        refunds,
+        created_at,
+    })
+}
+
+pub fn get_return(conn: &Connection, return_id: &str) -> Result<ReturnRecord, String> {
+    let mut record = conn
+        .query_row(
+            "SELECT id, return_number, sale_id, user_id, shift_id, refund_method, refund_minor, reason, created_at,
+                    loyalty_refund_minor, points_reversed, points_restored
+             FROM returns WHERE id = ?",
+            params![return_id],
+            |row| {
+                Ok(ReturnRecord {
+                    id: row.get(0)?,
+                    return_number: row.get(1)?,
+                    sale_id: row.get(2)?,
+                    user_id: row.get(3)?,
+                    shift_id: row.get(4)?,
+                    refund_method: row.get(5)?,
+                    refund_minor: row.get(6)?,
+                    loyalty_refund_minor: row.get(9)?,
+                    points_reversed: row.get(10)?,
+                    points_restored: row.get(11)?,
+                    reason: row.get(7)?,
+                    items: Vec::new(),
+                    refunds: Vec::new(),
+                    created_at: row.get(8)?,
+                })
+            },
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Return {} not found", return_id))?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT ri.id, ri.sale_item_id, ri.product_id, p.name, ri.quantity, ri.amount_minor, ri.damaged,
+                    p.unit, p.quantity_decimals
+             FROM return_items ri JOIN products p ON p.id = ri.product_id
+             WHERE ri.return_id = ?",
+        )
+        .map_err(|e| e.to_string())?;
+    record.items = stmt
+        .query_map(params![return_id], |row| {
+            Ok(ReturnedItem {
+                id: row.get(0)?,
+                sale_item_id: row.get(1)?,
+                product_id: row.get(2)?,
+                product_name: row.get(3)?,
+                quantity: row.get(4)?,
+                amount_minor: row.get(5)?,
+                damaged: row.get(6)?,
+                unit: row.get(7)?,
+                quantity_decimals: row.get(8)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, payment_id, method, amount_minor, reference FROM return_refunds
+             WHERE return_id = ? ORDER BY rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    record.refunds = stmt
+        .query_map(params![return_id], |row| {
+            Ok(TenderRefund {
+                id: row.get(0)?,
+                payment_id: row.get(1)?,
+                method: row.get(2)?,
+                amount_minor: row.get(3)?,
+                reference: row.get(4)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    Ok(record)
+}
+
+// Credit receipt for a return: negative lines and a negative total
+pub fn credit_receipt(record: &ReturnRecord, header: ReceiptHeader) -> Receipt {
+    let items: Vec<ReceiptItem> = record
+        .items
+        .iter()
+        .map(|item| {
+            let quantity = item.quantity as f64 / units::scale(item.quantity_decimals) as f64;
+            ReceiptItem {
+                name: item.product_name.clone(),
+                quantity,
+                price: -(item.amount_minor as f64 / 100.0) / quantity,
+                unit: Some(item.unit.clone()).filter(|u| u != units::DEFAULT_UNIT),
+            }
+        })
+        .collect();
+    let total = -(record.refund_minor as f64 / 100.0);
+
+    Receipt {
+        business_name: header.business_name,
+        address: header.address,
+        phone: header.phone,
+        items,
+        subtotal: total,
+        tax: 0.0,
+        discount: 0.0,
+        total,
+        currency: header.currency,
+        title: Some(format!("CREDIT NOTE {}", record.return_number)),
+        footer: Some(format!("Refunded via {}", record.refund_method)),
+        tax_lines: Vec::new(),
+    }
+}
@@ -0,0 +1,640 @@
+use crate::carts;
+use crate::costing;
+use crate::database;
+use crate::inventory;
+use crate::kits;
+use crate::ledger;
+use crate::loyalty;
+use crate::payments::{self, PaymentRecord, Tender};
+use crate::printer::{Receipt, ReceiptHeader, ReceiptItem, ReceiptTaxLine};
+use crate::promotions::{self, AppliedPromotion, CartLine};
+use crate::receivables;
+use crate::session::{self, SessionUser};
+use crate::shifts;
+use crate::stored_value;
+use crate::tax::{self, Rounding, TaxBreakdown, TaxableLine};
+use crate::units;
+use crate::variants;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct SaleRequest {
+    #[serde(default)]
+    pub customer_id: Option<String>,
+    pub items: Vec<SaleLine>,
+    // Manual discount on top of any promotions
+    #[serde(default)]
+    pub discount_minor: i64,
+    pub tenders: Vec<Tender>,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SaleLine {
+    pub product_id: String,
+    // In the selling unit: packs, or fixed-point base units (1250 = 1.250 kg)
+    pub quantity: i64,
+    // A pack of the product; the base unit when absent
+    #[serde(default)]
+    pub unit_id: Option<String>,
+    // Defaults to the current price of the selling unit
+    #[serde(default)]
+    pub price_minor: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SoldLine {
+    pub id: String,
+    pub product_id: String,
+    pub product_name: String,
+    // e.g. "M / Red" when the product is a variant
+    pub variant_label: Option<String>,
+    // Fixed-point base units taken from stock
+    pub quantity: i64,
+    pub unit_id: Option<String>,
+    pub unit_name: String,
+    // As entered, in unit_name, with quantity_decimals decimals
+    pub unit_quantity: i64,
+    pub quantity_decimals: i64,
+    // Per whole selling unit
+    pub price_minor: i64,
+    pub total_minor: i64,
+    // Promotions plus this line's share of any manual discount;
+    // total_minor is before it
+    pub discount_minor: i64,
+    pub promotions: Vec<AppliedPromotion>,
+    pub tax_class_id: String,
+    pub tax_rate_bp: i64,
+    pub tax_minor: i64,
+    // What the customer pays for the line, tax included
+    pub paid_minor: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct QuoteRequest {
+    #[serde(default)]
+    pub customer_id: Option<String>,
+    pub items: Vec<SaleLine>,
+    #[serde(default)]
+    pub discount_minor: i64,
+}
+
+// A cart priced with promotions, manual discount and tax applied
+#[derive(Debug, Serialize)]
+pub struct CartQuote {
+    pub items: Vec<SoldLine>,
+    pub subtotal_minor: i64,
+    pub promotion_discount_minor: i64,
+    pub manual_discount_minor: i64,
+    pub tax_minor: i64,
+    pub tax_breakdown: Vec<TaxBreakdown>,
+    pub total_minor: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SaleRecord {
+    pub id: String,
+    pub sale_number: String,
+    pub customer_id: Option<String>,
+    pub user_id: String,
+    pub shift_id: Option<String>,
+    pub subtotal_minor: i64,
+    pub discount_minor: i64,
+    pub tax_minor: i64,
+    pub tax_breakdown: Vec<TaxBreakdown>,
+    pub total_minor: i64,
+    pub payment_method: String,
+    pub change_minor: i64,
+    pub points_earned: i64,
+    pub items: Vec<SoldLine>,
+    pub payments: Vec<PaymentRecord>,
+    pub created_at: String,
+}
+
+fn priced_line(conn: &Connection, line: &SaleLine) -> Result<SoldLine, String> {
+    if line.quantity <= 0 {
+        return Err("Sale quantities must be positive".to_string());
+    }
+    let (name, is_active, variant_label): (String, i64, Option<String>) = conn
+        .query_row(
+            "SELECT name, is_active, variant_label FROM products WHERE id = ?",
+            params![line.product_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Product {} not found", line.product_id))?;
+    if is_active == 0 {
+        return Err(format!("{} is no longer sold", name));
+    }
+    if variants::has_variants(conn, &line.product_id)? {
+        return Err(format!("Choose a variant of {}", name));
+    }
+    let unit = units::selling_unit(conn, &line.product_id, line.unit_id.as_deref())?;
+    let quantity = line.quantity * unit.base_quantity;
+
+    let price_minor = line.price_minor.unwrap_or(unit.price_minor);
+    if price_minor < 0 {
+        return Err(format!("Price of {} cannot be negative", name));
+    }
+
+    Ok(SoldLine {
+        id: database::generate_id("si"),
+        product_id: line.product_id.clone(),
+        product_name: name,
+        variant_label,
+        quantity,
+        unit_id: unit.unit_id,
+        unit_name: unit.name,
+        unit_quantity: line.quantity,
+        quantity_decimals: unit.decimals,
+        price_minor,
+        total_minor: units::line_total(price_minor, line.quantity, unit.decimals),
+        discount_minor: 0,
+        promotions: Vec::new(),
+        tax_class_id: String::new(),
+        tax_rate_bp: 0,
+        tax_minor: 0,
+        paid_minor: 0,
+    })
+}
+
+// Stock may run negative, but not into units held for a parked cart. A
+// product entered on several lines is checked for their combined quantity.
+fn check_reservations(conn: &Connection, items: &[SoldLine]) -> Result<(), String> {
+    let mut requested: Vec<(&SoldLine, i64)> = Vec::new();
+    for item in items {
+        match requested.iter_mut().find(|(first, _)| first.product_id == item.product_id) {
+            Some((_, quantity)) => *quantity += item.quantity,
+            None => requested.push((item, item.quantity)),
+        }
+    }
+
+    for (item, quantity) in requested {
+        let reserved = carts::reserved_quantity(conn, &item.product_id)?;
+        if reserved == 0 {
+            continue;
+        }
+        let (stock, decimals): (i64, i64) = conn
+            .query_row(
+                "SELECT stock, quantity_decimals FROM products WHERE id = ?",
+                params![item.product_id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .map_err(|e| e.to_string())?;
+        let stock = kits::available_stock(conn, &item.product_id, stock)?;
+        if quantity > stock - reserved {
+            return Err(format!(
+                "Only {} of {} available; {} held in parked carts",
+                units::format_quantity((stock - reserved).max(0), decimals),
+                item.product_name,
+                units::format_quantity(reserved, decimals)
+            ));
+        }
+    }
+    Ok(())
+}
+
+// Price a cart the way the till charges it: promotions first, then the
+// manual discount shared across lines by value, then tax per line
+pub fn quote_cart(
+    conn: &Connection,
+    customer_id: Option<&str>,
+    lines: &[SaleLine],
+    manual_discount_minor: i64,
+    rounding: Rounding,
+) -> Result<CartQuote, String> {
+    if manual_discount_minor < 0 {
+        return Err("Discount cannot be negative".to_string());
+    }
+
+    let mut items = lines
+        .iter()
+        .map(|line| priced_line(conn, line))
+        .collect::<Result<Vec<_>, _>>()?;
+    check_reservations(conn, &items)?;
+
+    // Promotions count packs as items and a weighed line as one item
+    let cart: Vec<CartLine> = items
+        .iter()
+        .map(|item| match item.quantity_decimals {
+            0 => CartLine {
+                product_id: item.product_id.clone(),
+                quantity: item.unit_quantity,
+                price_minor: item.price_minor,
+            },
+            _ => CartLine {
+                product_id: item.product_id.clone(),
+                quantity: 1,
+                price_minor: item.total_minor,
+            },
+        })
+        .collect();
+    let priced = promotions::price_cart(conn, &cart, customer_id, chrono::Local::now())?;
+
+    for (item, line) in items.iter_mut().zip(priced.lines) {
+        item.discount_minor = line.discount_minor;
+        item.promotions = line.promotions;
+    }
+
+    if manual_discount_minor > priced.total_minor {
+        return Err("Discount cannot exceed the subtotal".to_string());
+    }
+    let weights: Vec<(usize, i64)> = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| (i, item.total_minor - item.discount_minor))
+        .collect();
+    for (index, share) in promotions::allocate(manual_discount_minor, &weights) {
+        items[index].discount_minor += share;
+    }
+
+    let taxable: Vec<TaxableLine> = items
+        .iter()
+        .map(|item| TaxableLine {
+            product_id: item.product_id.clone(),
+            amount_minor: item.total_minor - item.discount_minor,
+        })
+        .collect();
+    let taxed = tax::tax_lines(conn, &taxable, rounding)?;
+
+    for (item, line) in items.iter_mut().zip(&taxed.lines) {
+        item.tax_class_id = line.tax_class_id.clone();
+        item.tax_rate_bp = line.rate_bp;
+        item.tax_minor = line.tax_minor;
+        item.paid_minor = line.gross_minor;
+    }
+
+    Ok(CartQuote {
+        items,
+        subtotal_minor: priced.subtotal_minor,
+        promotion_discount_minor: priced.discount_minor,
+        manual_discount_minor,
+        tax_minor: taxed.tax_minor,
+        tax_breakdown: taxed.breakdown,
+        total_minor: taxed.total_minor,
+    })
+}
+
+pub fn create_sale(
+    conn: &Connection,
+    request: &SaleRequest,
+    rounding: Rounding,
+    minor_units_per_unit: i64,
+) -> Result<SaleRecord, String> {
+    let user = session::require_user()?;
+
+    if request.items.is_empty() {
+        return Err("A sale needs at least one item".to_string());
+    }
+
+    let quote = quote_cart(
+        conn,
+        request.customer_id.as_deref(),
+        &request.items,
+        request.discount_minor,
+        rounding,
+    )?;
+    let subtotal_minor = quote.subtotal_minor;
+    let discount_minor = quote.promotion_discount_minor + quote.manual_discount_minor;
+    let tax_minor = quote.tax_minor;
+    let total_minor = quote.total_minor;
+    let tax_breakdown = quote.tax_breakdown;
+    let items = quote.items;
+
+    let settlement = payments::settle(total_minor, &request.tenders)?;
+    let payment_method = settlement.payment_method();
+
+    let sale_id = database::generate_id("sale");
+    let created_at = chrono::Utc::now().to_rfc3339();
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM sales", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let sale_number = format!("S-{:06}", count + 1);
+    let shift_id = shifts::open_shift_for(conn, &user.id)?;
+
+    conn.execute(
+        "INSERT INTO sales (id, sale_number, customer_id, user_id, shift_id, total_minor, tax_minor, discount_minor,
+                            payment_method, status, notes, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, 'completed', ?, ?)",
+        params![
+            sale_id,
+            sale_number,
+            request.customer_id,
+            user.id,
+            shift_id,
+            total_minor,
+            tax_minor,
+            discount_minor,
+            payment_method,
+            request.notes,
+            created_at
+        ],
+    )
+    .map_err(|e| format!("Failed to create sale: {}", e))?;
+
+    for item in &items {
+        // Cost of goods sold is fixed by what the line takes out of stock
+        let taken = kits::take_for_sale(conn, &item.product_id, item.quantity, &sale_id)?;
+        let cost_minor = -costing::cost_of(conn, &taken)?;
+
+        conn.execute(
+            "INSERT INTO sale_items (id, sale_id, product_id, quantity, price_minor, total_minor, discount_minor,
+                                     tax_class_id, tax_rate_bp, tax_minor, paid_minor, unit_id, unit_name,
+                                     unit_quantity, quantity_decimals, cost_minor)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                item.id,
+                sale_id,
+                item.product_id,
+                item.quantity,
+                item.price_minor,
+                item.total_minor,
+                item.discount_minor,
+                item.tax_class_id,
+                item.tax_rate_bp,
+                item.tax_minor,
+                item.paid_minor,
+                item.unit_id,
+                item.unit_name,
+                item.unit_quantity,
+                item.quantity_decimals,
+                cost_minor
+            ],
+        )
+        .map_err(|e| format!("Failed to record sale item: {}", e))?;
+        promotions::record_line_promotions(conn, &item.id, &item.promotions)?;
+    }
+
+    tax::record_breakdown(conn, &sale_id, &tax_breakdown)?;
+
+    let payments = payments::record_payments(conn, &sale_id, request.customer_id.as_deref(), &settlement)?;
+    let points_earned = loyalty::earn_for_sale(conn, &sale_id, request.customer_id.as_deref(), minor_units_per_unit)?;
+
+    if let Some(shift_id) = &shift_id {
+        shifts::record_sale(conn, shift_id, total_minor)?;
+    }
+
+    println!("💰 Sale {} created: {} ({})", sale_number, total_minor, payment_method);
+
+    Ok(SaleRecord {
+        id: sale_id,
+        sale_number,
+        customer_id: request.customer_id.clone(),
+        user_id: user.id,
+        shift_id,
+        subtotal_minor,
+        discount_minor,
+        tax_minor,
+        tax_breakdown,
+        total_minor,
+        payment_method,
+        change_minor: settlement.change_minor,
+        points_earned,
+        items,
+        payments,
+        created_at,
+    })
+}
+
+// Receipt for a completed sale with its tax broken down per rate
+pub fn sale_receipt(conn: &Connection, sale_id: &str, header: ReceiptHeader) -> Result<Receipt, String> {
+    let (sale_number, total_minor, tax_minor, discount_minor, payment_method): (String, i64, i64, i64, String) = conn
+        .query_row(
+            "SELECT sale_number, total_minor, tax_minor, discount_minor, payment_method FROM sales WHERE id = ?",
+            params![sale_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Sale {} not found", sale_id))?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT p.name, COALESCE(si.unit_quantity, si.quantity), si.quantity_decimals, si.unit_name, si.price_minor
+             FROM sale_items si
+             JOIN products p ON p.id = si.product_id
+             WHERE si.sale_id = ? ORDER BY si.rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let items = stmt
+        .query_map(params![sale_id], |row| {
+            let quantity: i64 = row.get(1)?;
+            let decimals: i64 = row.get(2)?;
+            let unit: Option<String> = row.get(3)?;
+            Ok(ReceiptItem {
+                name: row.get(0)?,
+                quantity: quantity as f64 / units::scale(decimals) as f64,
+                price: row.get::<_, i64>(4)? as f64 / 100.0,
+                unit: unit.filter(|u| u != units::DEFAULT_UNIT),
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    let subtotal = items.iter().map(|i| i.quantity * i.price).sum();
+
+    let tax_lines = tax::sale_breakdown(conn, sale_id)?
+        .into_iter()
+        .map(|entry| ReceiptTaxLine {
+            label: entry.name,
+            rate_percent: entry.rate_bp as f64 / 100.0,
+            taxable: entry.taxable_minor as f64 / 100.0,
+            tax: entry.tax_minor as f64 / 100.0,
+        })
+        .collect();
+
+    Ok(Receipt {
+        business_name: header.business_name,
+        address: header.address,
+        phone: header.phone,
+        items,
+        subtotal,
+        tax: tax_minor as f64 / 100.0,
+        discount: discount_minor as f64 / 100.0,
+        total: total_minor as f64 / 100.0,
+        currency: header.currency,
+        title: Some(format!("SALE {}", sale_number)),
+        footer: Some(format!("Paid by {}", payment_method)),
+        tax_lines,
+    })
+}
+
+// Roles allowed to void a sale or approve someone else's void
+pub const VOID_ROLES: &[&str] = &["admin", "manager"];
+
+pub const VOID_REASONS: &[&str] = &[
+    "cashier_error",
+    "customer_cancelled",
+    "price_error",
+    "duplicate",
+    "test_sale",
+    "other",
+];
+
+#[derive(Debug, Deserialize)]
+pub struct Approver {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VoidRequest {
+    pub sale_id: String,
+    pub reason_code: String,
+    #[serde(default)]
+    pub note: Option<String>,
+    // Needed when the logged in user may not void on their own
+    #[serde(default)]
+    pub approver: Option<Approver>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VoidedSale {
+    pub id: String,
+    pub sale_number: String,
+    pub total_minor: i64,
+    pub user_id: String,
+    pub void_reason: String,
+    pub void_note: Option<String>,
+    pub voided_by: String,
+    pub voided_at: String,
+    pub created_at: String,
+}
+
+fn approving_user(conn: &Connection, request: &VoidRequest) -> Result<SessionUser, String> {
+    let current = session::require_user()?;
+    if VOID_ROLES.iter().any(|r| current.role.eq_ignore_ascii_case(r)) {
+        return Ok(current);
+    }
+
+    let approver = request
+        .approver
+        .as_ref()
+        .ok_or_else(|| "Voiding a sale needs manager approval".to_string())?;
+    let user = session::authenticate(conn, &approver.username, &approver.password)?;
+    if !VOID_ROLES.iter().any(|r| user.role.eq_ignore_ascii_case(r)) {
+        return Err(format!("{} is not allowed to approve voids", user.username));
+    }
+    Ok(user)
+}
+
+pub fn void_sale(conn: &Connection, request: &VoidRequest) -> Result<VoidedSale, String> {
+    if !VOID_REASONS.contains(&request.reason_code.as_str()) {
+        return Err(format!("Unknown void reason: {}", request.reason_code));
+    }
+    let note = request.note.as_deref().map(str::trim).filter(|n| !n.is_empty());
+    if request.reason_code == "other" && note.is_none() {
+        return Err("A note is required when the reason is 'other'".to_string());
+    }
+
+    let approver = approving_user(conn, request)?;
+
+    let (sale_number, user_id, total_minor, status, shift_id, created_at): (String, String, i64, String, Option<String>, String) = conn
+        .query_row(
+            "SELECT sale_number, user_id, total_minor, status, shift_id, created_at FROM sales WHERE id = ?",
+            params![request.sale_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?, row.get(5)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Sale {} not found", request.sale_id))?;
+
+    if status != "completed" {
+        return Err(format!("Sale {} is {} and cannot be voided", sale_number, status));
+    }
+
+    // Put back everything the sale took out of stock, which for kits may be
+    // their components rather than the kit itself
+    let sold: Vec<(String, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT product_id, -SUM(quantity) FROM inventory_movements
+                 WHERE reference_id = ? AND movement_type = ?
+                 GROUP BY product_id ORDER BY MIN(rowid)",
+            )
+            .map_err(|e| e.to_string())?;
+        let sold = stmt
+            .query_map(params![request.sale_id, inventory::SALE], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        sold
+    };
+    for (product_id, quantity) in sold {
+        inventory::record(
+            conn,
+            &product_id,
+            inventory::VOID,
+            quantity,
+            Some(&request.sale_id),
+            Some(&request.reason_code),
+        )?;
+    }
+
+    let shift_id = match shift_id {
+        Some(id) => Some(id),
+        None => shifts::open_shift_for(conn, &user_id)?,
+    };
+    if let Some(shift_id) = &shift_id {
+        shifts::reverse_sale(conn, shift_id, total_minor)?;
+    }
+
+    stored_value::reverse_sale(conn, &request.sale_id)?;
+    receivables::reverse_sale(conn, &request.sale_id)?;
+    loyalty::reverse_for_sale(conn, &request.sale_id, None, total_minor, total_minor, true)?;
+
+    let voided_at = chrono::Utc::now().to_rfc3339();
+    ledger::sanctioned(conn, || {
+        conn.execute(
+            "UPDATE sales SET status = 'voided', void_reason = ?, void_note = ?, voided_by = ?, voided_at = ?, updated_at = ?
+             WHERE id = ?",
+            params![request.reason_code, note, approver.id, voided_at, voided_at, request.sale_id],
+        )
+        .map_err(|e| format!("Failed to void sale: {}", e))
+    })?;
+
+    println!("🚫 Sale {} voided by {} ({})", sale_number, approver.username, request.reason_code);
+
+    Ok(VoidedSale {
+        id: request.sale_id.clone(),
+        sale_number,
+        total_minor,
+        user_id,
+        void_reason: request.reason_code.clone(),
+        void_note: note.map(str::to_string),
+        voided_by: approver.id,
+        voided_at,
+        created_at,
+    })
+}
+
+pub fn list_voided_sales(conn: &Connection) -> Result<Vec<VoidedSale>, String> {
+    session::require_role(VOID_ROLES)?;
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, sale_number, total_minor, user_id, void_reason, void_note, voided_by, voided_at, created_at
+             FROM voided_sales ORDER BY voided_at DESC",
+        )
+        .map_err(|e| e.to_string())?;
+    let sales = stmt
+        .query_map([], |row| {
+            Ok(VoidedSale {
+                id: row.get(0)?,
+                sale_number: row.get(1)?,
+                total_minor: row.get(2)?,
+                user_id: row.get(3)?,
+                void_reason: row.get(4)?,
+                void_note: row.get(5)?,
+                voided_by: row.get(6)?,
+                voided_at: row.get(7)?,
+                created_at: row.get(8)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(sales)
+}
@@ -0,0 +1,267 @@
+use rusqlite::functions::FunctionFlags;
+use rusqlite::types::ValueRef;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+const DEFAULT_LIMIT: u32 = 20;
+const MAX_LIMIT: u32 = 200;
+
+#[derive(Debug, Serialize)]
+pub struct ProductMatch {
+    pub id: String,
+    pub name: String,
+    pub sku: String,
+    pub barcode: Option<String>,
+    pub price_minor: i64,
+    pub stock: i64,
+    pub rank: f64,
+}
+
+fn is_arabic_diacritic(c: char) -> bool {
+    // Harakat, tanween, shadda, sukun, superscript alef and Quranic marks
+    matches!(c, '\u{0610}'..='\u{061A}' | '\u{064B}'..='\u{065F}' | '\u{0670}' | '\u{06D6}'..='\u{06ED}')
+}
+
+// Fold spelling variants so a cashier's query matches however the product
+// name was typed: hamza forms of alef, taa marbuta, alef maqsura, tatweel,
+// diacritics and Arabic-Indic digits. Latin text is lowercased.
+pub fn normalize(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        if is_arabic_diacritic(c) || c == '\u{0640}' {
+            continue;
+        }
+
+        let folded = match c {
+            'أ' | 'إ' | 'آ' | 'ٱ' => 'ا',
+            'ة' => 'ه',
+            'ى' => 'ي',
+            'ؤ' => 'و',
+            'ئ' => 'ي',
+            '٠'..='٩' => char::from(b'0' + (c as u32 - '٠' as u32) as u8),
+            '۰'..='۹' => char::from(b'0' + (c as u32 - '۰' as u32) as u8),
+            _ => c,
+        };
+
+        out.extend(folded.to_lowercase());
+    }
+
+    out
+}
+
+pub fn register_functions(conn: &Connection) -> Result<(), String> {
+    conn.create_scalar_function(
+        "search_normalize",
+        1,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            Ok(match ctx.get_raw(0) {
+                ValueRef::Text(t) => Some(normalize(&String::from_utf8_lossy(t))),
+                ValueRef::Integer(i) => Some(i.to_string()),
+                _ => None,
+            })
+        },
+    )
+    .map_err(|e| format!("Failed to register search_normalize(): {}", e))
+}
+
+// Text indexed for a product. Kept in one place so the triggers and the
+// rebuild agree on what is searchable.
+fn indexed_columns(row: &str) -> String {
+    format!(
+        "search_normalize({row}.name), search_normalize({row}.sku), \
+         search_normalize({row}.description), \
+         search_normalize(trim(COALESCE({row}.barcode, '') || ' ' || COALESCE( \
+             (SELECT group_concat(barcode, ' ') FROM product_barcodes WHERE product_id = {row}.id), '')))",
+        row = row
+    )
+}
+
+// Re-index one product, used when its extra barcodes change
+fn reindex_product(product_id: &str) -> String {
+    format!(
+        "DELETE FROM products_fts WHERE product_id = {id};
+         INSERT INTO products_fts (product_id, name, sku, description, barcode)
+         SELECT p.id, {columns} FROM products p WHERE p.id = {id};",
+        id = product_id,
+        columns = indexed_columns("p")
+    )
+}
+
+pub fn install(conn: &Connection) -> Result<(), String> {
+    let sql = format!(
+        r#"
+        CREATE VIRTUAL TABLE IF NOT EXISTS products_fts USING fts5(
+            product_id UNINDEXED,
+            name,
+            sku,
+            description,
+            barcode,
+            tokenize = 'unicode61 remove_diacritics 2',
+            prefix = '2 3'
+        );
+
+        -- bm25 weights: name counts most, codes next, description least
+        INSERT INTO products_fts (products_fts, rank) VALUES ('rank', 'bm25(0.0, 10.0, 5.0, 1.0, 5.0)');
+
+        DROP TRIGGER IF EXISTS products_fts_insert;
+        CREATE TRIGGER products_fts_insert
+        AFTER INSERT ON products
+        BEGIN
+            INSERT INTO products_fts (product_id, name, sku, description, barcode)
+            VALUES (NEW.id, {new_columns});
+        END;
+
+        DROP TRIGGER IF EXISTS products_fts_update;
+        CREATE TRIGGER products_fts_update
+        AFTER UPDATE OF name, sku, description, barcode ON products
+        BEGIN
+            DELETE FROM products_fts WHERE product_id = OLD.id;
+            INSERT INTO products_fts (product_id, name, sku, description, barcode)
+            VALUES (NEW.id, {new_columns});
+        END;
+
+        DROP TRIGGER IF EXISTS product_barcodes_fts_insert;
+        CREATE TRIGGER product_barcodes_fts_insert
+        AFTER INSERT ON product_barcodes
+        BEGIN
+            {reindex_new}
+        END;
+
+        DROP TRIGGER IF EXISTS product_barcodes_fts_delete;
+        CREATE TRIGGER product_barcodes_fts_delete
+        AFTER DELETE ON product_barcodes
+        BEGIN
+            {reindex_old}
+        END;
+
+        DROP TRIGGER IF EXISTS products_fts_delete;
+        CREATE TRIGGER products_fts_delete
+        AFTER DELETE ON products
+        BEGIN
+            DELETE FROM products_fts WHERE product_id = OLD.id;
+        END;
+        "#,
+        new_columns = indexed_columns("NEW"),
+        reindex_new = reindex_product("NEW.product_id"),
+        reindex_old = reindex_product("OLD.product_id"),
+    );
+
+    conn.execute_batch(&sql)
+        .map_err(|e| format!("Failed to install product search index: {}", e))?;
+
+    let indexed: i64 = conn
+        .query_row("SELECT COUNT(*) FROM products_fts", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let products: i64 = conn
+        .query_row("SELECT COUNT(*) FROM products", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+
+    if indexed != products {
+        rebuild_index(conn)?;
+    }
+
+    Ok(())
+}
+
+pub fn rebuild_index(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(&format!(
+        "DELETE FROM products_fts;
+         INSERT INTO products_fts (product_id, name, sku, description, barcode)
+         SELECT p.id, {} FROM products p;",
+        indexed_columns("p")
+    ))
+    .map_err(|e| format!("Failed to rebuild product search index: {}", e))?;
+
+    println!("🔎 Product search index rebuilt");
+    Ok(())
+}
+
+// Turn free text into an FTS5 query: every word must match as a prefix
+fn fts_query(query: &str) -> Option<String> {
+    let terms: Vec<String> = normalize(query)
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|t| !t.is_empty())
+        .map(|t| format!("\"{}\"*", t))
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
+    }
+}
+
+fn product_match(row: &rusqlite::Row<'_>) -> rusqlite::Result<ProductMatch> {
+    Ok(ProductMatch {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        sku: row.get(2)?,
+        barcode: row.get(3)?,
+        price_minor: row.get(4)?,
+        stock: row.get(5)?,
+        rank: row.get(6)?,
+    })
+}
+
+pub fn search_products(conn: &Connection, query: &str, limit: Option<u32>) -> Result<Vec<ProductMatch>, String> {
+    let match_expr = match fts_query(query) {
+        Some(expr) => expr,
+        None => return Ok(Vec::new()),
+    };
+    let limit = limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    // An exact SKU or barcode hit (a scan) always comes first
+    let mut results: Vec<ProductMatch> = {
+        let mut stmt = conn
+            .prepare_cached(
+                "SELECT id, name, sku, barcode, price_minor, stock, 0.0
+                 FROM products
+                 WHERE (sku = ?1 OR barcode = ?1
+                        OR id IN (SELECT product_id FROM product_barcodes WHERE barcode = ?1))
+                   AND is_active = 1",
+            )
+            .map_err(|e| e.to_string())?;
+        let exact = stmt
+            .query_map(params![query.trim()], product_match)
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        exact
+    };
+
+    // Rank inside the FTS table first (see `rank` in install) so only the
+    // top hits are joined back to products
+    let mut stmt = conn
+        .prepare_cached(
+            "SELECT p.id, p.name, p.sku, p.barcode, p.price_minor, p.stock, hits.rank
+             FROM (
+                 SELECT product_id, rank FROM products_fts
+                 WHERE products_fts MATCH ?1
+                 ORDER BY rank
+                 LIMIT ?2
+             ) AS hits
+             JOIN products p ON p.id = hits.product_id
+             WHERE p.is_active = 1
+             ORDER BY hits.rank",
+        )
+        .map_err(|e| e.to_string())?;
+
+    let ranked = stmt
+        .query_map(params![match_expr, limit * 2], product_match)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    for product in ranked {
+        if results.len() >= limit as usize {
+            break;
+        }
+        if !results.iter().any(|r| r.id == product.id) {
+            results.push(product);
+        }
+    }
+
+    Ok(results)
+}
@@ -0,0 +1,76 @@
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SessionUser {
+    pub id: String,
+    pub username: String,
+    pub role: String,
+}
+
+// The user logged in at this till. Write paths and database triggers read it
+// to attribute changes to whoever made them.
+static CURRENT_USER: Lazy<Mutex<Option<SessionUser>>> = Lazy::new(|| Mutex::new(None));
+
+pub fn set_current_user(user: SessionUser) {
+    *CURRENT_USER.lock().unwrap() = Some(user);
+}
+
+pub fn clear_current_user() {
+    *CURRENT_USER.lock().unwrap() = None;
+}
+
+pub fn current_user() -> Option<SessionUser> {
+    CURRENT_USER.lock().unwrap().clone()
+}
+
+pub fn current_user_id() -> Option<String> {
+    current_user().map(|u| u.id)
+}
+
+pub fn require_user() -> Result<SessionUser, String> {
+    current_user().ok_or_else(|| "No user is logged in".to_string())
+}
+
+// Fail unless the logged in user holds one of the given roles
+pub fn require_role(roles: &[&str]) -> Result<SessionUser, String> {
+    let user = require_user()?;
+    if roles.iter().any(|role| user.role.eq_ignore_ascii_case(role)) {
+        Ok(user)
+    } else {
+        Err(format!("Permission denied for role '{}'", user.role))
+    }
+}
+
+// Check another user's credentials without changing who is logged in,
+// e.g. a manager approving a void at a cashier's till
+pub fn authenticate(conn: &rusqlite::Connection, username: &str, password: &str) -> Result<SessionUser, String> {
+    let (id, password_hash, role, is_active) = conn
+        .query_row(
+            "SELECT id, password_hash, role, is_active FROM users WHERE username = ?",
+            rusqlite::params![username],
+            |row| {
+                Ok((
+                    row.get::<_, String>(0)?,
+                    row.get::<_, String>(1)?,
+                    row.get::<_, String>(2)?,
+                    row.get::<_, i32>(3)?,
+                ))
+            },
+        )
+        .map_err(|_| "Invalid username or password".to_string())?;
+
+    if is_active == 0 {
+        return Err("Account is disabled".to_string());
+    }
+    if !bcrypt::verify(password, &password_hash).map_err(|e| e.to_string())? {
+        return Err("Invalid username or password".to_string());
+    }
+
+    Ok(SessionUser {
+        id,
+        username: username.to_string(),
+        role,
+    })
+}
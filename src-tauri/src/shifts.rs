@@ -0,0 +1,151 @@
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct TenderTotal {
+    pub method: String,
+    pub payments: i64,
+    pub amount_minor: i64,
+}
+
+// What the drawer and the card terminal should show at the end of a shift
+#[derive(Debug, Serialize)]
+pub struct ShiftReconciliation {
+    pub shift_id: String,
+    pub starting_cash_minor: i64,
+    pub tenders: Vec<TenderTotal>,
+    pub total_sales_minor: i64,
+    pub cash_refunds_minor: i64,
+    // Gift cards sold or topped up for cash during the shift
+    pub gift_card_cash_minor: i64,
+    // Cash taken against customer account balances
+    pub account_payments_cash_minor: i64,
+    pub total_expenses_minor: i64,
+    pub expected_cash_minor: i64,
+    pub counted_cash_minor: Option<i64>,
+    pub cash_difference_minor: Option<i64>,
+}
+
+// The shift a user is currently working, if they opened one
+pub fn open_shift_for(conn: &Connection, user_id: &str) -> Result<Option<String>, String> {
+    conn.query_row(
+        "SELECT id FROM shifts WHERE user_id = ? AND status = 'open' ORDER BY start_time DESC LIMIT 1",
+        params![user_id],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+pub fn record_sale(conn: &Connection, shift_id: &str, amount_minor: i64) -> Result<(), String> {
+    conn.execute(
+        "UPDATE shifts SET total_sales_minor = total_sales_minor + ?, updated_at = ? WHERE id = ?",
+        params![amount_minor, chrono::Utc::now().to_rfc3339(), shift_id],
+    )
+    .map_err(|e| format!("Failed to update shift totals: {}", e))?;
+    Ok(())
+}
+
+pub fn record_refund(conn: &Connection, shift_id: &str, amount_minor: i64) -> Result<(), String> {
+    conn.execute(
+        "UPDATE shifts SET total_refunds_minor = total_refunds_minor + ?, updated_at = ? WHERE id = ?",
+        params![amount_minor, chrono::Utc::now().to_rfc3339(), shift_id],
+    )
+    .map_err(|e| format!("Failed to update shift totals: {}", e))?;
+    Ok(())
+}
+
+pub fn reverse_sale(conn: &Connection, shift_id: &str, amount_minor: i64) -> Result<(), String> {
+    conn.execute(
+        "UPDATE shifts SET total_sales_minor = total_sales_minor - ?, updated_at = ? WHERE id = ?",
+        params![amount_minor, chrono::Utc::now().to_rfc3339(), shift_id],
+    )
+    .map_err(|e| format!("Failed to update shift totals: {}", e))?;
+    Ok(())
+}
+
+// Totals by tender for the shift's sales. Voided sales are left out; cash
+// refunds and expenses come out of the expected drawer, and gift cards sold
+// and account payments taken in cash go into it.
+pub fn reconcile(conn: &Connection, shift_id: &str) -> Result<ShiftReconciliation, String> {
+    let (starting_cash_minor, ending_cash_minor, total_expenses_minor): (i64, Option<i64>, i64) = conn
+        .query_row(
+            "SELECT starting_cash_minor, ending_cash_minor, total_expenses_minor FROM shifts WHERE id = ?",
+            params![shift_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Shift {} not found", shift_id))?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT p.method, COUNT(*), SUM(p.amount_minor)
+             FROM payments p JOIN reportable_sales s ON s.id = p.sale_id
+             WHERE s.shift_id = ?
+             GROUP BY p.method ORDER BY p.method",
+        )
+        .map_err(|e| e.to_string())?;
+    let tenders = stmt
+        .query_map(params![shift_id], |row| {
+            Ok(TenderTotal {
+                method: row.get(0)?,
+                payments: row.get(1)?,
+                amount_minor: row.get(2)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+
+    let cash_refunds_minor: i64 = conn
+        .query_row(
+            "SELECT COALESCE(SUM(rf.amount_minor), 0) FROM return_refunds rf JOIN returns r ON r.id = rf.return_id
+             WHERE r.shift_id = ? AND rf.method = 'cash'",
+            params![shift_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    let gift_card_cash_minor: i64 = conn
+        .query_row(
+            "SELECT COALESCE(SUM(amount_minor), 0) FROM stored_value_ledger
+             WHERE shift_id = ? AND entry_type IN ('issue', 'top_up') AND payment_method = 'cash'",
+            params![shift_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    let account_payments_cash_minor: i64 = conn
+        .query_row(
+            "SELECT COALESCE(-SUM(amount_minor), 0) FROM customer_account_entries
+             WHERE shift_id = ? AND entry_type = 'payment' AND payment_method = 'cash'",
+            params![shift_id],
+            |row| row.get(0),
+        )
+        .map_err(|e| e.to_string())?;
+
+    let total_sales_minor = tenders.iter().map(|t| t.amount_minor).sum();
+    let cash_sales: i64 = tenders
+        .iter()
+        .filter(|t| t.method == "cash")
+        .map(|t| t.amount_minor)
+        .sum();
+    let expected_cash_minor = starting_cash_minor + cash_sales + gift_card_cash_minor + account_payments_cash_minor
+        - cash_refunds_minor
+        - total_expenses_minor;
+
+    Ok(ShiftReconciliation {
+        shift_id: shift_id.to_string(),
+        starting_cash_minor,
+        tenders,
+        total_sales_minor,
+        cash_refunds_minor,
+        gift_card_cash_minor,
+        account_payments_cash_minor,
+        total_expenses_minor,
+        expected_cash_minor,
+        counted_cash_minor: ending_cash_minor,
+        cash_difference_minor: ending_cash_minor.map(|counted| counted - expected_cash_minor),
+    })
+}
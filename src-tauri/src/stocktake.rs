@@ -0,0 +1,358 @@
+use crate::barcode;
+use crate::database;
+use crate::inventory;
+use crate::session;
+use crate::units;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+const STOCKTAKE_ROLES: &[&str] = &["admin", "manager"];
+
+#[derive(Debug, Deserialize)]
+pub struct OpenStocktake {
+    // Count one category; the whole store when absent
+    #[serde(default)]
+    pub category_id: Option<String>,
+    #[serde(default)]
+    pub notes: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CountEntry {
+    pub stocktake_id: String,
+    // Either the product or a scanned code
+    #[serde(default)]
+    pub product_id: Option<String>,
+    #[serde(default)]
+    pub barcode: Option<String>,
+    // Fixed-point base units counted; a scan counts what its code carries,
+    // usually one unit or one pack. Negative entries correct an earlier miscount.
+    #[serde(default)]
+    pub quantity: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PostStocktake {
+    pub stocktake_id: String,
+    // Lines whose variance is approved; every counted line when absent
+    #[serde(default)]
+    pub approved_product_ids: Option<Vec<String>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stocktake {
+    pub id: String,
+    pub stocktake_number: String,
+    pub category_id: Option<String>,
+    pub status: String,
+    pub notes: Option<String>,
+    pub opened_by: String,
+    pub opened_at: String,
+    pub posted_by: Option<String>,
+    pub posted_at: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CountLine {
+    pub product_id: String,
+    pub product_name: String,
+    pub sku: String,
+    // Stock when the session opened
+    pub expected_quantity: i64,
+    // None until someone counts the product
+    pub counted_quantity: Option<i64>,
+    pub counters: i64,
+    pub variance: i64,
+    pub unit_cost_minor: i64,
+    pub variance_value_minor: i64,
+    pub approved: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StocktakeReport {
+    pub stocktake: Stocktake,
+    pub lines: Vec<CountLine>,
+    pub counted_lines: i64,
+    pub uncounted_lines: i64,
+    pub variance_units: i64,
+    pub shrinkage_value_minor: i64,
+    pub overage_value_minor: i64,
+    pub variance_value_minor: i64,
+}
+
+// Open a count and freeze what the system expects to find, with the cost
+// each unit is valued at. A category count covers its subcategories too.
+pub fn open_stocktake(conn: &Connection, request: &OpenStocktake) -> Result<Stocktake, String> {
+    let user = session::require_role(STOCKTAKE_ROLES)?;
+
+    let already_open: Option<String> = conn
+        .query_row(
+            "SELECT stocktake_number FROM stocktakes
+             WHERE status = 'open'
+               AND (category_id IS NULL OR ?1 IS NULL
+                    OR category_id IN (SELECT ancestor_id FROM category_tree WHERE category_id = ?1)
+                    OR category_id IN (SELECT category_id FROM category_tree WHERE ancestor_id = ?1))",
+            params![request.category_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(number) = already_open {
+        return Err(format!("Stocktake {} already covers these products", number));
+    }
+
+    let id = database::generate_id("stk");
+    let count: i64 = conn
+        .query_row("SELECT COUNT(*) FROM stocktakes", [], |row| row.get(0))
+        .map_err(|e| e.to_string())?;
+    let stocktake_number = format!("ST-{:04}", count + 1);
+
+    conn.execute(
+        "INSERT INTO stocktakes (id, stocktake_number, category_id, status, notes, opened_by, opened_at)
+         VALUES (?, ?, ?, 'open', ?, ?, ?)",
+        params![id, stocktake_number, request.category_id, request.notes, user.id, chrono::Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| format!("Failed to open stocktake: {}", e))?;
+
+    let frozen = conn
+        .execute(
+            "INSERT INTO stocktake_lines (id, stocktake_id, product_id, expected_quantity, unit_cost_minor)
+             SELECT lower(hex(randomblob(16))), ?1, id, stock, cost_minor FROM products
+             WHERE is_active = 1
+               AND (?2 IS NULL OR category_id IN (SELECT category_id FROM category_tree WHERE ancestor_id = ?2))",
+            params![id, request.category_id],
+        )
+        .map_err(|e| format!("Failed to freeze expected stock: {}", e))?;
+    if frozen == 0 {
+        return Err("There are no products to count".to_string());
+    }
+
+    println!("📋 Stocktake {} opened with {} products", stocktake_number, frozen);
+    get_stocktake(conn, &id)
+}
+
+pub fn get_stocktake(conn: &Connection, stocktake_id: &str) -> Result<Stocktake, String> {
+    conn.query_row(
+        "SELECT id, stocktake_number, category_id, status, notes, opened_by, opened_at, posted_by, posted_at
+         FROM stocktakes WHERE id = ?",
+        params![stocktake_id],
+        |row| {
+            Ok(Stocktake {
+                id: row.get(0)?,
+                stocktake_number: row.get(1)?,
+                category_id: row.get(2)?,
+                status: row.get(3)?,
+                notes: row.get(4)?,
+                opened_by: row.get(5)?,
+                opened_at: row.get(6)?,
+                posted_by: row.get(7)?,
+                posted_at: row.get(8)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Stocktake {} not found", stocktake_id))
+}
+
+pub fn list_stocktakes(conn: &Connection) -> Result<Vec<Stocktake>, String> {
+    let ids: Vec<String> = {
+        let mut stmt = conn
+            .prepare("SELECT id FROM stocktakes ORDER BY opened_at DESC")
+            .map_err(|e| e.to_string())?;
+        let ids = stmt
+            .query_map([], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        ids
+    };
+
+    ids.iter().map(|id| get_stocktake(conn, id)).collect()
+}
+
+fn open_session(conn: &Connection, stocktake_id: &str) -> Result<Stocktake, String> {
+    let stocktake = get_stocktake(conn, stocktake_id)?;
+    if stocktake.status != "open" {
+        return Err(format!("Stocktake {} is {}", stocktake.stocktake_number, stocktake.status));
+    }
+    Ok(stocktake)
+}
+
+// Add one counter's tally for a product. Several people can count the same
+// product (front shelf, back room); their entries add up.
+pub fn record_count(conn: &Connection, entry: &CountEntry) -> Result<CountLine, String> {
+    let user = session::require_user()?;
+    let stocktake = open_session(conn, &entry.stocktake_id)?;
+
+    let (product_id, scanned_quantity, source) = match (&entry.product_id, &entry.barcode) {
+        (Some(product_id), _) => {
+            // One whole unit unless a quantity is given
+            let decimals = units::product_unit(conn, product_id)?.quantity_decimals;
+            (product_id.clone(), units::scale(decimals), "manual")
+        }
+        (None, Some(code)) => {
+            let lookup = barcode::lookup_barcode(conn, code)?;
+            let product = lookup.product.ok_or_else(|| format!("No product for barcode {}", code))?;
+            let counted = (lookup.quantity * product.base_quantity as f64).round() as i64;
+            (product.id, counted.max(1), "scan")
+        }
+        (None, None) => return Err("A count needs a product or a barcode".to_string()),
+    };
+    let quantity = entry.quantity.unwrap_or(scanned_quantity);
+
+    let counted: Option<i64> = conn
+        .query_row(
+            "SELECT (SELECT SUM(quantity) FROM stocktake_counts c
+                     WHERE c.stocktake_id = l.stocktake_id AND c.product_id = l.product_id)
+             FROM stocktake_lines l WHERE l.stocktake_id = ? AND l.product_id = ?",
+            params![stocktake.id, product_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Product {} is not part of stocktake {}", product_id, stocktake.stocktake_number))?;
+    if counted.unwrap_or(0) + quantity < 0 {
+        return Err("A count cannot go below zero".to_string());
+    }
+
+    conn.execute(
+        "INSERT INTO stocktake_counts (id, stocktake_id, product_id, quantity, source, barcode, counted_by, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            database::generate_id("cnt"),
+            stocktake.id,
+            product_id,
+            quantity,
+            source,
+            entry.barcode,
+            user.id,
+            chrono::Utc::now().to_rfc3339()
+        ],
+    )
+    .map_err(|e| format!("Failed to record count: {}", e))?;
+
+    load_lines(conn, &stocktake.id, Some(&product_id))?
+        .pop()
+        .ok_or_else(|| format!("Product {} is not part of stocktake {}", product_id, stocktake.stocktake_number))
+}
+
+fn load_lines(conn: &Connection, stocktake_id: &str, product_id: Option<&str>) -> Result<Vec<CountLine>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT l.product_id, p.name, p.sku, l.expected_quantity, l.unit_cost_minor, l.approved,
+                    SUM(c.quantity), COUNT(DISTINCT c.counted_by), p.quantity_decimals
+             FROM stocktake_lines l
+             JOIN products p ON p.id = l.product_id
+             LEFT JOIN stocktake_counts c ON c.stocktake_id = l.stocktake_id AND c.product_id = l.product_id
+             WHERE l.stocktake_id = ?1 AND (?2 IS NULL OR l.product_id = ?2)
+             GROUP BY l.id
+             ORDER BY p.name",
+        )
+        .map_err(|e| e.to_string())?;
+    let lines = stmt
+        .query_map(params![stocktake_id, product_id], |row| {
+            let expected_quantity: i64 = row.get(3)?;
+            let unit_cost_minor: i64 = row.get(4)?;
+            let counted_quantity: Option<i64> = row.get(6)?;
+            let variance = counted_quantity.map_or(0, |counted| counted - expected_quantity);
+            Ok(CountLine {
+                product_id: row.get(0)?,
+                product_name: row.get(1)?,
+                sku: row.get(2)?,
+                expected_quantity,
+                counted_quantity,
+                counters: row.get(7)?,
+                variance,
+                unit_cost_minor,
+                variance_value_minor: units::line_total(unit_cost_minor, variance, row.get(8)?),
+                approved: row.get(5)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(lines)
+}
+
+// Variance per product against the frozen expectation, valued at cost.
+// Uncounted products carry no variance until someone counts them.
+pub fn stocktake_report(conn: &Connection, stocktake_id: &str) -> Result<StocktakeReport, String> {
+    let stocktake = get_stocktake(conn, stocktake_id)?;
+    let lines = load_lines(conn, stocktake_id, None)?;
+
+    let counted_lines = lines.iter().filter(|l| l.counted_quantity.is_some()).count() as i64;
+    let shrinkage_value_minor = lines.iter().map(|l| l.variance_value_minor.min(0)).sum();
+    let overage_value_minor = lines.iter().map(|l| l.variance_value_minor.max(0)).sum();
+
+    Ok(StocktakeReport {
+        stocktake,
+        counted_lines,
+        uncounted_lines: lines.len() as i64 - counted_lines,
+        variance_units: lines.iter().map(|l| l.variance).sum(),
+        variance_value_minor: lines.iter().map(|l| l.variance_value_minor).sum(),
+        shrinkage_value_minor,
+        overage_value_minor,
+        lines,
+    })
+}
+
+// Adjust stock by each approved variance. The adjustment is relative to the
+// frozen quantity, so sales made while counting are not undone.
+pub fn post_stocktake(conn: &Connection, request: &PostStocktake) -> Result<StocktakeReport, String> {
+    let user = session::require_role(STOCKTAKE_ROLES)?;
+    let stocktake = open_session(conn, &request.stocktake_id)?;
+    let lines = load_lines(conn, &stocktake.id, None)?;
+
+    if let Some(approved) = &request.approved_product_ids {
+        if let Some(unknown) = approved.iter().find(|id| !lines.iter().any(|l| &l.product_id == *id)) {
+            return Err(format!("Product {} is not part of stocktake {}", unknown, stocktake.stocktake_number));
+        }
+    }
+
+    let reason = format!("Stocktake {}", stocktake.stocktake_number);
+    let mut adjusted = 0;
+    for line in lines.iter().filter(|l| l.counted_quantity.is_some()) {
+        let approved = match &request.approved_product_ids {
+            Some(ids) => ids.contains(&line.product_id),
+            None => true,
+        };
+        if !approved {
+            continue;
+        }
+
+        conn.execute(
+            "UPDATE stocktake_lines SET approved = 1 WHERE stocktake_id = ? AND product_id = ?",
+            params![stocktake.id, line.product_id],
+        )
+        .map_err(|e| e.to_string())?;
+        if line.variance != 0 {
+            inventory::record(
+                conn,
+                &line.product_id,
+                inventory::ADJUSTMENT,
+                line.variance,
+                Some(&stocktake.id),
+                Some(&reason),
+            )?;
+            adjusted += 1;
+        }
+    }
+
+    conn.execute(
+        "UPDATE stocktakes SET status = 'posted', posted_by = ?, posted_at = ? WHERE id = ?",
+        params![user.id, chrono::Utc::now().to_rfc3339(), stocktake.id],
+    )
+    .map_err(|e| format!("Failed to post stocktake: {}", e))?;
+
+    println!("📋 Stocktake {} posted: {} product(s) adjusted", stocktake.stocktake_number, adjusted);
+    stocktake_report(conn, &stocktake.id)
+}
+
+pub fn cancel_stocktake(conn: &Connection, stocktake_id: &str) -> Result<Stocktake, String> {
+    session::require_role(STOCKTAKE_ROLES)?;
+    let stocktake = open_session(conn, stocktake_id)?;
+    conn.execute("UPDATE stocktakes SET status = 'cancelled' WHERE id = ?", params![stocktake.id])
+        .map_err(|e| format!("Failed to cancel stocktake: {}", e))?;
+    get_stocktake(conn, stocktake_id)
+}
@@ -0,0 +1,558 @@
+use crate::database;
+use crate::money;
+use crate::payments;
+use crate::session;
+use crate::shifts;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+pub const GIFT_CARD: &str = "gift_card";
+pub const STORE_CREDIT: &str = "store_credit";
+
+// Gift cards can be paid for with these; the money lands in the shift
+const FUNDING_METHODS: &[&str] = &[payments::CASH, payments::CARD, payments::WALLET];
+
+#[derive(Debug, Serialize)]
+pub struct StoredValueAccount {
+    pub id: String,
+    pub code: String,
+    pub kind: String,
+    pub customer_id: Option<String>,
+    pub status: String,
+    pub balance_minor: i64,
+    pub expires_at: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StoredValueEntry {
+    pub id: String,
+    pub entry_type: String,
+    pub amount_minor: i64,
+    pub balance_after_minor: i64,
+    pub sale_id: Option<String>,
+    pub return_id: Option<String>,
+    pub shift_id: Option<String>,
+    pub user_id: Option<String>,
+    pub payment_method: Option<String>,
+    pub note: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct IssueRequest {
+    pub amount_minor: i64,
+    // How the customer paid for the card
+    pub payment_method: String,
+    #[serde(default)]
+    pub customer_id: Option<String>,
+    #[serde(default)]
+    pub expires_at: Option<String>,
+}
+
+// The ledger is append-only; only sanctioned maintenance (training resets)
+// may rewrite it
+pub fn install(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        r#"
+        DROP TRIGGER IF EXISTS stored_value_ledger_no_update;
+        CREATE TRIGGER stored_value_ledger_no_update
+        BEFORE UPDATE ON stored_value_ledger
+        WHEN NOT ledger_override()
+        BEGIN
+            SELECT RAISE(ABORT, 'The stored value ledger is append-only');
+        END;
+
+        DROP TRIGGER IF EXISTS stored_value_ledger_no_delete;
+        CREATE TRIGGER stored_value_ledger_no_delete
+        BEFORE DELETE ON stored_value_ledger
+        WHEN NOT ledger_override()
+        BEGIN
+            SELECT RAISE(ABORT, 'The stored value ledger is append-only');
+        END;
+        "#,
+    )
+    .map_err(|e| format!("Failed to install stored value triggers: {}", e))?;
+
+    migrate_store_credit(conn)
+}
+
+// Store credit used to be a plain counter on customers. Open a ledger
+// account for any balance that predates the ledger.
+fn migrate_store_credit(conn: &Connection) -> Result<(), String> {
+    let balances: Vec<(String, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id, store_credit_minor FROM customers
+                 WHERE store_credit_minor <> 0
+                   AND id NOT IN (SELECT customer_id FROM stored_value_accounts WHERE kind = 'store_credit')",
+            )
+            .map_err(|e| e.to_string())?;
+        let balances = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        balances
+    };
+
+    for (customer_id, balance) in balances {
+        let account_id = store_credit_account(conn, &customer_id)?;
+        append(conn, &account_id, "migrate", balance, Movement::default())?;
+        println!("💳 Store credit of customer {} moved to the ledger", customer_id);
+    }
+    Ok(())
+}
+
+// Luhn check digit, so a mistyped card number is caught at the till
+fn luhn_digit(payload: &str) -> u32 {
+    let sum: u32 = payload
+        .chars()
+        .rev()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| {
+            if i % 2 == 0 {
+                let doubled = d * 2;
+                if doubled > 9 { doubled - 9 } else { doubled }
+            } else {
+                d
+            }
+        })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+pub fn is_valid_code(code: &str) -> bool {
+    code.len() == 16
+        && code.chars().all(|c| c.is_ascii_digit())
+        && luhn_digit(&code[..15]) == code[15..].parse::<u32>().unwrap_or(10)
+}
+
+fn generate_code(conn: &Connection) -> Result<String, String> {
+    loop {
+        let random: i64 = conn
+            .query_row("SELECT abs(random() % 100000000000000)", [], |row| row.get(0))
+            .map_err(|e| e.to_string())?;
+        let payload = format!("9{:014}", random);
+        let code = format!("{}{}", payload, luhn_digit(&payload));
+
+        let taken: i64 = conn
+            .query_row(
+                "SELECT COUNT(*) FROM stored_value_accounts WHERE code = ?",
+                params![code],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if taken == 0 {
+            return Ok(code);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Movement<'a> {
+    sale_id: Option<&'a str>,
+    return_id: Option<&'a str>,
+    payment_method: Option<&'a str>,
+    note: Option<String>,
+}
+
+fn balance_of(conn: &Connection, account_id: &str) -> Result<i64, String> {
+    conn.query_row(
+        "SELECT COALESCE(SUM(amount_minor), 0) FROM stored_value_ledger WHERE account_id = ?",
+        params![account_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+fn append(conn: &Connection, account_id: &str, entry_type: &str, amount_minor: i64, movement: Movement<'_>) -> Result<i64, String> {
+    let balance_after = balance_of(conn, account_id)? + amount_minor;
+    if balance_after < 0 {
+        return Err(format!("Balance of {} is not enough", balance_after - amount_minor));
+    }
+
+    let user_id = session::current_user_id();
+    let shift_id = match &user_id {
+        Some(user_id) => shifts::open_shift_for(conn, user_id)?,
+        None => None,
+    };
+
+    conn.execute(
+        "INSERT INTO stored_value_ledger (id, account_id, entry_type, amount_minor, balance_after_minor, sale_id, return_id,
+                                          shift_id, user_id, payment_method, note, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        params![
+            database::generate_id("svl"),
+            account_id,
+            entry_type,
+            amount_minor,
+            balance_after,
+            movement.sale_id,
+            movement.return_id,
+            shift_id,
+            user_id,
+            movement.payment_method,
+            movement.note,
+            chrono::Utc::now().to_rfc3339()
+        ],
+    )
+    .map_err(|e| format!("Failed to record stored value movement: {}", e))?;
+
+    let customer_id: Option<String> = conn
+        .query_row(
+            "SELECT customer_id FROM stored_value_accounts WHERE id = ? AND kind = 'store_credit'",
+            params![account_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .flatten();
+    if let Some(customer_id) = customer_id {
+        money::mirror_customer_balance(conn, &customer_id, "store_credit_minor", balance_after)?;
+    }
+
+    Ok(balance_after)
+}
+
+fn account_by(conn: &Connection, column: &str, value: &str) -> Result<Option<StoredValueAccount>, String> {
+    conn.query_row(
+        &format!(
+            "SELECT a.id, a.code, a.kind, a.customer_id, a.status, a.expires_at, a.created_at,
+                    COALESCE((SELECT SUM(amount_minor) FROM stored_value_ledger WHERE account_id = a.id), 0)
+             FROM stored_value_accounts a WHERE a.{} = ?",
+            column
+        ),
+        params![value],
+        |row| {
+            Ok(StoredValueAccount {
+                id: row.get(0)?,
+                code: row.get(1)?,
+                kind: row.get(2)?,
+                customer_id: row.get(3)?,
+                status: row.get(4)?,
+                expires_at: row.get(5)?,
+                created_at: row.get(6)?,
+                balance_minor: row.get(7)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+fn gift_card(conn: &Connection, code: &str) -> Result<StoredValueAccount, String> {
+    let code = code.trim();
+    if !is_valid_code(code) {
+        return Err(format!("{} is not a valid gift card number", code));
+    }
+    expire_accounts(conn)?;
+    account_by(conn, "code", code)?
+        .filter(|a| a.kind == GIFT_CARD)
+        .ok_or_else(|| format!("Gift card {} not found", code))
+}
+
+fn store_credit_account(conn: &Connection, customer_id: &str) -> Result<String, String> {
+    let existing: Option<String> = conn
+        .query_row(
+            "SELECT id FROM stored_value_accounts WHERE kind = 'store_credit' AND customer_id = ?",
+            params![customer_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    if let Some(id) = existing {
+        return Ok(id);
+    }
+
+    let id = database::generate_id("sva");
+    conn.execute(
+        "INSERT INTO stored_value_accounts (id, code, kind, customer_id, status, created_at)
+         VALUES (?, ?, ?, ?, 'active', ?)",
+        params![id, generate_code(conn)?, STORE_CREDIT, customer_id, chrono::Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| format!("Failed to open store credit account: {}", e))?;
+    Ok(id)
+}
+
+pub fn issue_gift_card(conn: &Connection, request: &IssueRequest) -> Result<StoredValueAccount, String> {
+    session::require_user()?;
+    if request.amount_minor <= 0 {
+        return Err("Gift card value must be positive".to_string());
+    }
+    if !FUNDING_METHODS.contains(&request.payment_method.as_str()) {
+        return Err(format!("Gift cards cannot be paid for by {}", request.payment_method));
+    }
+    if let Some(expires_at) = &request.expires_at {
+        let expires_at = chrono::DateTime::parse_from_rfc3339(expires_at)
+            .map_err(|_| format!("Invalid expiry date: {}", expires_at))?;
+        if expires_at <= chrono::Utc::now() {
+            return Err("A gift card cannot be issued already expired".to_string());
+        }
+    }
+
+    let id = database::generate_id("sva");
+    let code = generate_code(conn)?;
+    conn.execute(
+        "INSERT INTO stored_value_accounts (id, code, kind, customer_id, status, expires_at, created_at)
+         VALUES (?, ?, ?, ?, 'active', ?, ?)",
+        params![id, code, GIFT_CARD, request.customer_id, request.expires_at, chrono::Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| format!("Failed to issue gift card: {}", e))?;
+
+    append(
+        conn,
+        &id,
+        "issue",
+        request.amount_minor,
+        Movement {
+            payment_method: Some(&request.payment_method),
+            ..Movement::default()
+        },
+    )?;
+
+    // The code alone spends the balance, so only its last digits are logged
+    println!("🎁 Gift card {} (…{}) issued for {}", id, &code[code.len().saturating_sub(4)..], request.amount_minor);
+    gift_card(conn, &code)
+}
+
+pub fn top_up_gift_card(conn: &Connection, code: &str, amount_minor: i64, payment_method: &str) -> Result<StoredValueAccount, String> {
+    session::require_user()?;
+    if amount_minor <= 0 {
+        return Err("Top-up amount must be positive".to_string());
+    }
+    if !FUNDING_METHODS.contains(&payment_method) {
+        return Err(format!("Gift cards cannot be paid for by {}", payment_method));
+    }
+
+    let card = gift_card(conn, code)?;
+    if card.status != "active" {
+        return Err(format!("Gift card {} is {}", card.code, card.status));
+    }
+    append(
+        conn,
+        &card.id,
+        "top_up",
+        amount_minor,
+        Movement {
+            payment_method: Some(payment_method),
+            ..Movement::default()
+        },
+    )?;
+    gift_card(conn, code)
+}
+
+pub fn check_balance(conn: &Connection, code: &str) -> Result<StoredValueAccount, String> {
+    gift_card(conn, code)
+}
+
+// Pay part of a sale from a gift card
+pub fn redeem_gift_card(conn: &Connection, code: &str, amount_minor: i64, sale_id: &str) -> Result<(), String> {
+    let card = gift_card(conn, code)?;
+    if card.status != "active" {
+        return Err(format!("Gift card {} is {}", card.code, card.status));
+    }
+    if card.balance_minor < amount_minor {
+        return Err(format!("Gift card balance of {} is not enough", card.balance_minor));
+    }
+    append(
+        conn,
+        &card.id,
+        "redeem",
+        -amount_minor,
+        Movement {
+            sale_id: Some(sale_id),
+            ..Movement::default()
+        },
+    )?;
+    Ok(())
+}
+
+pub fn use_store_credit(conn: &Connection, customer_id: Option<&str>, amount_minor: i64, sale_id: &str) -> Result<(), String> {
+    let customer_id = customer_id.ok_or_else(|| "Store credit needs a customer on the sale".to_string())?;
+    let account_id = store_credit_account(conn, customer_id)?;
+    let balance = balance_of(conn, &account_id)?;
+    if balance < amount_minor {
+        return Err(format!("Store credit balance of {} is not enough", balance));
+    }
+    append(
+        conn,
+        &account_id,
+        "redeem",
+        -amount_minor,
+        Movement {
+            sale_id: Some(sale_id),
+            ..Movement::default()
+        },
+    )?;
+    Ok(())
+}
+
+pub fn credit_customer(conn: &Connection, customer_id: &str, amount_minor: i64, return_id: &str) -> Result<(), String> {
+    let account_id = store_credit_account(conn, customer_id)?;
+    append(
+        conn,
+        &account_id,
+        "refund",
+        amount_minor,
+        Movement {
+            return_id: Some(return_id),
+            ..Movement::default()
+        },
+    )?;
+    Ok(())
+}
+
+// Put a return's refund back on the gift card the sale was paid with
+pub fn refund_to_gift_card(
+    conn: &Connection,
+    sale_id: &str,
+    code: &str,
+    amount_minor: i64,
+    return_id: &str,
+) -> Result<(), String> {
+    let account_id: String = conn
+        .query_row(
+            "SELECT l.account_id FROM stored_value_ledger l JOIN stored_value_accounts a ON a.id = l.account_id
+             WHERE l.sale_id = ? AND l.entry_type = 'redeem' AND a.kind = 'gift_card' AND a.code = ?
+             ORDER BY l.rowid LIMIT 1",
+            params![sale_id, code.trim()],
+            |row| row.get(0),
+        )
+        .map_err(|_| format!("The sale was not paid with gift card {}", code))?;
+    append(
+        conn,
+        &account_id,
+        "refund",
+        amount_minor,
+        Movement {
+            sale_id: Some(sale_id),
+            return_id: Some(return_id),
+            ..Movement::default()
+        },
+    )?;
+    Ok(())
+}
+
+// Give back everything a voided sale took from gift cards and store credit
+pub fn reverse_sale(conn: &Connection, sale_id: &str) -> Result<(), String> {
+    let redemptions: Vec<(String, i64)> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT account_id, -SUM(amount_minor) FROM stored_value_ledger
+                 WHERE sale_id = ? AND entry_type IN ('redeem', 'void')
+                 GROUP BY account_id HAVING SUM(amount_minor) < 0",
+            )
+            .map_err(|e| e.to_string())?;
+        let redemptions = stmt
+            .query_map(params![sale_id], |row| Ok((row.get(0)?, row.get(1)?)))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        redemptions
+    };
+
+    for (account_id, amount) in redemptions {
+        append(
+            conn,
+            &account_id,
+            "void",
+            amount,
+            Movement {
+                sale_id: Some(sale_id),
+                ..Movement::default()
+            },
+        )?;
+    }
+    Ok(())
+}
+
+// Run the expiry sweep on demand from the back office
+pub fn expire_gift_cards(conn: &Connection) -> Result<usize, String> {
+    session::require_role(&["admin", "manager"])?;
+    expire_accounts(conn)
+}
+
+// Zero the balance of gift cards past their expiry date
+fn expire_accounts(conn: &Connection) -> Result<usize, String> {
+    let expired: Vec<String> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT id FROM stored_value_accounts
+                 WHERE status = 'active' AND expires_at IS NOT NULL AND expires_at <= ?",
+            )
+            .map_err(|e| e.to_string())?;
+        let expired = stmt
+            .query_map(params![chrono::Utc::now().to_rfc3339()], |row| row.get(0))
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        expired
+    };
+
+    for account_id in &expired {
+        let balance = balance_of(conn, account_id)?;
+        if balance > 0 {
+            append(conn, account_id, "expire", -balance, Movement::default())?;
+        }
+        conn.execute(
+            "UPDATE stored_value_accounts SET status = 'expired' WHERE id = ?",
+            params![account_id],
+        )
+        .map_err(|e| e.to_string())?;
+    }
+
+    if !expired.is_empty() {
+        println!("⏰ {} gift card(s) expired", expired.len());
+    }
+    Ok(expired.len())
+}
+
+pub fn history(conn: &Connection, code: &str) -> Result<Vec<StoredValueEntry>, String> {
+    let account = account_by(conn, "code", code.trim())?
+        .ok_or_else(|| format!("Account {} not found", code))?;
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, entry_type, amount_minor, balance_after_minor, sale_id, return_id, shift_id, user_id,
+                    payment_method, note, created_at
+             FROM stored_value_ledger WHERE account_id = ? ORDER BY rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let entries = stmt
+        .query_map(params![account.id], |row| {
+            Ok(StoredValueEntry {
+                id: row.get(0)?,
+                entry_type: row.get(1)?,
+                amount_minor: row.get(2)?,
+                balance_after_minor: row.get(3)?,
+                sale_id: row.get(4)?,
+                return_id: row.get(5)?,
+                shift_id: row.get(6)?,
+                user_id: row.get(7)?,
+                payment_method: row.get(8)?,
+                note: row.get(9)?,
+                created_at: row.get(10)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(entries)
+}
+
+pub fn customer_store_credit(conn: &Connection, customer_id: &str) -> Result<Option<StoredValueAccount>, String> {
+    let account_id: Option<String> = conn
+        .query_row(
+            "SELECT id FROM stored_value_accounts WHERE kind = 'store_credit' AND customer_id = ?",
+            params![customer_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?;
+    match account_id {
+        Some(id) => account_by(conn, "id", &id),
+        None => Ok(None),
+    }
+}
@@ -0,0 +1,365 @@
+use crate::database;
+use crate::money::FULL_BP;
+use crate::session;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+pub const STANDARD: &str = "standard";
+pub const ZERO_RATED: &str = "zero_rated";
+pub const EXEMPT: &str = "exempt";
+
+const KINDS: &[&str] = &[STANDARD, ZERO_RATED, EXEMPT];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaxClass {
+    #[serde(default)]
+    pub id: String,
+    pub code: String,
+    pub name: String,
+    pub kind: String,
+    #[serde(default)]
+    pub rate_bp: i64,
+    // Used for products without a tax class of their own
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+// How a line's tax is rounded to minor units
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rounding {
+    HalfUp,
+    HalfEven,
+    Down,
+    Up,
+}
+
+impl Rounding {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        match value {
+            "half_up" => Ok(Rounding::HalfUp),
+            "half_even" => Ok(Rounding::HalfEven),
+            "down" => Ok(Rounding::Down),
+            "up" => Ok(Rounding::Up),
+            other => Err(format!("Unknown tax rounding rule: {}", other)),
+        }
+    }
+
+    // numerator / denominator for non-negative values, rounded by the rule
+    fn divide(self, numerator: i128, denominator: i128) -> i64 {
+        let quotient = numerator / denominator;
+        let remainder = numerator % denominator;
+        let rounded = match self {
+            Rounding::Down => quotient,
+            Rounding::Up if remainder > 0 => quotient + 1,
+            Rounding::Up => quotient,
+            Rounding::HalfUp if remainder * 2 >= denominator => quotient + 1,
+            Rounding::HalfUp => quotient,
+            Rounding::HalfEven if remainder * 2 > denominator => quotient + 1,
+            Rounding::HalfEven if remainder * 2 == denominator && quotient % 2 == 1 => quotient + 1,
+            Rounding::HalfEven => quotient,
+        };
+        rounded as i64
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TaxableLine {
+    pub product_id: String,
+    // Line value after discounts, as charged on the shelf price
+    pub amount_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LineTax {
+    pub tax_class_id: String,
+    pub tax_code: String,
+    pub rate_bp: i64,
+    pub price_includes_tax: bool,
+    // Value of the line before tax
+    pub taxable_minor: i64,
+    pub tax_minor: i64,
+    // What the customer pays for the line
+    pub gross_minor: i64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TaxBreakdown {
+    pub tax_class_id: String,
+    pub code: String,
+    pub name: String,
+    pub kind: String,
+    pub rate_bp: i64,
+    pub taxable_minor: i64,
+    pub tax_minor: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TaxResult {
+    pub lines: Vec<LineTax>,
+    pub breakdown: Vec<TaxBreakdown>,
+    pub tax_minor: i64,
+    pub total_minor: i64,
+}
+
+// Seed the three classes every store starts with
+pub fn install_defaults(conn: &Connection) -> Result<(), String> {
+    let now = chrono::Utc::now().to_rfc3339();
+    let defaults = [
+        ("tax-standard", STANDARD, "Standard rate", STANDARD, 1500, true),
+        ("tax-zero", ZERO_RATED, "Zero-rated", ZERO_RATED, 0, false),
+        ("tax-exempt", EXEMPT, "Exempt", EXEMPT, 0, false),
+    ];
+    for (id, code, name, kind, rate_bp, is_default) in defaults {
+        conn.execute(
+            "INSERT OR IGNORE INTO tax_classes (id, code, name, kind, rate_bp, is_default, created_at)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+            params![id, code, name, kind, rate_bp, is_default, now],
+        )
+        .map_err(|e| format!("Failed to create tax class {}: {}", code, e))?;
+    }
+    Ok(())
+}
+
+fn tax_class_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<TaxClass> {
+    Ok(TaxClass {
+        id: row.get(0)?,
+        code: row.get(1)?,
+        name: row.get(2)?,
+        kind: row.get(3)?,
+        rate_bp: row.get(4)?,
+        is_default: row.get(5)?,
+    })
+}
+
+pub fn list_tax_classes(conn: &Connection) -> Result<Vec<TaxClass>, String> {
+    let mut stmt = conn
+        .prepare("SELECT id, code, name, kind, rate_bp, is_default FROM tax_classes ORDER BY rate_bp DESC, code")
+        .map_err(|e| e.to_string())?;
+    let classes = stmt
+        .query_map([], tax_class_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(classes)
+}
+
+pub fn save_tax_class(conn: &Connection, class: &TaxClass) -> Result<TaxClass, String> {
+    session::require_role(&["admin", "manager"])?;
+    if !KINDS.contains(&class.kind.as_str()) {
+        return Err(format!("Unknown tax kind: {}", class.kind));
+    }
+    if class.code.trim().is_empty() || class.name.trim().is_empty() {
+        return Err("Tax classes need a code and a name".to_string());
+    }
+    if class.kind == STANDARD && (class.rate_bp <= 0 || class.rate_bp > FULL_BP) {
+        return Err("A standard rate must be between 0 and 100%".to_string());
+    }
+
+    let mut class = class.clone();
+    // Zero-rated and exempt supplies never carry tax
+    if class.kind != STANDARD {
+        class.rate_bp = 0;
+    }
+    if class.id.is_empty() {
+        class.id = database::generate_id("tax");
+    }
+    let now = chrono::Utc::now().to_rfc3339();
+
+    if class.is_default {
+        conn.execute("UPDATE tax_classes SET is_default = 0 WHERE id <> ?", params![class.id])
+            .map_err(|e| e.to_string())?;
+    }
+    conn.execute(
+        "INSERT INTO tax_classes (id, code, name, kind, rate_bp, is_default, created_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+         ON CONFLICT(id) DO UPDATE SET
+            code = excluded.code, name = excluded.name, kind = excluded.kind, rate_bp = excluded.rate_bp,
+            is_default = excluded.is_default, updated_at = excluded.created_at",
+        params![class.id, class.code, class.name, class.kind, class.rate_bp, class.is_default, now],
+    )
+    .map_err(|e| format!("Failed to save tax class: {}", e))?;
+
+    Ok(class)
+}
+
+pub fn assign_product_tax(
+    conn: &Connection,
+    product_id: &str,
+    tax_class_id: Option<&str>,
+    price_includes_tax: bool,
+) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    let updated = conn
+        .execute(
+            "UPDATE products SET tax_class_id = ?, price_includes_tax = ?, updated_at = ? WHERE id = ?",
+            params![tax_class_id, price_includes_tax, chrono::Utc::now().to_rfc3339(), product_id],
+        )
+        .map_err(|e| format!("Failed to set product tax: {}", e))?;
+    if updated == 0 {
+        return Err(format!("Product {} not found", product_id));
+    }
+    Ok(())
+}
+
+// The class a product is taxed under, falling back to its category's class
+// and then the default class
+fn product_tax(conn: &Connection, product_id: &str) -> Result<(TaxClass, bool), String> {
+    conn.query_row(
+        "SELECT t.id, t.code, t.name, t.kind, t.rate_bp, t.is_default, p.price_includes_tax
+         FROM products p
+         LEFT JOIN category_defaults d ON d.category_id = p.category_id
+         JOIN tax_classes t ON t.id = COALESCE(p.tax_class_id, d.tax_class_id,
+                                               (SELECT id FROM tax_classes WHERE is_default = 1 LIMIT 1))
+         WHERE p.id = ?",
+        params![product_id],
+        |row| Ok((tax_class_from_row(row)?, row.get(6)?)),
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("No tax class for product {}", product_id))
+}
+
+// Tax on one line. An inclusive price already contains the tax, so it is
+// carved out; an exclusive price has it added on top. Negative amounts are
+// rounded as their magnitude so refunds mirror sales.
+pub fn line_tax(amount_minor: i64, rate_bp: i64, price_includes_tax: bool, rounding: Rounding) -> (i64, i64) {
+    let sign = if amount_minor < 0 { -1 } else { 1 };
+    let amount = amount_minor.abs() as i128;
+
+    let (taxable, tax) = if price_includes_tax {
+        let tax = rounding.divide(amount * rate_bp as i128, (FULL_BP + rate_bp) as i128);
+        (amount as i64 - tax, tax)
+    } else {
+        (amount as i64, rounding.divide(amount * rate_bp as i128, FULL_BP as i128))
+    };
+
+    (sign * taxable, sign * tax)
+}
+
+pub fn tax_lines(conn: &Connection, lines: &[TaxableLine], rounding: Rounding) -> Result<TaxResult, String> {
+    let mut taxed = Vec::with_capacity(lines.len());
+    let mut breakdown: Vec<TaxBreakdown> = Vec::new();
+
+    for line in lines {
+        let (class, price_includes_tax) = product_tax(conn, &line.product_id)?;
+        let (taxable_minor, tax_minor) = line_tax(line.amount_minor, class.rate_bp, price_includes_tax, rounding);
+
+        match breakdown.iter_mut().find(|b| b.tax_class_id == class.id) {
+            Some(entry) => {
+                entry.taxable_minor += taxable_minor;
+                entry.tax_minor += tax_minor;
+            }
+            None => breakdown.push(TaxBreakdown {
+                tax_class_id: class.id.clone(),
+                code: class.code.clone(),
+                name: class.name.clone(),
+                kind: class.kind.clone(),
+                rate_bp: class.rate_bp,
+                taxable_minor,
+                tax_minor,
+            }),
+        }
+
+        taxed.push(LineTax {
+            tax_class_id: class.id,
+            tax_code: class.code,
+            rate_bp: class.rate_bp,
+            price_includes_tax,
+            taxable_minor,
+            tax_minor,
+            gross_minor: taxable_minor + tax_minor,
+        });
+    }
+
+    breakdown.sort_by(|a, b| b.rate_bp.cmp(&a.rate_bp).then(a.code.cmp(&b.code)));
+
+    Ok(TaxResult {
+        tax_minor: taxed.iter().map(|l| l.tax_minor).sum(),
+        total_minor: taxed.iter().map(|l| l.gross_minor).sum(),
+        lines: taxed,
+        breakdown,
+    })
+}
+
+pub fn record_breakdown(conn: &Connection, sale_id: &str, breakdown: &[TaxBreakdown]) -> Result<(), String> {
+    for entry in breakdown {
+        conn.execute(
+            "INSERT INTO sale_tax_lines (id, sale_id, tax_class_id, code, name, kind, rate_bp, taxable_minor, tax_minor)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            params![
+                database::generate_id("stax"),
+                sale_id,
+                entry.tax_class_id,
+                entry.code,
+                entry.name,
+                entry.kind,
+                entry.rate_bp,
+                entry.taxable_minor,
+                entry.tax_minor
+            ],
+        )
+        .map_err(|e| format!("Failed to record tax breakdown: {}", e))?;
+    }
+    Ok(())
+}
+
+pub fn sale_breakdown(conn: &Connection, sale_id: &str) -> Result<Vec<TaxBreakdown>, String> {
+    let mut stmt = conn
+        .prepare(
+            "SELECT tax_class_id, code, name, kind, rate_bp, taxable_minor, tax_minor
+             FROM sale_tax_lines WHERE sale_id = ? ORDER BY rate_bp DESC, code",
+        )
+        .map_err(|e| e.to_string())?;
+    let breakdown = stmt
+        .query_map(params![sale_id], |row| {
+            Ok(TaxBreakdown {
+                tax_class_id: row.get(0)?,
+                code: row.get(1)?,
+                name: row.get(2)?,
+                kind: row.get(3)?,
+                rate_bp: row.get(4)?,
+                taxable_minor: row.get(5)?,
+                tax_minor: row.get(6)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(breakdown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ties_round_by_rule() {
+        // 2.5 and 3.5 minor units of tax: 50 and 70 at 5%
+        assert_eq!(line_tax(50, 500, false, Rounding::HalfEven), (50, 2));
+        assert_eq!(line_tax(70, 500, false, Rounding::HalfEven), (70, 4));
+        assert_eq!(line_tax(50, 500, false, Rounding::HalfUp), (50, 3));
+        assert_eq!(line_tax(50, 500, false, Rounding::Down), (50, 2));
+        assert_eq!(line_tax(51, 500, false, Rounding::Up), (51, 3));
+        assert_eq!(line_tax(40, 500, false, Rounding::Up), (40, 2));
+    }
+
+    #[test]
+    fn inclusive_prices_carve_tax_out() {
+        assert_eq!(line_tax(1000, 1500, false, Rounding::HalfUp), (1000, 150));
+        assert_eq!(line_tax(1150, 1500, true, Rounding::HalfUp), (1000, 150));
+        // 999 / 1.15 leaves 130.30 of tax
+        assert_eq!(line_tax(999, 1500, true, Rounding::HalfUp), (869, 130));
+        assert_eq!(line_tax(999, 0, true, Rounding::HalfUp), (999, 0));
+    }
+
+    #[test]
+    fn negative_amounts_mirror_positive_ones() {
+        for rounding in [Rounding::HalfUp, Rounding::HalfEven, Rounding::Down, Rounding::Up] {
+            for inclusive in [false, true] {
+                let (taxable, tax) = line_tax(1234, 1500, inclusive, rounding);
+                assert_eq!(line_tax(-1234, 1500, inclusive, rounding), (-taxable, -tax));
+            }
+        }
+        assert_eq!(line_tax(-50, 500, false, Rounding::HalfUp), (-50, -3));
+    }
+}
@@ -0,0 +1,136 @@
+use crate::config::{self, StoreProfile};
+use crate::database::{self, Database};
+use crate::inventory;
+use crate::ledger;
+use rusqlite::params;
+
+pub const TRAINING_BANNER: &str = "TRAINING – NOT A RECEIPT";
+
+// Transactional tables emptied in a fresh training copy. The catalog, users
+// and customers are kept so cashiers practise against real products; stock
+// levels are kept too and restart the movement ledger as opening balances.
+const TRAINING_CLEARED_TABLES: &[&str] = &[
+    "parked_cart_items",
+    "parked_carts",
+    "return_refunds",
+    "return_items",
+    "returns",
+    "sale_tax_lines",
+    "sale_item_promotions",
+    "sale_items",
+    "payments",
+    "sales",
+    "loyalty_ledger",
+    "stored_value_ledger",
+    "stored_value_accounts",
+    "customer_account_entries",
+    // Supplier invoices are booked as expenses against their purchase order
+    "expenses",
+    "goods_receipt_items",
+    "goods_receipts",
+    "purchase_order_items",
+    "purchase_orders",
+    "stocktake_counts",
+    "stocktake_lines",
+    "stocktakes",
+    "movement_costs",
+    "cost_layers",
+    "lot_movements",
+    "inventory_movements",
+    "shifts",
+];
+
+fn remove_database_files(profile: &StoreProfile) -> Result<(), String> {
+    let path = &profile.database_path;
+    for suffix in ["", "-wal", "-shm", "-journal"] {
+        let file = std::path::PathBuf::from(format!("{}{}", path.display(), suffix));
+        if file.exists() {
+            std::fs::remove_file(&file)
+                .map_err(|e| format!("Failed to remove {:?}: {}", file, e))?;
+        }
+    }
+    Ok(())
+}
+
+// Snapshot the live database into the training file, then strip every sale
+// so training activity starts from an empty ledger.
+fn clone_live_database(db: &Database, training: &StoreProfile) -> Result<(), String> {
+    if let Some(parent) = training.database_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create training directory: {}", e))?;
+    }
+
+    {
+        let conn = db.get_connection();
+        let target = training.database_path.to_string_lossy().to_string();
+        conn.execute("VACUUM INTO ?", params![target])
+            .map_err(|e| format!("Failed to copy live database: {}", e))?;
+    }
+
+    let conn = rusqlite::Connection::open(&training.database_path)
+        .map_err(|e| format!("Failed to open training database: {}", e))?;
+    database::register_functions(&conn)?;
+
+    // The copy is private to training, so the completed-sale guard is lifted
+    ledger::sanctioned(&conn, || {
+        for table in TRAINING_CLEARED_TABLES {
+            conn.execute(&format!("DELETE FROM {}", table), [])
+                .map_err(|e| format!("Failed to clear {} in training database: {}", table, e))?;
+        }
+        // Customer balances went with their ledgers
+        conn.execute(
+            "UPDATE customers SET loyalty_points = 0, store_credit_minor = 0, account_balance_minor = 0",
+            [],
+        )
+        .map_err(|e| format!("Failed to reset customer balances in training database: {}", e))?;
+        inventory::record_opening_stock(&conn, "Stock when training started")?;
+        // Last, so the changes above are not left behind as audit entries
+        conn.execute("DELETE FROM audit_log", [])
+            .map_err(|e| format!("Failed to clear audit_log in training database: {}", e))?;
+        Ok(())
+    })?;
+
+    println!("🎓 Training database created at {:?}", training.database_path);
+    Ok(())
+}
+
+pub fn enter_training(db: &Database) -> Result<StoreProfile, String> {
+    if db.is_training() {
+        return Ok(db.active_profile());
+    }
+
+    let live = db.active_profile();
+    let training = config::training_profile(&live);
+
+    if !training.database_path.exists() {
+        clone_live_database(db, &training)?;
+    }
+
+    db.switch_to(training)
+}
+
+pub fn exit_training(db: &Database) -> Result<StoreProfile, String> {
+    match db.live_profile() {
+        Some(live) => db.switch_to(live),
+        None => Ok(db.active_profile()),
+    }
+}
+
+// Throw away all training activity and start again from a fresh copy of
+// the live catalog. Works from inside or outside training mode.
+pub fn reset_training(db: &Database) -> Result<StoreProfile, String> {
+    let live = db.live_profile().unwrap_or_else(|| db.active_profile());
+    let training = config::training_profile(&live);
+
+    exit_training(db)?;
+    remove_database_files(&training)?;
+    clone_live_database(db, &training)?;
+
+    println!("🎓 Training database reset");
+    db.switch_to(training)
+}
+
+// Fiscal (ZATCA) e-invoice submission must never receive training sales
+pub fn fiscal_submission_allowed(db: &Database) -> bool {
+    !db.is_training()
+}
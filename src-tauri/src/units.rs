@@ -0,0 +1,329 @@
+use crate::barcode;
+use crate::database;
+use crate::session;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+// Unit of products that were stocked before units existed
+pub const DEFAULT_UNIT: &str = "pcs";
+// Grams of a kilogram, millilitres of a litre
+pub const MAX_DECIMALS: i64 = 3;
+
+// Quantities are fixed-point integers in the product's base unit, the way
+// money is held in minor units: with 3 decimals, 1250 is 1.250 kg. Stock,
+// movements, lots and returns all count in these steps; prices and costs
+// stay per whole unit.
+#[derive(Debug, Serialize)]
+pub struct ProductUnit {
+    pub product_id: String,
+    pub unit: String,
+    pub quantity_decimals: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PackRequest {
+    pub product_id: String,
+    // e.g. "case"
+    pub name: String,
+    // Base quantity in one pack, fixed-point like stock
+    pub quantity: i64,
+    #[serde(default)]
+    pub barcode: Option<String>,
+    // Defaults to the base price times the pack quantity
+    #[serde(default)]
+    pub price_minor: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Pack {
+    pub id: String,
+    pub product_id: String,
+    pub name: String,
+    pub quantity: i64,
+    pub barcode: Option<String>,
+    pub price_override_minor: Option<i64>,
+    pub price_minor: i64,
+    pub created_at: String,
+}
+
+// How a sale or cart line is counted and priced
+#[derive(Debug)]
+pub struct SellingUnit {
+    pub unit_id: Option<String>,
+    pub name: String,
+    // Decimals of quantities entered in this unit
+    pub decimals: i64,
+    // Base steps in one step of this unit
+    pub base_quantity: i64,
+    pub price_minor: i64,
+}
+
+pub fn scale(decimals: i64) -> i64 {
+    10_i64.pow(decimals.clamp(0, MAX_DECIMALS) as u32)
+}
+
+// Price of a fixed-point quantity at a per-unit price, rounded half up
+pub fn line_total(price_minor: i64, quantity: i64, decimals: i64) -> i64 {
+    let scale = scale(decimals) as i128;
+    let value = price_minor as i128 * quantity as i128;
+    let rounded = if value >= 0 { (value + scale / 2) / scale } else { (value - scale / 2) / scale };
+    rounded as i64
+}
+
+// 1250 with 3 decimals -> "1.250"
+pub fn format_quantity(quantity: i64, decimals: i64) -> String {
+    if decimals <= 0 {
+        return quantity.to_string();
+    }
+    let scale = scale(decimals);
+    let sign = if quantity < 0 { "-" } else { "" };
+    format!(
+        "{}{}.{:0width$}",
+        sign,
+        quantity.abs() / scale,
+        quantity.abs() % scale,
+        width = decimals as usize
+    )
+}
+
+// line_total(price, quantity, decimals) for reports and views
+pub fn register_functions(conn: &Connection) -> Result<(), String> {
+    conn.create_scalar_function(
+        "line_total",
+        3,
+        FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| Ok(line_total(ctx.get(0)?, ctx.get(1)?, ctx.get(2)?)),
+    )
+    .map_err(|e| format!("Failed to register line_total(): {}", e))
+}
+
+pub fn product_unit(conn: &Connection, product_id: &str) -> Result<ProductUnit, String> {
+    conn.query_row(
+        "SELECT id, unit, quantity_decimals FROM products WHERE id = ?",
+        params![product_id],
+        |row| {
+            Ok(ProductUnit {
+                product_id: row.get(0)?,
+                unit: row.get(1)?,
+                quantity_decimals: row.get(2)?,
+            })
+        },
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Product {} not found", product_id))
+}
+
+// Changing the decimals changes what every stored quantity means, so it is
+// only allowed before the product has stock or sales
+pub fn set_product_unit(conn: &Connection, product_id: &str, unit: &str, quantity_decimals: i64) -> Result<ProductUnit, String> {
+    session::require_role(&["admin", "manager"])?;
+    let unit = unit.trim();
+    if unit.is_empty() {
+        return Err("Unit is required".to_string());
+    }
+    if !(0..=MAX_DECIMALS).contains(&quantity_decimals) {
+        return Err(format!("Quantities can have 0 to {} decimals", MAX_DECIMALS));
+    }
+
+    let current = product_unit(conn, product_id)?;
+    if current.quantity_decimals != quantity_decimals {
+        let (name, stock, used): (String, i64, bool) = conn
+            .query_row(
+                "SELECT name, stock,
+                        EXISTS(SELECT 1 FROM sale_items WHERE product_id = p.id)
+                        OR EXISTS(SELECT 1 FROM product_units WHERE product_id = p.id)
+                 FROM products p WHERE id = ?",
+                params![product_id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .map_err(|e| e.to_string())?;
+        if stock != 0 || used {
+            return Err(format!("{} already has stock, sales or packs; its decimals can no longer change", name));
+        }
+    }
+
+    conn.execute(
+        "UPDATE products SET unit = ?, quantity_decimals = ?, updated_at = ? WHERE id = ?",
+        params![unit, quantity_decimals, chrono::Utc::now().to_rfc3339(), product_id],
+    )
+    .map_err(|e| format!("Failed to update unit: {}", e))?;
+    product_unit(conn, product_id)
+}
+
+const PACK_COLUMNS: &str = "u.id, u.product_id, u.name, u.quantity, u.barcode, u.price_minor,
+                            COALESCE(u.price_minor, line_total(p.price_minor, u.quantity, p.quantity_decimals)),
+                            u.created_at";
+
+fn pack_from_row(row: &rusqlite::Row<'_>) -> rusqlite::Result<Pack> {
+    Ok(Pack {
+        id: row.get(0)?,
+        product_id: row.get(1)?,
+        name: row.get(2)?,
+        quantity: row.get(3)?,
+        barcode: row.get(4)?,
+        price_override_minor: row.get(5)?,
+        price_minor: row.get(6)?,
+        created_at: row.get(7)?,
+    })
+}
+
+pub fn add_pack(conn: &Connection, request: &PackRequest) -> Result<Pack, String> {
+    session::require_role(&["admin", "manager"])?;
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err("Pack name is required".to_string());
+    }
+    let product = product_unit(conn, &request.product_id)?;
+    if request.quantity <= 0 {
+        return Err("Pack quantity must be positive".to_string());
+    }
+    if request.price_minor.is_some_and(|p| p < 0) {
+        return Err("Prices cannot be negative".to_string());
+    }
+
+    let code = request.barcode.as_deref().map(str::trim).filter(|c| !c.is_empty());
+    if let Some(code) = code {
+        let numeric = code.chars().all(|c| c.is_ascii_digit());
+        if numeric && matches!(code.len(), 8 | 12 | 13 | 14) && !barcode::has_valid_check_digit(code) {
+            return Err(format!("Invalid check digit: {}", code));
+        }
+        let taken: bool = conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM products WHERE barcode = ?1 OR sku = ?1)
+                     OR EXISTS(SELECT 1 FROM product_barcodes WHERE barcode = ?1)
+                     OR EXISTS(SELECT 1 FROM product_units WHERE barcode = ?1)",
+                params![code],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if taken {
+            return Err(format!("Barcode {} already belongs to a product", code));
+        }
+    }
+
+    let id = database::generate_id("pack");
+    conn.execute(
+        "INSERT INTO product_units (id, product_id, name, quantity, barcode, price_minor, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)",
+        params![id, request.product_id, name, request.quantity, code, request.price_minor, chrono::Utc::now().to_rfc3339()],
+    )
+    .map_err(|e| format!("Failed to add pack {}: {}", name, e))?;
+
+    println!(
+        "📦 Pack {} of {} {} added",
+        name,
+        format_quantity(request.quantity, product.quantity_decimals),
+        product.unit
+    );
+    get_pack(conn, &id)
+}
+
+pub fn get_pack(conn: &Connection, pack_id: &str) -> Result<Pack, String> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM product_units u JOIN products p ON p.id = u.product_id WHERE u.id = ?",
+            PACK_COLUMNS
+        ),
+        params![pack_id],
+        pack_from_row,
+    )
+    .optional()
+    .map_err(|e| e.to_string())?
+    .ok_or_else(|| format!("Pack {} not found", pack_id))
+}
+
+pub fn find_pack_by_barcode(conn: &Connection, code: &str) -> Result<Option<Pack>, String> {
+    conn.query_row(
+        &format!(
+            "SELECT {} FROM product_units u JOIN products p ON p.id = u.product_id
+             WHERE u.barcode = ? AND p.is_active = 1",
+            PACK_COLUMNS
+        ),
+        params![code],
+        pack_from_row,
+    )
+    .optional()
+    .map_err(|e| e.to_string())
+}
+
+pub fn list_packs(conn: &Connection, product_id: &str) -> Result<Vec<Pack>, String> {
+    let mut stmt = conn
+        .prepare(&format!(
+            "SELECT {} FROM product_units u JOIN products p ON p.id = u.product_id
+             WHERE u.product_id = ? ORDER BY u.quantity",
+            PACK_COLUMNS
+        ))
+        .map_err(|e| e.to_string())?;
+    let packs = stmt
+        .query_map(params![product_id], pack_from_row)
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(packs)
+}
+
+// Sale lines keep the pack's name, so removing a pack leaves history intact
+pub fn remove_pack(conn: &Connection, pack_id: &str) -> Result<(), String> {
+    session::require_role(&["admin", "manager"])?;
+    let removed = conn
+        .execute("DELETE FROM product_units WHERE id = ?", params![pack_id])
+        .map_err(|e| format!("Failed to remove pack: {}", e))?;
+    if removed == 0 {
+        return Err(format!("Pack {} not found", pack_id));
+    }
+    Ok(())
+}
+
+// The unit a line is entered in: a pack, or the product's base unit
+pub fn selling_unit(conn: &Connection, product_id: &str, unit_id: Option<&str>) -> Result<SellingUnit, String> {
+    match unit_id {
+        Some(pack_id) => {
+            let pack = get_pack(conn, pack_id)?;
+            if pack.product_id != product_id {
+                return Err(format!("Pack {} belongs to another product", pack.name));
+            }
+            Ok(SellingUnit {
+                unit_id: Some(pack.id),
+                name: pack.name,
+                decimals: 0,
+                base_quantity: pack.quantity,
+                price_minor: pack.price_minor,
+            })
+        }
+        None => conn
+            .query_row(
+                "SELECT unit, quantity_decimals, price_minor FROM products WHERE id = ?",
+                params![product_id],
+                |row| {
+                    Ok(SellingUnit {
+                        unit_id: None,
+                        name: row.get(0)?,
+                        decimals: row.get(1)?,
+                        base_quantity: 1,
+                        price_minor: row.get(2)?,
+                    })
+                },
+            )
+            .optional()
+            .map_err(|e| e.to_string())?
+            .ok_or_else(|| format!("Product {} not found", product_id)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_total_rounds_half_away_from_zero() {
+        assert_eq!(line_total(250, 4, 0), 1_000);
+        // 1.5 kg at 1.99 is 2.985
+        assert_eq!(line_total(199, 1_500, 3), 299);
+        assert_eq!(line_total(199, -1_500, 3), -299);
+        assert_eq!(line_total(100, 1_234, 3), 123);
+        assert_eq!(line_total(100, 1_235, 3), 124);
+        assert_eq!(line_total(-100, 1_235, 3), -124);
+    }
+}
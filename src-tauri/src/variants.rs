@@ -0,0 +1,416 @@
+use crate::barcode;
+use crate::database;
+use crate::session;
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+// Keeps a size/colour run from turning into thousands of products by mistake
+const MAX_VARIANTS: usize = 200;
+
+#[derive(Debug, Deserialize)]
+pub struct VariantAttribute {
+    pub name: String,
+    pub values: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VariantMatrix {
+    pub parent_id: String,
+    // e.g. Size [S, M, L] x Colour [Red, Blue]
+    pub attributes: Vec<VariantAttribute>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VariantUpdate {
+    pub variant_id: String,
+    pub sku: String,
+    #[serde(default)]
+    pub barcode: Option<String>,
+    // None follows the parent's price
+    #[serde(default)]
+    pub price_override_minor: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VariantValue {
+    pub attribute: String,
+    pub value: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Variant {
+    pub id: String,
+    pub parent_id: String,
+    pub name: String,
+    pub sku: String,
+    pub barcode: Option<String>,
+    pub variant_label: String,
+    pub attributes: Vec<VariantValue>,
+    pub price_minor: i64,
+    pub price_override_minor: Option<i64>,
+    pub stock: i64,
+    pub is_active: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProductVariants {
+    pub parent_id: String,
+    pub parent_name: String,
+    pub parent_sku: String,
+    pub variants: Vec<Variant>,
+    pub total_stock: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct VariantSales {
+    pub product_id: String,
+    pub sku: String,
+    pub variant_label: String,
+    pub quantity_sold: i64,
+    pub quantity_returned: i64,
+    // What customers paid, less refunds
+    pub revenue_minor: i64,
+    pub stock: i64,
+}
+
+// Variants are products with a parent_id, so stock, sales, barcodes and
+// lots work on them unchanged. Renaming or repricing the parent carries
+// over to its variants, except prices a variant overrides.
+pub fn install(conn: &Connection) -> Result<(), String> {
+    conn.execute_batch(
+        r#"
+        DROP TRIGGER IF EXISTS products_variant_follow_parent;
+        CREATE TRIGGER products_variant_follow_parent
+        AFTER UPDATE OF name, price_minor ON products
+        WHEN NEW.parent_id IS NULL
+        BEGIN
+            UPDATE products
+            SET name = NEW.name || ' (' || variant_label || ')',
+                price_minor = COALESCE(price_override_minor, NEW.price_minor),
+                updated_at = strftime('%Y-%m-%dT%H:%M:%fZ', 'now')
+            WHERE parent_id = NEW.id;
+        END;
+        "#,
+    )
+    .map_err(|e| format!("Failed to install variant triggers: {}", e))
+}
+
+// Short upper-case code for a SKU suffix: "Navy Blue" -> "NAVYBLUE"
+fn sku_code(value: &str) -> String {
+    value
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+// Every combination of attribute values, in the order given
+fn combinations(attributes: &[VariantAttribute]) -> Vec<Vec<VariantValue>> {
+    let mut combos: Vec<Vec<VariantValue>> = vec![Vec::new()];
+    for attribute in attributes {
+        let mut next = Vec::new();
+        for combo in &combos {
+            for value in &attribute.values {
+                let mut extended: Vec<VariantValue> = combo
+                    .iter()
+                    .map(|v| VariantValue {
+                        attribute: v.attribute.clone(),
+                        value: v.value.clone(),
+                    })
+                    .collect();
+                extended.push(VariantValue {
+                    attribute: attribute.name.clone(),
+                    value: value.clone(),
+                });
+                next.push(extended);
+            }
+        }
+        combos = next;
+    }
+    combos
+}
+
+fn clean_attributes(attributes: &[VariantAttribute]) -> Result<Vec<VariantAttribute>, String> {
+    if attributes.is_empty() {
+        return Err("Give at least one attribute, e.g. Size or Colour".to_string());
+    }
+    let mut cleaned: Vec<VariantAttribute> = Vec::new();
+    for attribute in attributes {
+        let name = attribute.name.trim().to_string();
+        if name.is_empty() {
+            return Err("Attribute names cannot be empty".to_string());
+        }
+        if cleaned.iter().any(|a| a.name.eq_ignore_ascii_case(&name)) {
+            return Err(format!("Attribute {} is listed twice", name));
+        }
+        let mut values: Vec<String> = Vec::new();
+        for value in &attribute.values {
+            let value = value.trim().to_string();
+            if sku_code(&value).is_empty() {
+                return Err(format!("{} values need at least one letter or digit", name));
+            }
+            if !values.iter().any(|v| v.eq_ignore_ascii_case(&value)) {
+                values.push(value);
+            }
+        }
+        if values.is_empty() {
+            return Err(format!("Attribute {} has no values", name));
+        }
+        cleaned.push(VariantAttribute { name, values });
+    }
+    Ok(cleaned)
+}
+
+// Create the variants of a product from attribute lists. Combinations the
+// product already has are left alone, so a new colour can be added later.
+pub fn create_variant_matrix(conn: &Connection, matrix: &VariantMatrix) -> Result<ProductVariants, String> {
+    session::require_role(&["admin", "manager"])?;
+    let attributes = clean_attributes(&matrix.attributes)?;
+
+    let (parent_name, parent_sku, parent_of_parent, stock): (String, String, Option<String>, i64) = conn
+        .query_row(
+            "SELECT name, sku, parent_id, stock FROM products WHERE id = ?",
+            params![matrix.parent_id],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Product {} not found", matrix.parent_id))?;
+    if parent_of_parent.is_some() {
+        return Err(format!("{} is itself a variant", parent_name));
+    }
+    if stock != 0 {
+        return Err(format!(
+            "{} still has {} in stock; adjust it out or onto a variant first",
+            parent_name, stock
+        ));
+    }
+
+    let combos = combinations(&attributes);
+    if combos.len() > MAX_VARIANTS {
+        return Err(format!("{} combinations is more than the {} allowed", combos.len(), MAX_VARIANTS));
+    }
+
+    let now = chrono::Utc::now().to_rfc3339();
+    let mut created = 0;
+    for combo in combos {
+        let label = combo.iter().map(|v| v.value.as_str()).collect::<Vec<_>>().join(" / ");
+        let exists: bool = conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM products WHERE parent_id = ? AND variant_label = ?)",
+                params![matrix.parent_id, label],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if exists {
+            continue;
+        }
+
+        let id = database::generate_id("prod");
+        let sku = std::iter::once(parent_sku.clone())
+            .chain(combo.iter().map(|v| sku_code(&v.value)))
+            .collect::<Vec<_>>()
+            .join("-");
+        conn.execute(
+            "INSERT INTO products (id, name, sku, description, price_minor, cost_minor, stock, low_stock_threshold,
+                                   own_low_stock_threshold, category_id, supplier_id, is_active, created_at, tax_class_id, price_includes_tax,
+                                   track_lots, expiry_warning_days, unit, quantity_decimals, parent_id, variant_label)
+             SELECT ?1, name || ' (' || ?3 || ')', ?2, description, price_minor, cost_minor, 0, low_stock_threshold,
+                    own_low_stock_threshold, category_id, supplier_id, is_active, ?4, tax_class_id, price_includes_tax,
+                    track_lots, expiry_warning_days, unit, quantity_decimals, id, ?3
+             FROM products WHERE id = ?5",
+            params![id, sku, label, now, matrix.parent_id],
+        )
+        .map_err(|e| format!("Failed to create variant {}: {}", sku, e))?;
+
+        for (position, value) in combo.iter().enumerate() {
+            conn.execute(
+                "INSERT INTO product_variant_attributes (id, product_id, attribute, value, position)
+                 VALUES (?, ?, ?, ?, ?)",
+                params![database::generate_id("pva"), id, value.attribute, value.value, position as i64],
+            )
+            .map_err(|e| format!("Failed to record variant attribute: {}", e))?;
+        }
+        created += 1;
+    }
+
+    println!("👕 {} variant(s) created for {}", created, parent_name);
+    list_variants(conn, &matrix.parent_id)
+}
+
+pub fn update_variant(conn: &Connection, update: &VariantUpdate) -> Result<Variant, String> {
+    session::require_role(&["admin", "manager"])?;
+    let sku = update.sku.trim();
+    if sku.is_empty() {
+        return Err("SKU is required".to_string());
+    }
+    if update.price_override_minor.is_some_and(|p| p < 0) {
+        return Err("Prices cannot be negative".to_string());
+    }
+
+    let parent_id: String = conn
+        .query_row(
+            "SELECT parent_id FROM products WHERE id = ? AND parent_id IS NOT NULL",
+            params![update.variant_id],
+            |row| row.get(0),
+        )
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Variant {} not found", update.variant_id))?;
+
+    conn.execute(
+        "UPDATE products
+         SET sku = ?1,
+             price_override_minor = ?2,
+             price_minor = COALESCE(?2, (SELECT price_minor FROM products WHERE id = ?3)),
+             updated_at = ?4
+         WHERE id = ?5",
+        params![sku, update.price_override_minor, parent_id, chrono::Utc::now().to_rfc3339(), update.variant_id],
+    )
+    .map_err(|e| format!("Failed to update variant: {}", e))?;
+
+    if let Some(code) = update.barcode.as_deref().map(str::trim).filter(|c| !c.is_empty()) {
+        let known: bool = conn
+            .query_row(
+                "SELECT EXISTS(SELECT 1 FROM product_barcodes WHERE product_id = ? AND barcode = ?)",
+                params![update.variant_id, code],
+                |row| row.get(0),
+            )
+            .map_err(|e| e.to_string())?;
+        if !known {
+            barcode::add_product_barcode(conn, &update.variant_id, code)?;
+        }
+    }
+
+    list_variants(conn, &parent_id)?
+        .variants
+        .into_iter()
+        .find(|v| v.id == update.variant_id)
+        .ok_or_else(|| format!("Variant {} not found", update.variant_id))
+}
+
+fn variant_attributes(conn: &Connection, product_id: &str) -> Result<Vec<VariantValue>, String> {
+    let mut stmt = conn
+        .prepare("SELECT attribute, value FROM product_variant_attributes WHERE product_id = ? ORDER BY position")
+        .map_err(|e| e.to_string())?;
+    let values = stmt
+        .query_map(params![product_id], |row| {
+            Ok(VariantValue {
+                attribute: row.get(0)?,
+                value: row.get(1)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(values)
+}
+
+pub fn list_variants(conn: &Connection, parent_id: &str) -> Result<ProductVariants, String> {
+    let (parent_name, parent_sku): (String, String) = conn
+        .query_row("SELECT name, sku FROM products WHERE id = ?", params![parent_id], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .optional()
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| format!("Product {} not found", parent_id))?;
+
+    let rows: Vec<Variant> = {
+        let mut stmt = conn
+            .prepare(
+                "SELECT p.id, p.name, p.sku,
+                        COALESCE(p.barcode, (SELECT barcode FROM product_barcodes WHERE product_id = p.id
+                                             ORDER BY created_at LIMIT 1)),
+                        p.variant_label, p.price_minor, p.price_override_minor, p.stock, p.is_active
+                 FROM products p WHERE p.parent_id = ? ORDER BY p.rowid",
+            )
+            .map_err(|e| e.to_string())?;
+        let rows = stmt
+            .query_map(params![parent_id], |row| {
+                Ok(Variant {
+                    id: row.get(0)?,
+                    parent_id: parent_id.to_string(),
+                    name: row.get(1)?,
+                    sku: row.get(2)?,
+                    barcode: row.get(3)?,
+                    variant_label: row.get(4)?,
+                    attributes: Vec::new(),
+                    price_minor: row.get(5)?,
+                    price_override_minor: row.get(6)?,
+                    stock: row.get(7)?,
+                    is_active: row.get::<_, i64>(8)? != 0,
+                })
+            })
+            .map_err(|e| e.to_string())?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        rows
+    };
+
+    let mut variants = Vec::new();
+    for mut variant in rows {
+        variant.attributes = variant_attributes(conn, &variant.id)?;
+        variants.push(variant);
+    }
+
+    Ok(ProductVariants {
+        parent_id: parent_id.to_string(),
+        parent_name,
+        parent_sku,
+        total_stock: variants.iter().map(|v| v.stock).sum(),
+        variants,
+    })
+}
+
+// Whether a product is sold through its variants rather than on its own
+pub fn has_variants(conn: &Connection, product_id: &str) -> Result<bool, String> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM products WHERE parent_id = ? AND is_active = 1)",
+        params![product_id],
+        |row| row.get(0),
+    )
+    .map_err(|e| e.to_string())
+}
+
+// Units sold and returned per variant of a product, voided sales excluded
+pub fn variant_sales(
+    conn: &Connection,
+    parent_id: &str,
+    from: Option<&str>,
+    to: Option<&str>,
+) -> Result<Vec<VariantSales>, String> {
+    let mut stmt = conn
+        .prepare(
+            "WITH sold AS (
+                 SELECT si.id, si.product_id, si.quantity, COALESCE(si.paid_minor, si.total_minor - si.discount_minor) AS net_minor
+                 FROM sale_items si JOIN reportable_sales s ON s.id = si.sale_id
+                 WHERE (?2 IS NULL OR s.created_at >= ?2) AND (?3 IS NULL OR s.created_at < ?3)
+             )
+             SELECT p.id, p.sku, p.variant_label, p.stock,
+                    COALESCE((SELECT SUM(quantity) FROM sold WHERE product_id = p.id), 0),
+                    COALESCE((SELECT SUM(ri.quantity) FROM return_items ri
+                              WHERE ri.sale_item_id IN (SELECT id FROM sold WHERE product_id = p.id)), 0),
+                    COALESCE((SELECT SUM(net_minor) FROM sold WHERE product_id = p.id), 0)
+                      - COALESCE((SELECT SUM(ri.amount_minor) FROM return_items ri
+                                  WHERE ri.sale_item_id IN (SELECT id FROM sold WHERE product_id = p.id)), 0)
+             FROM products p WHERE p.parent_id = ?1
+             ORDER BY p.rowid",
+        )
+        .map_err(|e| e.to_string())?;
+    let rows = stmt
+        .query_map(params![parent_id, from, to], |row| {
+            Ok(VariantSales {
+                product_id: row.get(0)?,
+                sku: row.get(1)?,
+                variant_label: row.get(2)?,
+                stock: row.get(3)?,
+                quantity_sold: row.get(4)?,
+                quantity_returned: row.get(5)?,
+                revenue_minor: row.get(6)?,
+            })
+        })
+        .map_err(|e| e.to_string())?
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| e.to_string())?;
+    Ok(rows)
+}